@@ -0,0 +1,252 @@
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::settings::Cli;
+use crate::source::Source;
+use crate::utils::{self, MediaInfo, NowPlayingResult};
+
+// cmus, read with the same "status" command as cmus-remote -Q
+pub struct Cmus {
+    socket: Option<PathBuf>,
+}
+
+impl Cmus {
+    pub fn new(settings: &Cli) -> Cmus {
+        Cmus {
+            socket: settings.cmus_socket.as_deref().map(utils::expand_home),
+        }
+    }
+
+    // Same locations as cmus uses when --listen is not set
+    fn socket(&self) -> PathBuf {
+        if let Some(socket) = &self.socket {
+            return socket.clone();
+        }
+        if let Some(socket) = env::var_os("CMUS_SOCKET") {
+            return PathBuf::from(socket);
+        }
+        if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR") {
+            let socket = PathBuf::from(runtime_dir).join("cmus-socket");
+            if socket.exists() {
+                return socket;
+            }
+        }
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(config_dir) => PathBuf::from(config_dir).join("cmus/socket"),
+            None => utils::expand_home("~/.config/cmus/socket"),
+        }
+    }
+
+    fn query(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut stream = UnixStream::connect(self.socket())?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        stream.write_all(b"status\n")?;
+
+        // The response ends with an empty line
+        let mut lines = Vec::new();
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.is_empty() {
+                break;
+            }
+            lines.push(line);
+        }
+        Ok(lines)
+    }
+}
+
+impl Source for Cmus {
+    fn name(&self) -> String {
+        String::from("cmus")
+    }
+
+    fn get_media_info(&mut self) -> NowPlayingResult {
+        match self.query() {
+            Ok(lines) => media_info(&lines),
+            Err(_) => Err("cmus is not running.".into()),
+        }
+    }
+}
+
+fn media_info(lines: &[String]) -> NowPlayingResult {
+    // "key value" lines, tags are "tag key value"
+    let value = |key: &str| -> Option<String> {
+        lines
+            .iter()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(' '))
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string())
+    };
+    let tag = |name: &str| value(&format!("tag {}", name));
+
+    let status = value("status").unwrap_or_default();
+    if status != "playing" && status != "paused" {
+        return Err("cmus is not playing anything.".into());
+    }
+
+    let file = value("file").unwrap_or_default();
+    let is_stream = file.starts_with("http://") || file.starts_with("https://");
+
+    // Streams have the current title in "stream", files without tags only the path
+    let title = match tag("title").or(value("stream")) {
+        Some(title) => title,
+        None => utils::file_stem(&file),
+    };
+    let artist = tag("artist").unwrap_or_else(|| String::from("Unknown Artist"));
+    let album = tag("album").unwrap_or_else(|| String::from("Unknown Album"));
+    let album_artist = tag("albumartist").unwrap_or(artist.clone());
+
+    let seconds = |key: &str| -> Option<u64> { value(key)?.parse().ok() };
+    let position = seconds("position");
+
+    Ok(MediaInfo {
+        title,
+        artist,
+        album_artist,
+        album,
+        is_playing: status == "playing",
+        duration: seconds("duration").unwrap_or(0),
+        position: position.unwrap_or(0),
+        is_track_position: position.is_some(),
+        url: match is_stream {
+            true => file.clone(),
+            false => String::new(),
+        },
+        path: match is_stream {
+            true => String::new(),
+            false => file,
+        },
+        musicbrainz_album_id: tag("musicbrainz_albumid").unwrap_or_default(),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    fn lines(output: &str) -> Vec<String> {
+        output.lines().map(|line| line.to_string()).collect()
+    }
+
+    // Output of cmus-remote -Q
+    const PLAYING: &str = "status playing
+file /music/Radiohead/OK Computer/02 Paranoid Android.flac
+duration 383
+position 82
+tag artist Radiohead
+tag album OK Computer
+tag title Paranoid Android
+tag date 1997
+tag genre Alternative Rock
+tag tracknumber 2
+tag albumartist Radiohead
+tag musicbrainz_albumid 0b6b4ba0-d36f-47bd-b4ea-6a5b91842d29
+set aaa_mode all
+set continue true
+set play_library true
+set play_sorted false
+set replaygain disabled
+set replaygain_limit true
+set replaygain_preamp 0.000000
+set repeat false
+set repeat_current false
+set shuffle off
+set softvol false
+set vol_left 100
+set vol_right 100";
+
+    #[test]
+    fn tagged_files() {
+        let media_info = media_info(&lines(PLAYING)).unwrap();
+        assert_eq!(media_info.title, "Paranoid Android");
+        assert_eq!(media_info.artist, "Radiohead");
+        assert_eq!(media_info.album_artist, "Radiohead");
+        assert_eq!(media_info.album, "OK Computer");
+        assert!(media_info.is_playing);
+        assert_eq!(media_info.duration, 383);
+        assert_eq!(media_info.position, 82);
+        assert!(media_info.is_track_position);
+        assert_eq!(
+            media_info.path,
+            "/music/Radiohead/OK Computer/02 Paranoid Android.flac"
+        );
+        assert_eq!(media_info.url, "");
+        assert_eq!(
+            media_info.musicbrainz_album_id,
+            "0b6b4ba0-d36f-47bd-b4ea-6a5b91842d29"
+        );
+    }
+
+    #[test]
+    fn streams_and_untagged_files() {
+        let stream = media_info(&lines(
+            "status playing
+file https://radio.example.org/stream.mp3
+duration -1
+position 61
+stream Example Artist - Example Song
+set shuffle off",
+        ))
+        .unwrap();
+        assert_eq!(stream.title, "Example Artist - Example Song");
+        assert_eq!(stream.artist, "Unknown Artist");
+        assert_eq!(stream.duration, 0);
+        assert_eq!(stream.url, "https://radio.example.org/stream.mp3");
+        assert_eq!(stream.path, "");
+
+        let file = media_info(&lines(
+            "status paused
+file /music/Some Track.ogg
+duration 200
+position 10
+tag title ",
+        ))
+        .unwrap();
+        assert_eq!(file.title, "Some Track");
+        assert_eq!(file.album, "Unknown Album");
+        assert!(!file.is_playing);
+    }
+
+    #[test]
+    fn stopped() {
+        let stopped = media_info(&lines("status stopped\nset shuffle off"));
+        assert_eq!(
+            stopped.err().unwrap().to_string(),
+            "cmus is not playing anything."
+        );
+    }
+
+    #[test]
+    fn status_command() {
+        let socket = env::temp_dir().join(format!("cmus-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0; 7];
+            stream.read_exact(&mut command).unwrap();
+            assert_eq!(&command, b"status\n");
+            stream
+                .write_all(format!("{}\n\n", PLAYING).as_bytes())
+                .unwrap();
+        });
+
+        let mut cmus = Cmus {
+            socket: Some(socket.clone()),
+        };
+        let media_info = cmus.get_media_info();
+        let _ = std::fs::remove_file(&socket);
+        assert_eq!(media_info.unwrap().title, "Paranoid Android");
+        assert_eq!(
+            cmus.get_media_info().err().unwrap().to_string(),
+            "cmus is not running."
+        );
+    }
+}
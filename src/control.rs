@@ -0,0 +1,495 @@
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::events::Event;
+
+// State of the running instance reported by the "status" command
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Status {
+    pub player: String,
+    pub player_id: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub is_playing: bool,
+    // Track length and position in seconds, position was read at position_updated (unix time)
+    pub duration: u64,
+    pub position: u64,
+    pub position_updated: u64,
+    pub cover_url: String,
+    pub cover_source: String,
+    // Local file of the track, empty for streams or if the player does not know it
+    pub path: String,
+    pub discord_connected: bool,
+    pub presence_paused: bool,
+    // Schedule rule that is active now, empty outside of the schedule
+    pub schedule: String,
+    pub preferred_player: String,
+}
+
+pub type SharedStatus = Arc<Mutex<Status>>;
+
+// Single line of JSON sent by the client, e.g. {"command":"status"} or
+// {"command":"prefer","player":"mpv"}
+#[derive(Serialize, Deserialize)]
+struct Request {
+    command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    player: Option<String>,
+}
+
+// Single line of JSON sent back, e.g. {"ok":true,"status":{...}}
+#[derive(Serialize, Deserialize, Default)]
+struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<Status>,
+}
+
+pub fn new_status() -> SharedStatus {
+    Arc::new(Mutex::new(Status::default()))
+}
+
+// Modify shared status from the main loop
+pub fn update<F: FnOnce(&mut Status)>(status: &SharedStatus, f: F) {
+    if let Ok(mut status) = status.lock() {
+        f(&mut status);
+    }
+}
+
+// Forget the current track, the state of the presence itself is kept
+pub fn clear_track(status: &SharedStatus) {
+    update(status, |status| {
+        *status = Status {
+            player: status.player.clone(),
+            discord_connected: status.discord_connected,
+            presence_paused: status.presence_paused,
+            schedule: status.schedule.clone(),
+            preferred_player: status.preferred_player.clone(),
+            ..Default::default()
+        }
+    });
+}
+
+// Forget the current track and its player, e.g. after the player was closed
+pub fn clear_player(status: &SharedStatus) {
+    clear_track(status);
+    update(status, |status| status.player.clear());
+}
+
+// Location of the control socket, in a directory only the user can access
+pub fn get_socket_path() -> PathBuf {
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(runtime_dir) if !runtime_dir.is_empty() => {
+            PathBuf::from(runtime_dir).join("music-discord-rpc.sock")
+        }
+        // The temp dir is shared with other users, so the socket gets its own directory
+        _ => env::temp_dir()
+            .join(format!("music-discord-rpc-{}", current_uid()))
+            .join("control.sock"),
+    }
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and always succeeds
+    unsafe { libc::getuid() }
+}
+
+// Create the directory of the socket if it is missing and make sure that no
+// other user owns it or the socket, they could pretend to be the running instance
+fn check_socket_path(socket_path: &Path) -> Result<(), String> {
+    let uid = current_uid();
+    if let Some(dir) = socket_path.parent() {
+        if !dir.exists() {
+            if let Err(err) = fs::DirBuilder::new().mode(0o700).create(dir) {
+                return Err(format!("Could not create {}: {}", dir.display(), err));
+            }
+        }
+        match fs::symlink_metadata(dir) {
+            Ok(metadata) if metadata.uid() == uid && metadata.mode() & 0o077 == 0 => {}
+            Ok(_) => {
+                return Err(format!(
+                    "{} must be a directory that only you can access.",
+                    dir.display()
+                ))
+            }
+            Err(err) => return Err(format!("Could not read {}: {}", dir.display(), err)),
+        }
+    }
+    match fs::symlink_metadata(socket_path) {
+        Ok(metadata) if metadata.uid() != uid => Err(format!(
+            "{} belongs to another user.",
+            socket_path.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Accept commands from the control socket and forward them to the main loop.
+// Returns false if the socket could not be created.
+pub fn listen(sender: Sender<Event>, status: SharedStatus) -> bool {
+    listen_on(&get_socket_path(), sender, status)
+}
+
+fn listen_on(socket_path: &Path, sender: Sender<Event>, status: SharedStatus) -> bool {
+    if let Err(err) = check_socket_path(socket_path) {
+        error!("Could not create control socket: {}", err);
+        return false;
+    }
+
+    if socket_path.exists() {
+        // Do not steal the socket from another running instance
+        if UnixStream::connect(socket_path).is_ok() {
+            warn!(
+                "Another instance is already listening on {}, control commands will go to it.",
+                socket_path.display()
+            );
+            return false;
+        }
+        let _ = fs::remove_file(socket_path);
+    }
+
+    let listener = match UnixListener::bind(socket_path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Could not create control socket: {}", err);
+            return false;
+        }
+    };
+    // Only the user can control the presence
+    let _ = fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            handle_client(stream, &sender, &status);
+        }
+    });
+    true
+}
+
+// Remove the socket file on exit
+pub fn remove_socket() {
+    let _ = fs::remove_file(get_socket_path());
+}
+
+fn handle_client(stream: UnixStream, sender: &Sender<Event>, status: &SharedStatus) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() {
+        return;
+    }
+
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => handle_request(&request, sender, status),
+        Err(err) => Response {
+            error: Some(format!("invalid request: {}", err)),
+            ..Default::default()
+        },
+    };
+
+    let mut writer = &stream;
+    if let Ok(response) = serde_json::to_string(&response) {
+        let _ = writeln!(writer, "{}", response);
+    }
+}
+
+fn handle_request(request: &Request, sender: &Sender<Event>, status: &SharedStatus) -> Response {
+    let paused = match status.lock() {
+        Ok(status) => status.presence_paused,
+        Err(_) => false,
+    };
+
+    let event = match request.command.as_str() {
+        "status" => None,
+        "pause-presence" => Some(Event::Pause),
+        "resume-presence" => Some(Event::Resume),
+        "toggle" if paused => Some(Event::Resume),
+        "toggle" => Some(Event::Pause),
+        "refresh" => Some(Event::Refresh),
+        "reload" => Some(Event::Reload),
+        // Without a player any player can be shown again
+        "prefer" => Some(Event::PreferPlayer(request.player.clone())),
+        command => {
+            return Response {
+                error: Some(format!("unknown command \"{}\"", command)),
+                ..Default::default()
+            }
+        }
+    };
+
+    if let Some(event) = event {
+        if !send_event(event, sender, status) {
+            return Response {
+                error: Some(String::from("main loop is not running")),
+                ..Default::default()
+            };
+        }
+    }
+
+    Response {
+        ok: true,
+        status: status.lock().ok().map(|status| status.clone()),
+        ..Default::default()
+    }
+}
+
+// Forward event to the main loop. Pause state and preferred player are
+// updated right away, so the reply to the caller already includes them.
+pub fn send_event(event: Event, sender: &Sender<Event>, status: &SharedStatus) -> bool {
+    match event {
+        Event::Pause => update(status, |status| status.presence_paused = true),
+        Event::Resume => update(status, |status| status.presence_paused = false),
+        Event::PreferPlayer(ref player) => update(status, |status| {
+            status.preferred_player = player.clone().unwrap_or_default()
+        }),
+        _ => {}
+    }
+    sender.send(event).is_ok()
+}
+
+// Send a request to the running instance and read its response
+fn send_request(socket_path: &Path, request: &Request) -> Result<Response, String> {
+    check_socket_path(socket_path)?;
+    let mut stream = UnixStream::connect(socket_path).map_err(|_| {
+        format!(
+            "Could not connect to {}. Is music-discord-rpc running?",
+            socket_path.display()
+        )
+    })?;
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+
+    let request = serde_json::to_string(request).unwrap_or_default();
+    if writeln!(stream, "{}", request).is_err() {
+        return Err(String::from("Could not send command to music-discord-rpc."));
+    }
+
+    let mut line = String::new();
+    if BufReader::new(&stream).read_line(&mut line).is_err() {
+        return Err(String::from("No response from music-discord-rpc."));
+    }
+    serde_json::from_str::<Response>(&line)
+        .map_err(|_| String::from("Invalid response from music-discord-rpc."))
+}
+
+// Send command to the running instance, print the result and exit
+pub fn send_command(command: &str, player: Option<String>) -> ! {
+    let request = Request {
+        command: command.to_string(),
+        player,
+    };
+    let response = match send_request(&get_socket_path(), &request) {
+        Ok(response) => response,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    if !response.ok {
+        println!("Error: {}", response.error.unwrap_or_default());
+        process::exit(1);
+    }
+
+    let status = response.status.unwrap_or_default();
+    match command {
+        "status" => print_status(&status),
+        "pause-presence" | "resume-presence" | "toggle" => match status.presence_paused {
+            true => println!("Presence paused."),
+            false => println!("Presence resumed."),
+        },
+        "refresh" => println!("Activity refresh requested."),
+        "reload" => println!("Configuration reload requested."),
+        "prefer" => match status.preferred_player.is_empty() {
+            true => println!("Any player can be shown."),
+            false => println!("Preferred player: {}", status.preferred_player),
+        },
+        _ => {}
+    }
+    process::exit(0);
+}
+
+fn print_status(status: &Status) {
+    let or_none = |value: &str| match value.is_empty() {
+        true => String::from("-"),
+        false => value.to_string(),
+    };
+
+    println!("Player:   {}", or_none(&status.player));
+    if !status.title.is_empty() {
+        println!("Track:    {} - {}", status.artist, status.title);
+        println!("Album:    {}", or_none(&status.album));
+        println!(
+            "Playback: {}",
+            if status.is_playing {
+                "playing"
+            } else {
+                "paused"
+            }
+        );
+        match status.cover_url.as_str() {
+            "" | "missing-cover" => println!("Cover:    -"),
+            url => println!("Cover:    {} ({})", url, status.cover_source),
+        }
+        if !status.path.is_empty() {
+            println!("File:     {}", status.path);
+        }
+    }
+    println!(
+        "Discord:  {}",
+        if status.discord_connected {
+            "connected"
+        } else {
+            "disconnected"
+        }
+    );
+    println!(
+        "Presence: {}",
+        if status.presence_paused {
+            "paused"
+        } else {
+            "visible"
+        }
+    );
+    if !status.schedule.is_empty() {
+        println!("Schedule: {}", status.schedule);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn request(command: &str, player: Option<&str>) -> Request {
+        Request {
+            command: command.to_string(),
+            player: player.map(String::from),
+        }
+    }
+
+    #[test]
+    fn commands() {
+        let dir = env::temp_dir().join(format!("music-discord-rpc-test-{}", process::id()));
+        let socket_path = dir.join("control.sock");
+        let (sender, receiver) = mpsc::channel();
+        let status = new_status();
+        update(&status, |status| {
+            status.title = String::from("Paranoid Android")
+        });
+        assert!(listen_on(&socket_path, sender, status.clone()));
+        assert_eq!(
+            fs::metadata(&dir).unwrap().mode() & 0o777,
+            0o700,
+            "only the user can reach the socket"
+        );
+
+        let send = |command: &str, player: Option<&str>| {
+            send_request(&socket_path, &request(command, player)).unwrap()
+        };
+
+        let response = send("status", None);
+        assert!(response.ok);
+        assert_eq!(response.status.unwrap().title, "Paranoid Android");
+
+        let response = send("pause-presence", None);
+        assert!(response.status.unwrap().presence_paused);
+        assert!(matches!(receiver.try_recv(), Ok(Event::Pause)));
+
+        let response = send("toggle", None);
+        assert!(!response.status.unwrap().presence_paused);
+        assert!(matches!(receiver.try_recv(), Ok(Event::Resume)));
+
+        let response = send("prefer", Some("mpv"));
+        assert_eq!(response.status.unwrap().preferred_player, "mpv");
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Event::PreferPlayer(Some(player))) if player == "mpv"
+        ));
+        let response = send("prefer", None);
+        assert_eq!(response.status.unwrap().preferred_player, "");
+        assert!(matches!(receiver.try_recv(), Ok(Event::PreferPlayer(None))));
+
+        let response = send("resume-presence", None);
+        assert!(response.ok);
+        assert!(matches!(receiver.try_recv(), Ok(Event::Resume)));
+
+        let response = send("shutdown", None);
+        assert!(!response.ok);
+        assert_eq!(response.error.unwrap(), "unknown command \"shutdown\"");
+        assert!(response.status.is_none());
+        assert!(receiver.try_recv().is_err());
+
+        // A second instance does not take over the socket
+        let (sender, _) = mpsc::channel();
+        assert!(!listen_on(&socket_path, sender, new_status()));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn clear_keeps_the_presence_state() {
+        let status = new_status();
+        update(&status, |status| {
+            *status = Status {
+                player: String::from("mpv"),
+                title: String::from("Paranoid Android"),
+                discord_connected: true,
+                presence_paused: true,
+                preferred_player: String::from("mpv"),
+                ..Default::default()
+            }
+        });
+        clear_track(&status);
+        let cleared = status.lock().unwrap().clone();
+        assert_eq!(cleared.title, "");
+        assert_eq!(cleared.player, "mpv");
+        assert!(cleared.discord_connected && cleared.presence_paused);
+        assert_eq!(cleared.preferred_player, "mpv");
+
+        clear_player(&status);
+        assert_eq!(status.lock().unwrap().player, "");
+        assert!(status.lock().unwrap().presence_paused);
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let (sender, _) = mpsc::channel();
+        let status = new_status();
+        let (client, server) = UnixStream::pair().unwrap();
+        let mut writer = &client;
+        writeln!(writer, "status").unwrap();
+        handle_client(server, &sender, &status);
+
+        let mut line = String::new();
+        BufReader::new(&client).read_line(&mut line).unwrap();
+        let response: Response = serde_json::from_str(&line).unwrap();
+        assert!(!response.ok);
+        assert!(response.error.unwrap().starts_with("invalid request: "));
+    }
+
+    #[test]
+    fn main_loop_stopped() {
+        let (sender, receiver) = mpsc::channel();
+        drop(receiver);
+        let response = handle_request(&request("refresh", None), &sender, &new_status());
+        assert_eq!(response.error.unwrap(), "main loop is not running");
+    }
+}
@@ -0,0 +1,180 @@
+use dbus::arg::{PropMap, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::{
+    PropertiesPropertiesChanged, RequestNameReply,
+};
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender as _};
+use dbus::message::{MatchRule, SignalArgs};
+use dbus::Path;
+use dbus_crossroads::{Crossroads, MethodErr};
+use log::{error, warn};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use crate::control::{self, SharedStatus, Status};
+use crate::events::Event;
+
+const BUS_NAME: &str = "io.github.music_discord_rpc";
+const OBJECT_PATH: &str = "/io/github/music_discord_rpc";
+const INTERFACE: &str = "io.github.music_discord_rpc";
+
+// Data of the exported object, shared by all method and property handlers
+struct Service {
+    sender: Sender<Event>,
+    status: SharedStatus,
+}
+
+impl Service {
+    fn send(&self, event: Event) -> Result<(), MethodErr> {
+        match control::send_event(event, &self.sender, &self.status) {
+            true => Ok(()),
+            false => Err(MethodErr::failed("main loop is not running")),
+        }
+    }
+
+    fn status(&self) -> Status {
+        match self.status.lock() {
+            Ok(status) => status.clone(),
+            Err(_) => Status::default(),
+        }
+    }
+}
+
+// Own a name on the session bus, so the running instance can be controlled
+// and observed the same way as MPRIS players, e.g. with busctl or gdbus
+pub fn start(sender: Sender<Event>, status: SharedStatus) {
+    thread::spawn(move || {
+        if let Err(err) = serve(sender, status) {
+            error!("Could not start D-Bus service: {}", err);
+        }
+    });
+}
+
+fn serve(sender: Sender<Event>, status: SharedStatus) -> Result<(), dbus::Error> {
+    let connection = Connection::new_session()?;
+    if connection.request_name(BUS_NAME, false, true, true)? != RequestNameReply::PrimaryOwner {
+        warn!(
+            "D-Bus name {} is already owned by another instance.",
+            BUS_NAME
+        );
+        return Ok(());
+    }
+
+    let mut crossroads = Crossroads::new();
+    let interface = crossroads.register(INTERFACE, |b| {
+        b.method("Pause", (), (), |_, service: &mut Service, ()| {
+            service.send(Event::Pause)
+        });
+        b.method("Resume", (), (), |_, service: &mut Service, ()| {
+            service.send(Event::Resume)
+        });
+        b.method("Refresh", (), (), |_, service: &mut Service, ()| {
+            service.send(Event::Refresh)
+        });
+        b.method("ClearCoverCache", (), (), |_, service: &mut Service, ()| {
+            service.send(Event::ClearCoverCache)
+        });
+
+        b.property("CurrentTrack")
+            .get(|_, service: &mut Service| Ok(current_track(&service.status())));
+        b.property("CoverUrl")
+            .get(|_, service: &mut Service| Ok(cover_url(&service.status())));
+        b.property("DiscordConnected")
+            .get(|_, service: &mut Service| Ok(service.status().discord_connected));
+        b.property("ActivePlayer")
+            .get(|_, service: &mut Service| Ok(service.status().player));
+        b.property("PresencePaused")
+            .get(|_, service: &mut Service| Ok(service.status().presence_paused));
+    });
+    crossroads.insert(
+        OBJECT_PATH,
+        &[interface],
+        Service {
+            sender,
+            status: status.clone(),
+        },
+    );
+
+    connection.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |message, connection| {
+            let _ = crossroads.handle_message(message, connection);
+            true
+        }),
+    );
+
+    // Properties are changed by the main loop, so check them periodically
+    // and notify subscribers about the ones that changed
+    let mut last_status = Status::default();
+    loop {
+        connection.process(Duration::from_millis(500))?;
+
+        let current_status = match status.lock() {
+            Ok(status) => status.clone(),
+            Err(_) => continue,
+        };
+        if current_status == last_status {
+            continue;
+        }
+
+        let changed_properties = changed_properties(&last_status, &current_status);
+        last_status = current_status;
+        if changed_properties.is_empty() {
+            continue;
+        }
+
+        let signal = PropertiesPropertiesChanged {
+            interface_name: String::from(INTERFACE),
+            changed_properties,
+            invalidated_properties: Vec::new(),
+        };
+        let _ = connection.send(signal.to_emit_message(&Path::from(OBJECT_PATH)));
+    }
+}
+
+fn current_track(status: &Status) -> String {
+    match status.title.is_empty() {
+        true => String::new(),
+        false => format!("{} - {}", status.artist, status.title),
+    }
+}
+
+fn cover_url(status: &Status) -> String {
+    match status.cover_url.as_str() {
+        "missing-cover" => String::new(),
+        url => url.to_string(),
+    }
+}
+
+fn changed_properties(old: &Status, new: &Status) -> PropMap {
+    let mut properties = PropMap::new();
+    if current_track(old) != current_track(new) {
+        properties.insert(
+            String::from("CurrentTrack"),
+            Variant(Box::new(current_track(new))),
+        );
+    }
+    if cover_url(old) != cover_url(new) {
+        properties.insert(String::from("CoverUrl"), Variant(Box::new(cover_url(new))));
+    }
+    if old.discord_connected != new.discord_connected {
+        properties.insert(
+            String::from("DiscordConnected"),
+            Variant(Box::new(new.discord_connected)),
+        );
+    }
+    if old.player != new.player {
+        properties.insert(
+            String::from("ActivePlayer"),
+            Variant(Box::new(new.player.clone())),
+        );
+    }
+    if old.presence_paused != new.presence_paused {
+        properties.insert(
+            String::from("PresencePaused"),
+            Variant(Box::new(new.presence_paused)),
+        );
+    }
+    properties
+}
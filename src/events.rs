@@ -0,0 +1,193 @@
+use log::{error, warn};
+use notify::{RecursiveMode, Watcher};
+use signal_hook::consts::signal::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Events sent to the main loop from other threads
+#[derive(Debug)]
+pub enum Event {
+    Shutdown,
+    Reload,
+    Pause,
+    Resume,
+    Refresh,
+    ClearCoverCache,
+    PreferPlayer(Option<String>),
+    // Player reported a change, check it now instead of after the interval
+    PlayerChanged,
+}
+
+// Replaces plain sleep in the main loop, so it can be woken up early by an event
+pub struct Events {
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+    pub shutdown: bool,
+    pub reload: bool,
+    // Activity hidden on user request
+    pub paused: bool,
+    // Activity should be sent again even if nothing changed
+    pub refresh: bool,
+    // Album covers should be fetched again
+    pub clear_cover_cache: bool,
+    // Player chosen in the tray menu, used before any other player
+    pub preferred_player: Option<String>,
+    // Refresh at this time even without any event, e.g. when the schedule changes
+    wake_at: Option<Instant>,
+}
+
+impl Events {
+    pub fn new() -> Events {
+        let (sender, receiver) = mpsc::channel();
+        Events {
+            sender,
+            receiver,
+            shutdown: false,
+            reload: false,
+            paused: false,
+            refresh: false,
+            clear_cover_cache: false,
+            preferred_player: None,
+            wake_at: None,
+        }
+    }
+
+    pub fn sender(&self) -> Sender<Event> {
+        self.sender.clone()
+    }
+
+    // True if the main loop should stop what it is doing and handle pending events
+    pub fn interrupted(&self) -> bool {
+        self.shutdown || self.reload || self.refresh
+    }
+
+    // Refresh after the given time, even if it is shorter than the interval
+    pub fn wake_after(&mut self, duration: Option<Duration>) {
+        self.wake_at = duration.map(|duration| Instant::now() + duration);
+    }
+
+    // Sleep for given number of seconds or until any event arrives
+    pub fn sleep(&mut self, secs: u64) {
+        if self.interrupted() {
+            return;
+        }
+
+        let mut timeout = Duration::from_secs(secs);
+        if let Some(wake_at) = self.wake_at {
+            timeout = timeout.min(wake_at.saturating_duration_since(Instant::now()));
+        }
+        if let Ok(event) = self.receiver.recv_timeout(timeout) {
+            self.handle(event);
+        }
+
+        // Handle everything else that arrived in the meantime
+        while let Ok(event) = self.receiver.try_recv() {
+            self.handle(event);
+        }
+
+        if self
+            .wake_at
+            .is_some_and(|wake_at| wake_at <= Instant::now())
+        {
+            self.wake_at = None;
+            self.refresh = true;
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Shutdown => self.shutdown = true,
+            Event::Reload => self.reload = true,
+            Event::Pause => {
+                self.paused = true;
+                self.refresh = true;
+            }
+            Event::Resume => {
+                self.paused = false;
+                self.refresh = true;
+            }
+            Event::Refresh => self.refresh = true,
+            Event::ClearCoverCache => {
+                self.clear_cover_cache = true;
+                self.refresh = true;
+            }
+            Event::PreferPlayer(player) => {
+                self.preferred_player = player;
+                self.refresh = true;
+            }
+            Event::PlayerChanged => {}
+        }
+    }
+}
+
+// Forward SIGINT and SIGTERM as shutdown and SIGHUP as config reload
+pub fn listen_signals(sender: Sender<Event>) {
+    let mut signals = match Signals::new([SIGINT, SIGTERM, SIGHUP]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            error!("Could not register signal handlers: {}", err);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            let event = match signal {
+                SIGHUP => Event::Reload,
+                _ => Event::Shutdown,
+            };
+
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// Send config reload event every time the config file is modified
+pub fn watch_config(sender: Sender<Event>, config_file: PathBuf) {
+    // Watch the whole directory, because most editors replace the file instead of writing to it
+    let config_dir = match config_file.parent() {
+        Some(dir) => dir.to_path_buf(),
+        None => return,
+    };
+
+    let (watcher_sender, watcher_receiver) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(watcher_sender) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            warn!("Could not watch config file for changes: {}", err);
+            return;
+        }
+    };
+    if let Err(err) = watcher.watch(&config_dir, RecursiveMode::NonRecursive) {
+        warn!("Could not watch config file for changes: {}", err);
+        return;
+    }
+
+    thread::spawn(move || {
+        // Keep the watcher alive as long as this thread
+        let _watcher = watcher;
+
+        for result in &watcher_receiver {
+            let event: notify::Event = match result {
+                Ok(event) => event,
+                Err(_) => continue,
+            };
+            if event.kind.is_access() || !event.paths.iter().any(|path| path == &config_file) {
+                continue;
+            }
+
+            // Editors often write the file in several steps, wait for them to finish
+            thread::sleep(Duration::from_millis(300));
+            while watcher_receiver.try_recv().is_ok() {}
+
+            if sender.send(Event::Reload).is_err() {
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,161 @@
+use log::{debug, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::control::SharedStatus;
+use crate::now_playing::{self, NowPlaying};
+use crate::template;
+use crate::utils;
+
+// Template used for files without one, except .json files that get the whole state
+const DEFAULT_TEMPLATE: &str = "{artist} - {title}";
+
+// File written every time the track changes
+struct OutputFile {
+    path: PathBuf,
+    // None means the whole state as JSON
+    template: Option<String>,
+}
+
+impl OutputFile {
+    // "path=template", the template is optional
+    fn parse(value: &str) -> OutputFile {
+        let (path, template) = match value.split_once('=') {
+            Some((path, template)) => (path, Some(template.to_string())),
+            None => (value, None),
+        };
+        let path = utils::expand_home(path.trim());
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension == "json");
+        let template = match template {
+            Some(template) => Some(template),
+            None if is_json => None,
+            None => Some(DEFAULT_TEMPLATE.to_string()),
+        };
+        OutputFile { path, template }
+    }
+
+    fn render(&self, now_playing: &NowPlaying) -> String {
+        match &self.template {
+            Some(_) if !now_playing.active => String::new(),
+            Some(template) => template::render(template, now_playing, false),
+            None => serde_json::to_string_pretty(now_playing).unwrap_or_default(),
+        }
+    }
+}
+
+// Write the current track to files, e.g. for OBS text sources or status bars
+pub fn start(files: &[String], cover_file: Option<&str>, status: SharedStatus) {
+    let files: Vec<OutputFile> = files.iter().map(|file| OutputFile::parse(file)).collect();
+    let cover_file = cover_file.map(utils::expand_home);
+
+    thread::spawn(move || {
+        let mut last_track = None;
+        let mut last_cover_url = None;
+        loop {
+            let now_playing = NowPlaying::new(&now_playing::current(&status));
+
+            // Position changes all the time, files are only written when something else changed
+            let track = (
+                now_playing.status(),
+                now_playing.title.clone(),
+                now_playing.artist.clone(),
+                now_playing.album.clone(),
+                now_playing.player.clone(),
+                now_playing.cover_url.clone(),
+            );
+            if last_track.as_ref() != Some(&track) {
+                for file in &files {
+                    if let Err(err) = write_atomic(&file.path, file.render(&now_playing).as_bytes())
+                    {
+                        warn!("Could not write {}: {}", file.path.display(), err);
+                    }
+                }
+                last_track = Some(track);
+            }
+
+            if let Some(cover_file) = &cover_file {
+                if last_cover_url.as_ref() != Some(&now_playing.cover_url) {
+                    save_cover(cover_file, &now_playing.cover_url);
+                    last_cover_url = Some(now_playing.cover_url.clone());
+                }
+            }
+
+            thread::sleep(Duration::from_millis(500));
+        }
+    });
+}
+
+// Download album cover, the file is removed when there is no cover
+fn save_cover(cover_file: &Path, cover_url: &str) {
+    if cover_url.is_empty() {
+        let _ = fs::remove_file(cover_file);
+        return;
+    }
+
+    let bytes = match reqwest::blocking::get(cover_url).and_then(|res| res.error_for_status()) {
+        Ok(res) => res.bytes(),
+        Err(err) => Err(err),
+    };
+    match bytes {
+        Ok(bytes) => match write_atomic(cover_file, &bytes) {
+            Ok(_) => debug!("Saved album cover to {}", cover_file.display()),
+            Err(err) => warn!("Could not write {}: {}", cover_file.display(), err),
+        },
+        Err(err) => {
+            warn!("Could not download album cover: {}", err);
+            let _ = fs::remove_file(cover_file);
+        }
+    }
+}
+
+// Write to a temporary file first, so readers never see a half written file
+fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut temp_name = path.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = PathBuf::from(temp_name);
+
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Status;
+
+    fn now_playing() -> NowPlaying {
+        NowPlaying::new(&Status {
+            title: String::from("Song"),
+            artist: String::from("Band"),
+            is_playing: true,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn templates_with_commas() {
+        let file = OutputFile::parse("/tmp/now_playing.txt={artist}, {title}");
+        assert_eq!(file.path, PathBuf::from("/tmp/now_playing.txt"));
+        assert_eq!(file.render(&now_playing()), "Band, Song");
+    }
+
+    #[test]
+    fn default_templates() {
+        let file = OutputFile::parse("/tmp/now_playing.txt");
+        assert_eq!(file.render(&now_playing()), "Band - Song");
+        let file = OutputFile::parse("/tmp/now_playing.json");
+        assert!(file.template.is_none());
+        assert!(file.render(&now_playing()).contains("\"title\": \"Song\""));
+        assert_eq!(
+            OutputFile::parse("/tmp/a.txt").render(&NowPlaying::new(&Status::default())),
+            ""
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use log::debug;
+use regex::{Regex, RegexBuilder};
+
+use crate::utils::MediaInfo;
+
+// Content filter, e.g. "hide; artist=^Some Podcast$" or
+// "placeholder; player=chrome; domain=meet\.google\.com"
+pub struct Filter {
+    rule: String,
+    action: Action,
+    conditions: Vec<Condition>,
+}
+
+#[derive(PartialEq)]
+enum Action {
+    // Clear the activity
+    Hide,
+    // Keep the track, without the album name and cover
+    HideAlbum,
+    // Replace the title and artist with the placeholder text
+    Placeholder,
+}
+
+enum Condition {
+    Title(Regex),
+    Artist(Regex),
+    Album(Regex),
+    Domain(Regex),
+    Player(Regex),
+    // Track length in seconds, both ends are optional
+    Duration(Option<u64>, Option<u64>),
+}
+
+impl Filter {
+    // "action; field=value; field=value", all conditions must match. Values
+    // are regular expressions (ignoring case), durations are "min-max" seconds.
+    pub fn parse(rule: &str) -> Result<Filter, String> {
+        let mut parts = rule.split(';').map(|part| part.trim());
+        let action = match parts.next() {
+            Some("hide") => Action::Hide,
+            Some("hide_album") => Action::HideAlbum,
+            Some("placeholder") => Action::Placeholder,
+            _ => {
+                return Err(String::from(
+                    "expected \"hide\", \"hide_album\" or \"placeholder\" before the first ;",
+                ))
+            }
+        };
+
+        let mut conditions = Vec::new();
+        for part in parts.filter(|part| !part.is_empty()) {
+            let (field, value) = match part.split_once('=') {
+                Some((field, value)) => (field.trim(), value.trim()),
+                None => return Err(format!("expected \"field=value\", got \"{}\"", part)),
+            };
+            let regex = || {
+                RegexBuilder::new(value)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|err| format!("invalid regex for {}: {}", field, err))
+            };
+            let condition = match field {
+                "title" => Condition::Title(regex()?),
+                "artist" => Condition::Artist(regex()?),
+                "album" => Condition::Album(regex()?),
+                "domain" => Condition::Domain(regex()?),
+                "player" => Condition::Player(regex()?),
+                "duration" => parse_range(value)?,
+                _ => {
+                    return Err(format!(
+                        "unknown field \"{}\", expected title, artist, album, domain, player or duration",
+                        field
+                    ))
+                }
+            };
+            conditions.push(condition);
+        }
+        if conditions.is_empty() {
+            return Err(String::from("expected at least one condition"));
+        }
+
+        Ok(Filter {
+            rule: rule.to_string(),
+            action,
+            conditions,
+        })
+    }
+
+    fn matches(&self, media_info: &MediaInfo, player: &str) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            Condition::Title(regex) => regex.is_match(&media_info.title),
+            Condition::Artist(regex) => regex.is_match(&media_info.artist),
+            Condition::Album(regex) => regex.is_match(&media_info.album),
+            Condition::Domain(regex) => match domain(&media_info.url) {
+                Some(domain) => regex.is_match(domain),
+                None => false,
+            },
+            Condition::Player(regex) => regex.is_match(player),
+            Condition::Duration(min, max) => {
+                media_info.duration > 0
+                    && min.is_none_or(|min| media_info.duration >= min)
+                    && max.is_none_or(|max| media_info.duration <= max)
+            }
+        })
+    }
+}
+
+// "60-", "-600" or "60-600"
+fn parse_range(value: &str) -> Result<Condition, String> {
+    let error = || {
+        format!(
+            "expected a duration like \"60-600\" in seconds, got \"{}\"",
+            value
+        )
+    };
+    let (min, max) = value.split_once('-').ok_or_else(error)?;
+    let bound = |bound: &str| -> Result<Option<u64>, String> {
+        match bound.trim() {
+            "" => Ok(None),
+            bound => bound.parse().map(Some).map_err(|_| error()),
+        }
+    };
+    Ok(Condition::Duration(bound(min)?, bound(max)?))
+}
+
+// Host of the media url, e.g. "www.youtube.com"
+fn domain(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let host = rest.split(['/', '?', '#']).next()?;
+    let host = host.rsplit('@').next()?;
+    host.split(':').next().filter(|host| !host.is_empty())
+}
+
+// Filters from settings, rules were already validated when they were loaded
+pub fn parse_all(rules: &[String]) -> Vec<Filter> {
+    rules
+        .iter()
+        .filter_map(|rule| Filter::parse(rule).ok())
+        .collect()
+}
+
+// Track with all matching filters applied and true if its album and cover are
+// hidden, None if the activity should be hidden
+pub fn apply(
+    filters: &[Filter],
+    mut media_info: MediaInfo,
+    player: &str,
+    placeholder: &str,
+) -> Option<(MediaInfo, bool)> {
+    let matched: Vec<&Filter> = filters
+        .iter()
+        .filter(|filter| filter.matches(&media_info, player))
+        .collect();
+    for filter in &matched {
+        debug!("Filter matched: {}", filter.rule);
+    }
+
+    if matched.iter().any(|filter| filter.action == Action::Hide) {
+        return None;
+    }
+    if matched
+        .iter()
+        .any(|filter| filter.action == Action::Placeholder)
+    {
+        media_info.title = placeholder.to_string();
+        media_info.artist = placeholder.to_string();
+        media_info.url = String::new();
+        media_info.path = String::new();
+    }
+    let is_album_hidden = !matched.is_empty();
+    if is_album_hidden {
+        // Both actions hide the album, it would give the track away
+        media_info.album = String::new();
+        media_info.album_artist = String::new();
+        media_info.art_url = String::new();
+        media_info.musicbrainz_album_id = String::new();
+    }
+    Some((media_info, is_album_hidden))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> MediaInfo {
+        MediaInfo {
+            title: String::from("Episode 12"),
+            artist: String::from("Some Podcast"),
+            album: String::from("Season 1"),
+            album_artist: String::from("Some Podcast"),
+            art_url: String::from("https://example.com/cover.jpg"),
+            url: String::from("https://www.youtube.com:443/watch?v=abc"),
+            duration: 3600,
+            ..Default::default()
+        }
+    }
+
+    fn apply_rules(rules: &[&str]) -> Option<(MediaInfo, bool)> {
+        let filters: Vec<Filter> = rules
+            .iter()
+            .map(|rule| Filter::parse(rule).unwrap())
+            .collect();
+        apply(&filters, track(), "Chrome", "Private")
+    }
+
+    fn error(rule: &str) -> String {
+        Filter::parse(rule).err().unwrap()
+    }
+
+    #[test]
+    fn hide() {
+        assert!(apply_rules(&["hide; artist=^some podcast$"]).is_none());
+        // All conditions must match
+        assert!(apply_rules(&["hide; artist=podcast; player=firefox"]).is_some());
+    }
+
+    #[test]
+    fn hide_album() {
+        let (media_info, is_album_hidden) = apply_rules(&["hide_album; duration=1800-"]).unwrap();
+        assert!(is_album_hidden);
+        assert_eq!(media_info.title, "Episode 12");
+        assert_eq!(media_info.album, "");
+        assert_eq!(media_info.album_artist, "");
+        assert_eq!(media_info.art_url, "");
+    }
+
+    #[test]
+    fn placeholder() {
+        let (media_info, is_album_hidden) =
+            apply_rules(&["placeholder; player=chrome|firefox; domain=youtube\\.com$"]).unwrap();
+        assert!(is_album_hidden);
+        assert_eq!(media_info.title, "Private");
+        assert_eq!(media_info.artist, "Private");
+        assert_eq!(media_info.url, "");
+    }
+
+    #[test]
+    fn no_match() {
+        let (media_info, is_album_hidden) =
+            apply_rules(&["hide; title=asmr", "hide_album; duration=-60"]).unwrap();
+        assert!(!is_album_hidden);
+        assert_eq!(media_info.album, "Season 1");
+        assert_eq!(media_info.art_url, "https://example.com/cover.jpg");
+    }
+
+    #[test]
+    fn quantifiers() {
+        assert!(apply_rules(&["hide; title=^episode \\d{1,3}$"]).is_none());
+        assert!(apply_rules(&["hide; title=^episode \\d{3,}$"]).is_some());
+    }
+
+    #[test]
+    fn durations() {
+        let matches = |range: &str| apply_rules(&[&format!("hide; duration={}", range)]).is_none();
+        assert!(matches("3600-"));
+        assert!(matches("-3600"));
+        assert!(matches("60-7200"));
+        assert!(!matches("3601-"));
+        assert!(!matches("-60"));
+    }
+
+    #[test]
+    fn domains() {
+        assert_eq!(
+            domain("https://www.youtube.com:443/watch?v=abc"),
+            Some("www.youtube.com")
+        );
+        assert_eq!(
+            domain("https://user@meet.google.com/abc"),
+            Some("meet.google.com")
+        );
+        assert_eq!(domain("file:///home/user/song.mp3"), None);
+        assert_eq!(domain(""), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            error("remove; title=x"),
+            "expected \"hide\", \"hide_album\" or \"placeholder\" before the first ;"
+        );
+        assert_eq!(error("hide"), "expected at least one condition");
+        assert_eq!(
+            error("hide; title"),
+            "expected \"field=value\", got \"title\""
+        );
+        assert_eq!(
+            error("hide; genre=rock"),
+            "unknown field \"genre\", expected title, artist, album, domain, player or duration"
+        );
+        assert_eq!(
+            error("hide; duration=long"),
+            "expected a duration like \"60-600\" in seconds, got \"long\""
+        );
+        assert!(error("hide; title=(").starts_with("invalid regex for title: "));
+    }
+}
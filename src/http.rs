@@ -0,0 +1,233 @@
+use log::{debug, error, info};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// Request line and headers together, longer requests are dropped
+const MAX_REQUEST_SIZE: u64 = 8 * 1024;
+// Slow clients don't keep a thread busy
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Minimal HTTP/1.1 server for the local endpoints. Every connection is
+// handled on its own thread and closed after the response.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    host: String,
+}
+
+// Listen on the address and pass every request to the handler. Returns false
+// if the address could not be used.
+pub fn serve<F>(name: &str, address: &str, handler: F) -> bool
+where
+    F: Fn(Request, TcpStream) + Send + Sync + Clone + 'static,
+{
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("[{}] Could not listen on {}: {}", name, address, err);
+            return false;
+        }
+    };
+    info!("[{}] Listening on http://{}", name, address);
+
+    let address = address.to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+            let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+            let handler = handler.clone();
+            let address = address.clone();
+            thread::spawn(move || {
+                if let Some(request) = read_request(&stream) {
+                    debug!("{} {}", request.method, request.path);
+                    if !is_local_host(&request.host, &address) {
+                        debug!("Rejected request for host {:?}", request.host);
+                        return forbidden(stream);
+                    }
+                    handler(request, stream);
+                }
+            });
+        }
+    });
+    true
+}
+
+// Read the request line and the Host header, other headers are not used
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_SIZE));
+
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    if !line.ends_with('\n') {
+        return None;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    // Query string is not used
+    let path = parts.next()?.split('?').next()?.to_string();
+
+    let mut host = String::new();
+    loop {
+        let mut header = String::new();
+        match reader.read_line(&mut header) {
+            // Size limit reached or the client disconnected
+            Ok(_) if !header.ends_with('\n') => return None,
+            Ok(_) if header.trim().is_empty() => break,
+            Ok(_) => {
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.trim().eq_ignore_ascii_case("host") {
+                        host = value.trim().to_string();
+                    }
+                }
+            }
+            Err(_) => return None,
+        }
+    }
+    Some(Request { method, path, host })
+}
+
+// Pages from other sites can point their own domain to 127.0.0.1 (DNS
+// rebinding), so only requests for the local address are answered
+fn is_local_host(host: &str, address: &str) -> bool {
+    let (bind_host, bind_port) = address.rsplit_once(':').unwrap_or((address, ""));
+    let (name, port) = match host.rsplit_once(':') {
+        Some((name, port)) if !port.ends_with(']') => (name, Some(port)),
+        _ => (host, None),
+    };
+    let name = name.to_ascii_lowercase();
+    let local = ["localhost", "127.0.0.1", "[::1]", bind_host].contains(&name.as_str());
+    local && port.is_none_or(|port| port == bind_port)
+}
+
+pub fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    let _ = stream.write_all(header.as_bytes());
+    let _ = stream.write_all(body);
+}
+
+// Send headers of a response without length, the body is written until the client disconnects
+pub fn start_stream(stream: &mut TcpStream, content_type: &str) -> bool {
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+        content_type
+    );
+    stream.write_all(header.as_bytes()).is_ok()
+}
+
+fn forbidden(stream: TcpStream) {
+    respond(stream, "403 Forbidden", "text/plain", b"Forbidden\n");
+}
+
+pub fn not_found(stream: TcpStream) {
+    respond(stream, "404 Not Found", "text/plain", b"Not Found\n");
+}
+
+pub fn method_not_allowed(stream: TcpStream) {
+    respond(
+        stream,
+        "405 Method Not Allowed",
+        "text/plain",
+        b"Method Not Allowed\n",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+
+    // Send the raw request and read it on the server side
+    fn read(raw: &[u8]) -> Option<Request> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(raw).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        read_request(&stream)
+    }
+
+    #[test]
+    fn requests() {
+        let request =
+            read(b"GET /overlay?theme=light HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n").unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/overlay");
+        assert_eq!(request.host, "127.0.0.1");
+
+        let request = read(b"GET / HTTP/1.1\r\nhost:  localhost:8974 \r\n\r\n").unwrap();
+        assert_eq!(request.host, "localhost:8974");
+
+        // Missing end of the headers
+        assert!(read(b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n").is_none());
+        assert!(read(b"").is_none());
+    }
+
+    #[test]
+    fn local_hosts() {
+        let address = "127.0.0.1:8974";
+        for host in [
+            "127.0.0.1:8974",
+            "127.0.0.1",
+            "localhost:8974",
+            "LocalHost",
+            "[::1]:8974",
+            "[::1]",
+        ] {
+            assert!(is_local_host(host, address), "{}", host);
+        }
+        for host in [
+            "",
+            "attacker.example.com",
+            "attacker.example.com:8974",
+            "localhost.example.com:8974",
+            "127.0.0.1:9000",
+            "[::1]:9000",
+        ] {
+            assert!(!is_local_host(host, address), "{}", host);
+        }
+    }
+
+    #[test]
+    fn size_limit() {
+        let long_path = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(MAX_REQUEST_SIZE as usize)
+        );
+        assert!(read(long_path.as_bytes()).is_none());
+
+        let mut many_headers = b"GET / HTTP/1.1\r\n".to_vec();
+        for index in 0..1000 {
+            many_headers.extend(format!("X-Header-{}: value\r\n", index).as_bytes());
+        }
+        many_headers.extend(b"\r\n");
+        assert!(read(&many_headers).is_none());
+    }
+
+    #[test]
+    fn responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        respond(stream, "200 OK", "text/plain", b"body\n");
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nConnection: close\r\n\r\nbody\n"
+        );
+    }
+}
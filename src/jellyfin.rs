@@ -0,0 +1,297 @@
+use reqwest::blocking::Client;
+use reqwest::header::AUTHORIZATION;
+use serde_json::Value;
+use std::time::Duration;
+
+use crate::settings::Cli;
+use crate::source::Source;
+use crate::utils::{MediaInfo, NowPlayingResult};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Item types shown with the "watching" activity
+const VIDEO_TYPES: [&str; 5] = ["Movie", "Episode", "Video", "MusicVideo", "TvChannel"];
+
+// Jellyfin server, read from the Sessions API with an API key, see
+// https://api.jellyfin.org/#tag/Session
+pub struct Jellyfin {
+    client: Client,
+    url: String,
+    user: String,
+    token: String,
+    is_video: bool,
+}
+
+impl Jellyfin {
+    pub fn new(settings: &Cli) -> Jellyfin {
+        Jellyfin {
+            client: Client::builder()
+                .timeout(Duration::from_secs(10))
+                .user_agent(format!("music-discord-rpc/{}", VERSION))
+                .build()
+                .unwrap_or_default(),
+            url: settings
+                .server_url
+                .clone()
+                .unwrap_or_default()
+                .trim_end_matches('/')
+                .to_string(),
+            user: settings.server_user.clone().unwrap_or_default(),
+            token: settings.server_token.clone().unwrap_or_default(),
+            is_video: false,
+        }
+    }
+}
+
+impl Source for Jellyfin {
+    fn name(&self) -> String {
+        String::from("Jellyfin")
+    }
+
+    fn get_media_info(&mut self) -> NowPlayingResult {
+        if self.url.is_empty() {
+            return Err("Server url is not set.".into());
+        }
+        let sessions: Value = self
+            .client
+            .get(format!("{}/Sessions?ActiveWithinSeconds=960", self.url))
+            .header(
+                AUTHORIZATION,
+                format!(
+                    "MediaBrowser Client=\"music-discord-rpc\", Version=\"{}\", Token=\"{}\"",
+                    VERSION, self.token
+                ),
+            )
+            .send()?
+            .error_for_status()?
+            .json()?;
+        self.media_info(&sessions)
+    }
+
+    fn is_video(&self) -> bool {
+        self.is_video
+    }
+}
+
+impl Jellyfin {
+    fn media_info(&mut self, sessions: &Value) -> NowPlayingResult {
+        // Sessions of the user with something loaded, playing ones first
+        let mut sessions: Vec<&Value> = sessions
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|session| session["NowPlayingItem"].is_object())
+            .filter(|session| {
+                self.user.is_empty()
+                    || session["UserName"]
+                        .as_str()
+                        .is_some_and(|name| name.eq_ignore_ascii_case(&self.user))
+            })
+            .collect();
+        sessions.sort_by_key(|session| session["PlayState"]["IsPaused"].as_bool().unwrap_or(false));
+        let session = match sessions.first() {
+            Some(session) => session,
+            None => return Err("Nothing is playing on the Jellyfin server.".into()),
+        };
+
+        let item = &session["NowPlayingItem"];
+        let text = |value: &Value| -> Option<String> {
+            value
+                .as_str()
+                .filter(|value| !value.is_empty())
+                .map(|value| value.to_string())
+        };
+
+        // Videos have no artist, the series name is used for episodes
+        let artist = match item["Artists"].as_array().map(|artists| {
+            artists
+                .iter()
+                .filter_map(|artist| artist.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }) {
+            Some(artists) if !artists.is_empty() => artists,
+            _ => text(&item["SeriesName"]).unwrap_or_else(|| String::from("Unknown Artist")),
+        };
+        let album = text(&item["Album"])
+            .or(text(&item["SeasonName"]))
+            .unwrap_or_else(|| String::from("Unknown Album"));
+        let album_artist = text(&item["AlbumArtist"]).unwrap_or(artist.clone());
+
+        // Ticks are 100 nanoseconds
+        let seconds = |ticks: &Value| ticks.as_u64().unwrap_or(0) / 10_000_000;
+        let position = &session["PlayState"]["PositionTicks"];
+
+        // Album cover for tracks, the item's own image otherwise
+        let image = match (text(&item["AlbumId"]), text(&item["AlbumPrimaryImageTag"])) {
+            (Some(album_id), Some(tag)) => Some((album_id, tag)),
+            _ => text(&item["Id"]).zip(text(&item["ImageTags"]["Primary"])),
+        };
+        let art_url = match image {
+            Some((id, tag)) => format!(
+                "{}/Items/{}/Images/Primary?maxHeight=512&tag={}",
+                self.url, id, tag
+            ),
+            None => String::new(),
+        };
+
+        self.is_video = item["Type"]
+            .as_str()
+            .is_some_and(|item_type| VIDEO_TYPES.contains(&item_type));
+
+        Ok(MediaInfo {
+            title: text(&item["Name"]).unwrap_or_else(|| String::from("Unknown Title")),
+            artist,
+            album_artist,
+            album,
+            is_playing: !session["PlayState"]["IsPaused"].as_bool().unwrap_or(false),
+            duration: seconds(&item["RunTimeTicks"]),
+            position: seconds(position),
+            is_track_position: position.is_u64(),
+            art_url,
+            musicbrainz_album_id: text(&item["ProviderIds"]["MusicBrainzAlbum"])
+                .unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn jellyfin(user: &str) -> Jellyfin {
+        Jellyfin {
+            client: Client::new(),
+            url: String::from("https://jellyfin.example.org"),
+            user: user.to_string(),
+            token: String::from("0123456789abcdef"),
+            is_video: false,
+        }
+    }
+
+    // Part of the response of /Sessions, most fields left out
+    fn sessions() -> Value {
+        json!([
+            {
+                "PlayState": {"CanSeek": false, "IsPaused": false, "IsMuted": false},
+                "Id": "c1d2e3f4",
+                "UserName": "Alice",
+                "Client": "Jellyfin Web",
+                "DeviceName": "Firefox"
+            },
+            {
+                "PlayState": {
+                    "PositionTicks": 1_234_567_890u64,
+                    "CanSeek": true,
+                    "IsPaused": true,
+                    "IsMuted": false,
+                    "PlayMethod": "DirectPlay"
+                },
+                "Id": "a1b2c3d4",
+                "UserName": "Alice",
+                "Client": "Finamp",
+                "NowPlayingItem": {
+                    "Name": "Lucky",
+                    "Id": "8f7e6d5c",
+                    "RunTimeTicks": 2_597_000_000u64,
+                    "Type": "Audio",
+                    "Album": "OK Computer",
+                    "AlbumId": "1a2b3c4d",
+                    "AlbumPrimaryImageTag": "9e8d7c6b",
+                    "Artists": ["Radiohead"],
+                    "AlbumArtist": "Radiohead",
+                    "ImageTags": {}
+                }
+            },
+            {
+                "PlayState": {
+                    "PositionTicks": 825_120_000u64,
+                    "CanSeek": true,
+                    "IsPaused": false,
+                    "IsMuted": false,
+                    "PlayMethod": "DirectPlay"
+                },
+                "Id": "e5f6a7b8",
+                "UserName": "alice",
+                "Client": "Jellyfin Web",
+                "NowPlayingItem": {
+                    "Name": "Paranoid Android",
+                    "Id": "2b9f0e6d",
+                    "RunTimeTicks": 3_834_930_000u64,
+                    "Type": "Audio",
+                    "Album": "OK Computer",
+                    "AlbumId": "1a2b3c4d",
+                    "AlbumPrimaryImageTag": "9e8d7c6b",
+                    "Artists": ["Radiohead", "Other Artist"],
+                    "AlbumArtist": "Radiohead",
+                    "ProviderIds": {"MusicBrainzAlbum": "0b6b4ba0-d36f-47bd-b4ea-6a5b91842d29"},
+                    "ImageTags": {"Primary": "5a4b3c2d"}
+                }
+            },
+            {
+                "PlayState": {"PositionTicks": 0, "IsPaused": false},
+                "Id": "f0e1d2c3",
+                "UserName": "bob",
+                "NowPlayingItem": {
+                    "Name": "Pilot",
+                    "Id": "3c4d5e6f",
+                    "RunTimeTicks": 36_000_000_000u64,
+                    "Type": "Episode",
+                    "SeriesName": "Some Series",
+                    "SeasonName": "Season 1",
+                    "ImageTags": {"Primary": "7f6e5d4c"}
+                }
+            }
+        ])
+    }
+
+    #[test]
+    fn playing_session_of_the_user() {
+        let mut jellyfin = jellyfin("ALICE");
+        let media_info = jellyfin.media_info(&sessions()).unwrap();
+        assert_eq!(media_info.title, "Paranoid Android");
+        assert_eq!(media_info.artist, "Radiohead, Other Artist");
+        assert_eq!(media_info.album_artist, "Radiohead");
+        assert_eq!(media_info.album, "OK Computer");
+        assert!(media_info.is_playing);
+        assert_eq!(media_info.duration, 383);
+        assert_eq!(media_info.position, 82);
+        assert!(media_info.is_track_position);
+        assert_eq!(
+            media_info.art_url,
+            "https://jellyfin.example.org/Items/1a2b3c4d/Images/Primary?maxHeight=512&tag=9e8d7c6b"
+        );
+        assert_eq!(
+            media_info.musicbrainz_album_id,
+            "0b6b4ba0-d36f-47bd-b4ea-6a5b91842d29"
+        );
+        assert!(!jellyfin.is_video());
+    }
+
+    #[test]
+    fn episodes() {
+        let mut jellyfin = jellyfin("bob");
+        let media_info = jellyfin.media_info(&sessions()).unwrap();
+        assert_eq!(media_info.title, "Pilot");
+        assert_eq!(media_info.artist, "Some Series");
+        assert_eq!(media_info.album, "Season 1");
+        assert_eq!(media_info.duration, 3600);
+        assert_eq!(
+            media_info.art_url,
+            "https://jellyfin.example.org/Items/3c4d5e6f/Images/Primary?maxHeight=512&tag=7f6e5d4c"
+        );
+        assert!(jellyfin.is_video());
+    }
+
+    #[test]
+    fn nothing_playing() {
+        let mut jellyfin = jellyfin("carol");
+        assert_eq!(
+            jellyfin.media_info(&sessions()).err().unwrap().to_string(),
+            "Nothing is playing on the Jellyfin server."
+        );
+        assert!(jellyfin.media_info(&json!([])).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use log::{debug, warn};
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::Event;
+use crate::settings::Cli;
+use crate::source::Source;
+use crate::utils::{self, MediaInfo, NowPlayingResult};
+
+// One line of input, every message replaces the previous one
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+struct Message {
+    clear: bool,
+    title: String,
+    artist: String,
+    album: String,
+    album_artist: String,
+    is_playing: Option<bool>,
+    duration: u64,
+    position: Option<u64>,
+    art_url: String,
+    url: String,
+    player: String,
+}
+
+// Newline-delimited JSON from stdin ("-") or a named pipe, for players and
+// scripts without MPRIS
+pub struct JsonInput {
+    // Last message and when it was received
+    state: Arc<Mutex<Option<(Message, Instant)>>>,
+    // Player of the last returned track
+    player: Option<String>,
+}
+
+impl JsonInput {
+    pub fn new(settings: &Cli, events: Sender<Event>) -> JsonInput {
+        let input = settings.json_input.clone().unwrap_or_default();
+        let state = Arc::new(Mutex::new(None));
+        read_messages(input, state.clone(), events);
+        JsonInput {
+            state,
+            player: None,
+        }
+    }
+}
+
+impl Source for JsonInput {
+    fn name(&self) -> String {
+        match self.player.as_deref() {
+            Some(player) if !player.is_empty() => player.to_string(),
+            _ => String::from("JSON"),
+        }
+    }
+
+    fn get_media_info(&mut self) -> NowPlayingResult {
+        let (message, received) = match self.state.lock() {
+            Ok(state) => match state.as_ref() {
+                Some(state) => state.clone(),
+                None => return Err("Nothing is playing on the JSON input.".into()),
+            },
+            Err(_) => return Err("JSON input state is not available.".into()),
+        };
+
+        // Same as a new player, the name is read again by the main loop
+        if self.player.as_ref() != Some(&message.player) {
+            let is_first = self.player.is_none();
+            self.player = Some(message.player.clone());
+            if !is_first {
+                return Err("Detected player change.".into());
+            }
+        }
+
+        // The position is counted from when the message was received
+        let is_playing = message.is_playing.unwrap_or(true);
+        let position = message.position.map(|position| {
+            let mut position = position;
+            if is_playing {
+                position += received.elapsed().as_secs();
+            }
+            match message.duration > 0 {
+                true => position.min(message.duration),
+                false => position,
+            }
+        });
+
+        let artist = match message.artist.is_empty() {
+            true => String::from("Unknown Artist"),
+            false => message.artist,
+        };
+        let album = match message.album.is_empty() {
+            true => String::from("Unknown Album"),
+            false => message.album,
+        };
+
+        Ok(MediaInfo {
+            title: match message.title.is_empty() {
+                true => String::from("Unknown Title"),
+                false => message.title,
+            },
+            album_artist: match message.album_artist.is_empty() {
+                true => artist.clone(),
+                false => message.album_artist,
+            },
+            artist,
+            album,
+            is_playing,
+            duration: message.duration,
+            position: position.unwrap_or(0),
+            is_track_position: position.is_some(),
+            art_url: message.art_url,
+            url: message.url,
+            ..Default::default()
+        })
+    }
+}
+
+// Read messages until the input is closed. Named pipes are opened again, so
+// every writer (e.g. echo '{...}' > pipe) can send its update and exit.
+fn read_messages(
+    input: String,
+    state: Arc<Mutex<Option<(Message, Instant)>>>,
+    events: Sender<Event>,
+) {
+    thread::spawn(move || loop {
+        let path = utils::expand_home(&input);
+        let reader: Box<dyn BufRead> = match input.as_str() {
+            "-" => Box::new(BufReader::new(io::stdin())),
+            _ => match File::open(&path) {
+                Ok(file) => Box::new(BufReader::new(file)),
+                Err(err) => {
+                    warn!("Could not open JSON input {}: {}", path.display(), err);
+                    thread::sleep(Duration::from_secs(5));
+                    continue;
+                }
+            },
+        };
+
+        if !read_lines(reader, &state, &events) {
+            return;
+        }
+        if input == "-" || !is_fifo(&path) {
+            debug!("JSON input closed.");
+            return;
+        }
+    });
+}
+
+// Store every message until the reader ends, false if the main loop is gone
+fn read_lines(
+    reader: Box<dyn BufRead>,
+    state: &Mutex<Option<(Message, Instant)>>,
+    events: &Sender<Event>,
+) -> bool {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message: Message = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("Invalid JSON input: {}", err);
+                continue;
+            }
+        };
+        if let Ok(mut state) = state.lock() {
+            *state = match message.clear {
+                true => None,
+                false => Some((message, Instant::now())),
+            };
+        }
+        if events.send(Event::PlayerChanged).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+fn is_fifo(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::mpsc;
+
+    // Source after reading the given lines, and the number of wake-ups
+    fn read(input: &str) -> (JsonInput, usize) {
+        let state = Arc::new(Mutex::new(None));
+        let (sender, receiver) = mpsc::channel();
+        let reader = Box::new(Cursor::new(input.to_string()));
+        assert!(read_lines(reader, &state, &sender));
+        let source = JsonInput {
+            state,
+            player: None,
+        };
+        (source, receiver.try_iter().count())
+    }
+
+    #[test]
+    fn last_message_is_used() {
+        let (mut source, events) = read(concat!(
+            r#"{"title": "Airbag", "artist": "Radiohead", "position": 10}"#,
+            "\n\n",
+            r#"{"title": "Paranoid Android", "artist": "Radiohead", "album": "OK Computer", "duration": 383, "position": 82, "is_playing": false, "art_url": "https://example.org/cover.jpg", "url": "https://example.org/track/2", "player": "My Player"}"#,
+            "\n",
+        ));
+        assert_eq!(events, 2);
+        let media_info = source.get_media_info().unwrap();
+        assert_eq!(source.name(), "My Player");
+        assert_eq!(media_info.title, "Paranoid Android");
+        assert_eq!(media_info.artist, "Radiohead");
+        assert_eq!(media_info.album_artist, "Radiohead");
+        assert_eq!(media_info.album, "OK Computer");
+        assert!(!media_info.is_playing);
+        assert_eq!(media_info.duration, 383);
+        assert_eq!(media_info.position, 82);
+        assert!(media_info.is_track_position);
+        assert_eq!(media_info.art_url, "https://example.org/cover.jpg");
+        assert_eq!(media_info.url, "https://example.org/track/2");
+    }
+
+    #[test]
+    fn missing_fields() {
+        let (mut source, _) = read("{}\n");
+        let media_info = source.get_media_info().unwrap();
+        assert_eq!(source.name(), "JSON");
+        assert_eq!(media_info.title, "Unknown Title");
+        assert_eq!(media_info.artist, "Unknown Artist");
+        assert_eq!(media_info.album, "Unknown Album");
+        assert!(media_info.is_playing);
+        assert!(!media_info.is_track_position);
+    }
+
+    #[test]
+    fn invalid_lines_are_skipped() {
+        let (mut source, events) = read(concat!(
+            r#"{"title": "Airbag"}"#,
+            "\nnot json\n",
+            r#"{"title": 5}"#,
+            "\n",
+        ));
+        assert_eq!(events, 1);
+        assert_eq!(source.get_media_info().unwrap().title, "Airbag");
+    }
+
+    #[test]
+    fn clear() {
+        let (mut source, events) = read(concat!(
+            r#"{"title": "Airbag"}"#,
+            "\n",
+            r#"{"clear": true}"#,
+            "\n",
+        ));
+        assert_eq!(events, 2);
+        assert_eq!(
+            source.get_media_info().err().unwrap().to_string(),
+            "Nothing is playing on the JSON input."
+        );
+    }
+
+    #[test]
+    fn player_change() {
+        let (mut source, _) = read(concat!(r#"{"title": "Airbag", "player": "one"}"#, "\n"));
+        assert!(source.get_media_info().is_ok());
+        *source.state.lock().unwrap() = Some((
+            serde_json::from_str(r#"{"title": "Lucky", "player": "two"}"#).unwrap(),
+            Instant::now(),
+        ));
+        assert_eq!(
+            source.get_media_info().err().unwrap().to_string(),
+            "Detected player change."
+        );
+        assert_eq!(source.name(), "two");
+        assert_eq!(source.get_media_info().unwrap().title, "Lucky");
+    }
+}
@@ -0,0 +1,644 @@
+use chrono::{Local, SecondsFormat};
+use log::kv::{self, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, Value};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+#[cfg(target_os = "linux")]
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::settings::Cli;
+
+// Name of the crate as it appears in log targets, e.g. music_discord_rpc::tray
+const CRATE_NAME: &str = env!("CARGO_CRATE_NAME");
+
+// Log file is rotated when it gets bigger than this, older files are kept as .1, .2 and .3
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+const MAX_ROTATED_FILES: u32 = 3;
+
+// Records logged before the logger is configured are kept until then
+const MAX_BUFFERED_RECORDS: usize = 1000;
+
+#[cfg(target_os = "linux")]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+// Level of every module, parsed from e.g. "info,tray=debug,reqwest=warn".
+// Modules of this app use the default level, other crates only log errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filters {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filters {
+    pub fn parse(spec: &str) -> Result<Filters, String> {
+        let mut filters = Filters {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        };
+
+        for directive in spec.split(',').map(str::trim) {
+            if directive.is_empty() {
+                continue;
+            }
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.trim();
+                    if module.is_empty() {
+                        return Err(format!("missing module name in \"{}\"", directive));
+                    }
+                    filters
+                        .modules
+                        .push((module.replace('-', "_"), parse_level(level.trim())?));
+                }
+                None => filters.default = parse_level(directive)?,
+            }
+        }
+
+        // The most specific module wins
+        filters
+            .modules
+            .sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Ok(filters)
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        let module = target
+            .strip_prefix(CRATE_NAME)
+            .map(|module| module.trim_start_matches("::"));
+
+        for (name, level) in &self.modules {
+            let matches = |path: &str| path == name || path.starts_with(&format!("{}::", name));
+            if matches(target) || module.is_some_and(matches) {
+                return *level;
+            }
+        }
+        match module {
+            Some(_) => self.default,
+            None => self.default.min(LevelFilter::Error),
+        }
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    level.parse::<LevelFilter>().map_err(|_| {
+        format!(
+            "unknown log level \"{}\" (expected off, error, warn, info, debug or trace)",
+            level
+        )
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Stdout,
+    Stderr,
+    File,
+    Journald,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+// Logging settings derived from the merged settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+    pub filters: Filters,
+    pub output: Output,
+    pub format: Format,
+    pub log_file: PathBuf,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            filters: Filters {
+                default: LevelFilter::Info,
+                modules: Vec::new(),
+            },
+            output: Output::Stdout,
+            format: Format::Text,
+            log_file: PathBuf::new(),
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn new(settings: &Cli, cache_dir: &Path) -> LogConfig {
+        let level = settings.log_level.as_deref().unwrap_or("info");
+        let mut filters = Filters::parse(level).unwrap_or(LogConfig::default().filters);
+        // Old flag, still shows debug messages of the whole app
+        if settings.debug_log {
+            filters.default = filters.default.max(LevelFilter::Debug);
+        }
+
+        LogConfig {
+            filters,
+            output: match settings.log_output.as_deref() {
+                Some("file") => Output::File,
+                Some("journald") => Output::Journald,
+                _ => Output::Stdout,
+            },
+            format: match settings.log_format.as_deref() {
+                Some("json") => Format::Json,
+                _ => Format::Text,
+            },
+            log_file: cache_dir.join("music-discord-rpc.log"),
+        }
+    }
+}
+
+// Owned copy of a record, so it can be buffered
+struct Entry {
+    time: chrono::DateTime<Local>,
+    level: Level,
+    target: String,
+    message: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Entry {
+    fn module(&self) -> &str {
+        match self.target.strip_prefix(CRATE_NAME) {
+            Some(module) => match module.trim_start_matches("::") {
+                "" => "main",
+                module => module,
+            },
+            None => &self.target,
+        }
+    }
+}
+
+struct FieldCollector(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for FieldCollector {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+struct State {
+    config: Option<LogConfig>,
+    buffer: Vec<Entry>,
+    file: Option<File>,
+    file_size: u64,
+    color: bool,
+    #[cfg(target_os = "linux")]
+    journald: Option<UnixDatagram>,
+}
+
+struct Logger {
+    state: Mutex<State>,
+}
+
+static LOGGER: Logger = Logger {
+    state: Mutex::new(State {
+        config: None,
+        buffer: Vec::new(),
+        file: None,
+        file_size: 0,
+        color: false,
+        #[cfg(target_os = "linux")]
+        journald: None,
+    }),
+};
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.state.lock() {
+            Ok(state) => match &state.config {
+                Some(config) => metadata.level() <= config.filters.level(metadata.target()),
+                None => true,
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        let mut fields = FieldCollector(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+        let entry = Entry {
+            time: Local::now(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            fields: fields.0,
+        };
+
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        let enabled = state
+            .config
+            .as_ref()
+            .map(|config| entry.level <= config.filters.level(&entry.target));
+        match enabled {
+            Some(true) => state.write(&entry),
+            Some(false) => {}
+            None => {
+                if state.buffer.len() < MAX_BUFFERED_RECORDS {
+                    state.buffer.push(entry);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(file) = &mut state.file {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+impl State {
+    fn write(&mut self, entry: &Entry) {
+        let (output, format) = match &self.config {
+            Some(config) => (config.output, config.format),
+            None => return,
+        };
+
+        match output {
+            Output::Stdout => {
+                let line = match format {
+                    Format::Text => format_text(entry, self.color),
+                    Format::Json => format_json(entry),
+                };
+                println!("{}", line);
+            }
+            Output::Stderr => {
+                let line = match format {
+                    Format::Text => format_text(entry, self.color),
+                    Format::Json => format_json(entry),
+                };
+                eprintln!("{}", line);
+            }
+            Output::File => {
+                let line = match format {
+                    Format::Text => format_file(entry),
+                    Format::Json => format_json(entry),
+                };
+                self.write_file(&line);
+            }
+            Output::Journald => self.write_journald(entry),
+        }
+    }
+
+    fn write_file(&mut self, line: &str) {
+        let log_file = match &self.config {
+            Some(config) => config.log_file.clone(),
+            None => return,
+        };
+
+        if self.file.is_some() && self.file_size >= MAX_FILE_SIZE {
+            self.file = None;
+            rotate(&log_file);
+        }
+        if self.file.is_none() {
+            match OpenOptions::new().create(true).append(true).open(&log_file) {
+                Ok(file) => {
+                    self.file_size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+                    self.file = Some(file);
+                }
+                Err(err) => {
+                    println!("Could not open log file {}: {}", log_file.display(), err);
+                    println!("{}", line);
+                    return;
+                }
+            }
+        }
+
+        if let Some(file) = &mut self.file {
+            if writeln!(file, "{}", line).is_ok() {
+                self.file_size += line.len() as u64 + 1;
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn write_journald(&mut self, entry: &Entry) {
+        if self.journald.is_none() {
+            self.journald = UnixDatagram::unbound().ok();
+        }
+        let sent = match &self.journald {
+            Some(socket) => socket
+                .send_to(&format_journald(entry), JOURNALD_SOCKET)
+                .is_ok(),
+            None => false,
+        };
+        // Journal is not running, don't lose the message
+        if !sent {
+            println!("{}", format_text(entry, false));
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn write_journald(&mut self, entry: &Entry) {
+        println!("{}", format_text(entry, false));
+    }
+}
+
+// Move music-discord-rpc.log to .1, .1 to .2 and so on, the oldest one is removed
+fn rotate(log_file: &Path) {
+    let rotated = |index: u32| PathBuf::from(format!("{}.{}", log_file.display(), index));
+    let _ = fs::remove_file(rotated(MAX_ROTATED_FILES));
+    for index in (1..MAX_ROTATED_FILES).rev() {
+        let _ = fs::rename(rotated(index), rotated(index + 1));
+    }
+    let _ = fs::rename(log_file, rotated(1));
+}
+
+// Format used in the terminal, info messages are printed as they are
+fn format_text(entry: &Entry, color: bool) -> String {
+    let (name, color_code) = match entry.level {
+        Level::Error => ("error", "31;1"),
+        Level::Warn => ("warn", "33;1"),
+        Level::Info => return entry.message.clone(),
+        Level::Debug => ("debug", "34;1"),
+        Level::Trace => ("trace", "35;1"),
+    };
+    match color {
+        true => format!("\x1b[{}m[{}]\x1b[0m {}", color_code, name, entry.message),
+        false => format!("[{}] {}", name, entry.message),
+    }
+}
+
+// Format used in the log file, with time, module and fields
+fn format_file(entry: &Entry) -> String {
+    let mut line = format!(
+        "{} {:<5} {}: {}",
+        entry.time.to_rfc3339_opts(SecondsFormat::Millis, false),
+        entry.level,
+        entry.module(),
+        entry.message
+    );
+    for (key, value) in &entry.fields {
+        line += &format!(" {}={:?}", key, value);
+    }
+    line
+}
+
+fn format_json(entry: &Entry) -> String {
+    let mut object = Map::new();
+    object.insert(
+        String::from("timestamp"),
+        Value::from(entry.time.to_rfc3339_opts(SecondsFormat::Millis, false)),
+    );
+    object.insert(
+        String::from("level"),
+        Value::from(entry.level.as_str().to_lowercase()),
+    );
+    object.insert(String::from("module"), Value::from(entry.module()));
+    object.insert(String::from("message"), Value::from(entry.message.clone()));
+    for (key, value) in &entry.fields {
+        object.insert(key.clone(), Value::from(value.clone()));
+    }
+    Value::Object(object).to_string()
+}
+
+// Native journal protocol, see systemd.journal-fields(7)
+#[cfg(target_os = "linux")]
+fn format_journald(entry: &Entry) -> Vec<u8> {
+    let priority = match entry.level {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    };
+
+    let mut fields = vec![
+        (String::from("MESSAGE"), entry.message.clone()),
+        (String::from("PRIORITY"), String::from(priority)),
+        (
+            String::from("SYSLOG_IDENTIFIER"),
+            String::from("music-discord-rpc"),
+        ),
+        (String::from("CODE_MODULE"), entry.module().to_string()),
+    ];
+    for (key, value) in &entry.fields {
+        let key: String = key
+            .chars()
+            .map(|char| match char.is_ascii_alphanumeric() {
+                true => char.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect();
+        fields.push((key.trim_start_matches('_').to_string(), value.clone()));
+    }
+
+    let mut data = Vec::new();
+    for (key, value) in fields {
+        data.extend_from_slice(key.as_bytes());
+        // Values with new lines are sent with their size instead of after "="
+        if value.contains('\n') {
+            data.push(b'\n');
+            data.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            data.extend_from_slice(value.as_bytes());
+        } else {
+            data.push(b'=');
+            data.extend_from_slice(value.as_bytes());
+        }
+        data.push(b'\n');
+    }
+    data
+}
+
+// Install the logger. Records are kept in memory until configure() is called,
+// so messages printed while loading the settings use the configured output.
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Trace);
+    }
+}
+
+// Apply new settings and write buffered records. Can be called again on reload.
+pub fn configure(config: LogConfig) {
+    let mut state = match LOGGER.state.lock() {
+        Ok(state) => state,
+        Err(_) => return,
+    };
+
+    if state.config.as_ref() != Some(&config) {
+        state.file = None;
+        state.file_size = 0;
+    }
+    let is_terminal = match config.output {
+        Output::Stderr => io::stderr().is_terminal(),
+        _ => io::stdout().is_terminal(),
+    };
+    state.color = is_terminal && std::env::var_os("NO_COLOR").is_none();
+    log::set_max_level(config.filters.max_level());
+    state.config = Some(config.clone());
+
+    let buffer = std::mem::take(&mut state.buffer);
+    for entry in buffer {
+        if entry.level <= config.filters.level(&entry.target) {
+            state.write(&entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(spec: &str, target: &str) -> LevelFilter {
+        Filters::parse(spec).unwrap().level(target)
+    }
+
+    fn entry(level: Level, target: &str, message: &str) -> Entry {
+        Entry {
+            time: Local::now(),
+            level,
+            target: target.to_string(),
+            message: message.to_string(),
+            fields: vec![(String::from("player"), String::from("mpv"))],
+        }
+    }
+
+    #[test]
+    fn module_filters() {
+        let spec = "info,music_discord_rpc::mpd=debug,tray=warn,reqwest=trace";
+        assert_eq!(level(spec, "music_discord_rpc"), LevelFilter::Info);
+        assert_eq!(level(spec, "music_discord_rpc::mpd"), LevelFilter::Debug);
+        assert_eq!(level(spec, "music_discord_rpc::mpv"), LevelFilter::Info);
+        assert_eq!(level(spec, "music_discord_rpc::tray"), LevelFilter::Warn);
+        assert_eq!(level(spec, "reqwest::connect"), LevelFilter::Trace);
+        // Other crates only log errors unless they are named
+        assert_eq!(level(spec, "mpris::player"), LevelFilter::Error);
+        assert_eq!(level("off", "mpris"), LevelFilter::Off);
+        assert_eq!(
+            Filters::parse(spec).unwrap().max_level(),
+            LevelFilter::Trace
+        );
+    }
+
+    #[test]
+    fn longest_module_wins() {
+        let spec = "warn,music-discord-rpc=error,music_discord_rpc::mpd=debug";
+        assert_eq!(level(spec, "music_discord_rpc::mpd"), LevelFilter::Debug);
+        assert_eq!(level(spec, "music_discord_rpc::mqtt"), LevelFilter::Error);
+        // Only whole module names match
+        assert_eq!(
+            level("mpd=debug", "music_discord_rpc::mpdx"),
+            LevelFilter::Info
+        );
+        assert_eq!(
+            level("mpd=debug", "music_discord_rpc::mpd::idle"),
+            LevelFilter::Debug
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            Filters::parse("loud").err().unwrap(),
+            "unknown log level \"loud\" (expected off, error, warn, info, debug or trace)"
+        );
+        assert_eq!(
+            Filters::parse("info,=debug").err().unwrap(),
+            "missing module name in \"=debug\""
+        );
+        assert!(Filters::parse("mpd=verbose").is_err());
+        assert_eq!(
+            Filters::parse(" debug , ,").unwrap(),
+            Filters {
+                default: LevelFilter::Debug,
+                modules: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn formats() {
+        let warning = entry(Level::Warn, "music_discord_rpc::mpd", "Connection lost");
+        assert_eq!(format_text(&warning, false), "[warn] Connection lost");
+        assert_eq!(
+            format_text(&warning, true),
+            "\x1b[33;1m[warn]\x1b[0m Connection lost"
+        );
+        assert!(format_file(&warning).ends_with(" WARN  mpd: Connection lost player=\"mpv\""));
+
+        let json: Value = serde_json::from_str(&format_json(&warning)).unwrap();
+        assert_eq!(json["level"], "warn");
+        assert_eq!(json["module"], "mpd");
+        assert_eq!(json["message"], "Connection lost");
+        assert_eq!(json["player"], "mpv");
+
+        let info = entry(Level::Info, "music_discord_rpc", "Started");
+        assert_eq!(format_text(&info, true), "Started");
+        assert_eq!(info.module(), "main");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn journald_fields() {
+        let error = entry(Level::Error, "reqwest", "Request failed\nTimed out");
+        let data = format_journald(&error);
+        let text = String::from_utf8_lossy(&data);
+        assert!(text.contains("PRIORITY=3\n"));
+        assert!(text.contains("CODE_MODULE=reqwest\n"));
+        assert!(text.contains("PLAYER=mpv\n"));
+        // Multi-line values have their size before them
+        let mut message = b"MESSAGE\n".to_vec();
+        message.extend_from_slice(&24u64.to_le_bytes());
+        message.extend_from_slice(b"Request failed\nTimed out\n");
+        assert!(data.starts_with(&message));
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = std::env::temp_dir().join(format!("logger-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let log_file = dir.join("music-discord-rpc.log");
+        let mut state = State {
+            config: Some(LogConfig {
+                output: Output::File,
+                log_file: log_file.clone(),
+                ..Default::default()
+            }),
+            buffer: Vec::new(),
+            file: None,
+            file_size: 0,
+            color: false,
+            #[cfg(target_os = "linux")]
+            journald: None,
+        };
+
+        // Every line fills the file, so each one starts a new file
+        let line = "x".repeat(MAX_FILE_SIZE as usize);
+        for index in 0..=MAX_ROTATED_FILES + 1 {
+            state.write_file(&format!("{}{}", index, line));
+        }
+        let first_char = |path: PathBuf| fs::read_to_string(path).unwrap().remove(0);
+        let rotated = |index: u32| PathBuf::from(format!("{}.{}", log_file.display(), index));
+        assert_eq!(first_char(log_file.clone()), '4');
+        assert_eq!(first_char(rotated(1)), '3');
+        assert_eq!(first_char(rotated(3)), '1');
+        assert!(!rotated(4).exists());
+
+        // Small lines are appended to the current file
+        fs::remove_file(&log_file).unwrap();
+        state.file = None;
+        state.write_file("one");
+        state.write_file("two");
+        assert_eq!(fs::read_to_string(&log_file).unwrap(), "one\ntwo\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
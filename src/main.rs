@@ -1,20 +1,51 @@
-use discord_rich_presence::activity::StatusDisplayType;
-use discord_rich_presence::{activity, DiscordIpc, DiscordIpcClient};
+use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
 use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
-use url_escape;
 
 #[cfg(target_os = "linux")]
 use mpris::PlayerFinder;
 
+use chrono::Local;
+use log::{debug, error, info, trace, warn};
 use std::env;
 use std::fs;
-use std::ops::Sub;
 use std::path::PathBuf;
-use std::thread::sleep;
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 
+use source::Source;
+
+mod cmus;
+mod control;
+#[cfg(target_os = "linux")]
+mod dbus_service;
+mod events;
+mod file_output;
+mod filter;
+mod http;
+mod jellyfin;
+mod json_input;
+mod logger;
+mod metrics;
+mod migrate;
+mod moc;
+mod mpd;
+mod mpv;
+mod mqtt;
+mod notifications;
+mod now_playing;
+mod player_match;
+#[cfg(target_os = "linux")]
+mod players;
+mod presence;
+mod schedule;
 mod settings;
+mod source;
+mod subsonic;
+mod template;
+#[cfg(target_os = "linux")]
+mod tray;
 mod utils;
+mod validate;
+mod webhook;
 
 // Load api key from .env file durning compilation
 const LASTFM_API_KEY: &'static str = match option_env!("LASTFM_API_KEY") {
@@ -29,11 +60,35 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Err(_) => (false, PathBuf::from("/")),
     };
 
-    let settings = settings::load_settings();
+    // Messages are kept until the log settings are known
+    logger::init();
 
-    debug_log!(settings.debug_log, "Settings: {:#?}", settings);
-    debug_log!(settings.debug_log, "home_exists: {}", home_exists);
-    debug_log!(settings.debug_log, "home_dir: {}", home_dir.display());
+    let (mut settings, config_error) = settings::load_settings();
+
+    // Set cache path
+    let cache_dir = match env::var("XDG_CACHE_HOME") {
+        Ok(xgd_cache_home) => PathBuf::from(xgd_cache_home).join("music-discord-rpc"),
+        Err(_) => home_dir.join(".cache/music-discord-rpc"),
+    };
+
+    // Log file is saved in the cache directory
+    if settings.log_output.as_deref() == Some("file") {
+        let _ = fs::create_dir_all(&cache_dir);
+    }
+    let mut log_config = logger::LogConfig::new(&settings, &cache_dir);
+    // Keep the output of preview clean, so it can be piped to other tools
+    if matches!(
+        settings.suboptions.command,
+        Some(settings::Commands::Preview { .. })
+    ) && log_config.output == logger::Output::Stdout
+    {
+        log_config.output = logger::Output::Stderr;
+    }
+    logger::configure(log_config);
+
+    debug!("Settings: {:?}", settings);
+    debug!("home_exists: {}", home_exists);
+    debug!("home_dir: {}", home_dir.display());
 
     // Exec subcommands
     #[cfg(target_os = "linux")]
@@ -53,10 +108,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         Some(settings::Commands::Restart {}) => utils::restart_service(),
+        // Handled while loading settings
+        Some(_) => {}
         None => {}
     }
     #[cfg(target_os = "macos")]
     match settings.suboptions.command {
+        // Handled after loading the cache
+        Some(settings::Commands::Preview { .. }) => {}
         Some(_) => {
             println!("Subcommands to manage the daemon are not available on macOS.");
             println!(
@@ -68,55 +127,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // User settings
-
-    // Use api key provided by user
-    let lastfm_api_key = settings.lastfm_api_key.unwrap_or(LASTFM_API_KEY.into());
-    if lastfm_api_key.is_empty() {
-        println!("\x1b[31mWARNING: Last.fm API key is not set. Album covers from Last.fm will not be available.\x1b[0m");
-    }
-
-    // Main loop interval
-    let mut interval = settings.interval.unwrap_or(10);
-    if interval < 5 {
-        interval = 5
-    }
-    debug_log!(settings.debug_log, "interval: {}", interval);
-
-    // Nicknames for buttons
-    let lastfm_name = settings.lastfm_name.unwrap_or_default();
-    let listenbrainz_name = settings.listenbrainz_name.unwrap_or_default();
-
-    // "Listening to ..."
-    let rpc_name = settings.rpc_name.unwrap_or(String::from("artist"));
-
-    // Icon displayed next to the album cover
-    let small_image = settings.small_image.unwrap_or(String::from("playPause"));
-    let mut lastfm_avatar = String::new();
-    if small_image == "lastfmAvatar" && !lastfm_name.is_empty() {
-        lastfm_avatar = utils::get_lastfm_avatar(&lastfm_name, &lastfm_api_key);
-        debug_log!(settings.debug_log, "lastfm_avatar: {}", lastfm_avatar);
-    }
-    let lastfm_icon_text = if !lastfm_name.is_empty() {
-        lastfm_name.to_string() + " on Last.fm"
-    } else {
-        String::new()
-    };
-
-    // Force player id and name
-    let force_player_name = settings.force_player_name.unwrap_or_default();
-    let force_player_id = settings.force_player_id.unwrap_or_default();
-
-    // Enable/disable use of cache
-    let mut cache_enabled: bool = !settings.disable_cache;
-    if !home_exists {
-        cache_enabled = false;
-    }
-
-    // Allowlist of music players
-    let allowlist_enabled: bool = match settings.allowlist.len() {
-        0 => false,
-        _ => true,
-    };
+    let mut options = settings::Options::new(&settings, home_exists);
 
     // Vars for activity update detection
     let mut last_title: String = String::new();
@@ -128,6 +139,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut last_is_playing: bool = false;
 
     let mut _cover_url: String = "".to_string();
+    let mut cover_source: String = String::new();
     let mut is_first_time_audio: bool = true;
     let mut is_first_time_video: bool = true;
     let mut is_interrupted: bool = false;
@@ -139,27 +151,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut player_notif: u8 = 0;
     let mut discord_notif: bool = false;
 
-    let mut client_audio = DiscordIpcClient::new("1129859263741837373");
-    let mut client_video = DiscordIpcClient::new("1356756023813210293");
+    let mut client_audio = DiscordIpcClient::new(&options.client_id);
+    let mut client_video = DiscordIpcClient::new(&options.video_client_id);
     let mut client: &mut DiscordIpcClient = &mut client_audio;
 
-    // Set cache path
-    let cache_dir = match env::var("XDG_CACHE_HOME") {
-        Ok(xgd_cache_home) => PathBuf::from(xgd_cache_home).join("music-discord-rpc"),
-        Err(_) => home_dir.join(".cache/music-discord-rpc"),
-    };
-
-    if cache_enabled {
-        debug_log!(
-            settings.debug_log,
-            "Cache location: {}",
-            &cache_dir.display()
-        );
+    if options.cache_enabled {
+        debug!("Cache location: {}", &cache_dir.display());
         if let Err(err) = fs::create_dir_all(&cache_dir) {
-            println!("Could not create cache directory: {}", err);
+            error!("Could not create cache directory: {}", err);
         }
     }
 
+    // Desktop notifications
+    let mut notifier = notifications::Notifier::new(&settings.notifications, cache_dir.clone());
+    if let Some(err) = config_error {
+        notifier.send("config", "Invalid config file", &err);
+    }
+    let mut last_notified_track = String::new();
+
     // Cache file
     let db_path = cache_dir.join("album_cache.db");
     let mut album_cache = match PickleDb::load(
@@ -168,14 +177,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         SerializationMethod::Json,
     ) {
         Ok(db) => {
-            if cache_enabled {
-                println!("Cache loaded from file: {}", &db_path.display());
+            if options.cache_enabled {
+                info!("Cache loaded from file: {}", &db_path.display());
             }
             db
         }
         Err(_) => {
-            if cache_enabled {
-                println!("Generated new cache file: {}", &db_path.display());
+            if options.cache_enabled {
+                info!("Generated new cache file: {}", &db_path.display());
             }
             PickleDb::new(
                 &db_path,
@@ -185,225 +194,483 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    loop {
-        debug_log!(
-            settings.debug_log,
-            "───────────────────────────────Loop─1───────────────────────────────────"
+    // Print the activity for the current track and exit
+    if let Some(settings::Commands::Preview { json, from_json }) = &settings.suboptions.command {
+        presence::preview(
+            &settings,
+            &options,
+            &mut album_cache,
+            from_json.as_deref(),
+            *json,
         );
+    }
 
-        // On Linux try to connect to MPRIS
-        #[cfg(target_os = "linux")]
-        let player = match PlayerFinder::new() {
-            Ok(player) => {
-                dbus_notif = false;
-                player
-            }
-            Err(err) => {
-                if !dbus_notif {
-                    println!("Could not connect to D-Bus: {}", err);
-                    dbus_notif = true;
-                }
-                sleep(Duration::from_secs(interval));
-                continue;
-            }
-        };
+    // Handle SIGINT, SIGTERM and SIGHUP
+    let mut events = events::Events::new();
+    events::listen_signals(events.sender());
 
-        // List available players and exit
-        if settings.list_players {
-            #[cfg(target_os = "linux")]
-            match player.find_all() {
-                Ok(player_list) => {
-                    if player_list.is_empty() {
-                        println!("Could not find any player with MPRIS support.");
-                    } else {
-                        println!("");
-                        println!("────────────────────────────────────────────────────");
-                        println!("List of available music players with MPRIS support:");
-                        for music_player in &player_list {
-                            println!(" * {}", music_player.identity());
-                        }
-                        println!("");
-                        println!("Use the name to choose from which source the script should take data for the Discord status.");
-                        println!("Usage instructions:");
-                        println!("");
-                        println!(r#" music-discord-rpc -a "{}""#, player_list[0].identity());
-                        println!("");
-                        println!("You can use the -a argument multiple times to add more than one player to the allowlist:");
-                        println!("");
-                        println!(
-                            r#" music-discord-rpc -a "{}" -a "Second Player" -a "Any other player""#,
-                            player_list[0].identity()
-                        );
-                    }
-                }
-                Err(_) => {
-                    println!("Could not find any player with MPRIS support.");
-                }
-            };
+    // Apply changes made to the config file without restarting
+    if let Some(config_file) = settings::get_config_file() {
+        if config_file.exists() {
+            events::watch_config(events.sender(), config_file);
+        }
+    }
 
-            #[cfg(target_os = "macos")]
-            {
-                println!("");
-                println!("Displaying the list of players is not supported on macOS.");
-                println!(
-                    "However, it's possible to show the name of the currently detected player."
-                );
+    // Accept commands like pause-presence from other processes
+    let status = control::new_status();
+    let is_control_listening = if !settings.list_players && !settings.get_player_id {
+        control::listen(events.sender(), status.clone())
+    } else {
+        false
+    };
 
-                match utils::get_currently_playing() {
-                    Ok(player) => {
-                        println!("Player name: {}", player.player_id);
-                        println!("");
-                        println!(
-                            "You can use this name together with the -a flag to add this player to the allowlist:"
-                        );
-                        println!(r#" music-discord-rpc -a "{}""#, player.player_id);
-                        println!("");
-                        println!("You can use the -a argument multiple times to add more than one player to the allowlist:");
-                        println!(
-                            r#" music-discord-rpc -a "{}" -a "Second Player" -a "Any other player""#,
-                            player.player_id
-                        );
-                    }
-                    Err(_) => {
-                        println!("No player detected.");
-                    }
-                };
+    // Same commands and status on the session bus
+    #[cfg(target_os = "linux")]
+    if !settings.list_players && !settings.get_player_id {
+        dbus_service::start(events.sender(), status.clone());
+
+        if settings.tray {
+            tray::start(events.sender(), status.clone(), settings::get_config_file());
+        }
+    }
+
+    // Prometheus metrics and now playing info for stream overlays and other tools
+    if !settings.list_players && !settings.get_player_id {
+        if let Some(port) = settings.metrics_port {
+            metrics::start(port, status.clone());
+        }
+        if let Some(port) = settings.now_playing_port {
+            now_playing::start(port, status.clone());
+        }
+        if !settings.output_files.is_empty() || settings.output_cover.is_some() {
+            file_output::start(
+                &settings.output_files,
+                settings.output_cover.as_deref(),
+                status.clone(),
+            );
+        }
+        if let Some(url) = &settings.webhook_url {
+            webhook::start(
+                webhook::Webhook {
+                    url: url.clone(),
+                    method: settings.webhook_method.clone().unwrap_or_default(),
+                    headers: settings.webhook_headers.clone(),
+                    body: settings.webhook_body.clone(),
+                    debounce: Duration::from_secs(settings.webhook_debounce.unwrap_or(2)),
+                    retries: settings.webhook_retries.unwrap_or(3),
+                },
+                status.clone(),
+            );
+        }
+        if let Some(broker) = &settings.mqtt_broker {
+            mqtt::start(
+                mqtt::Mqtt {
+                    broker: broker.clone(),
+                    username: settings.mqtt_username.clone(),
+                    password: settings.mqtt_password.clone(),
+                    topic_prefix: settings.mqtt_topic_prefix.clone().unwrap_or_default(),
+                    home_assistant: settings.mqtt_home_assistant,
+                },
+                status.clone(),
+            );
+        }
+    }
+
+    // Player selected with the source option, MPRIS or media-control are used without it
+    let mut external_source = source::new(&settings, events.sender());
+
+    // All MPRIS players are tracked, so the presented one can change as soon as another one is preferred
+    #[cfg(target_os = "linux")]
+    let mut player_tracker = players::Tracker::new();
+    #[cfg(target_os = "linux")]
+    if external_source.is_none() && !settings.list_players && !settings.get_player_id {
+        players::watch(events.sender());
+    }
+
+    loop {
+        trace!("───────────────────────────────Loop─1───────────────────────────────────");
+
+        if events.shutdown {
+            break;
+        }
+
+        // Fetch album covers again, e.g. after a wrong cover was fixed on Last.fm
+        if events.clear_cover_cache {
+            events.clear_cover_cache = false;
+            for album_id in album_cache.get_all() {
+                let _ = album_cache.rem(&album_id);
             }
+            last_album_id.clear();
+            info!("[cache] cleared album covers.");
+        }
 
-            return Ok(());
+        // Send the activity again after refresh, pause or resume
+        if events.refresh {
+            events.refresh = false;
+            is_interrupted = true;
         }
 
-        // Find active player (and filter them by name if enabled)
-        #[cfg(target_os = "linux")]
-        let player_finder = if allowlist_enabled {
-            let mut allowlist_finder = Err(mpris::FindingError::NoPlayerFound);
-            for allowlist_entry in &settings.allowlist {
-                allowlist_finder = player.find_by_name(&allowlist_entry);
+        // Reload config file on SIGHUP or when the file changed
+        if events.reload {
+            events.reload = false;
+            match settings::reload_settings() {
+                Ok(new_settings) => {
+                    let new_options = settings::Options::new(&new_settings, home_exists);
+                    logger::configure(logger::LogConfig::new(&new_settings, &cache_dir));
+                    debug!("Settings: {:?}", new_settings);
+
+                    // Reconnect using new Discord applications if they changed
+                    if new_options.client_id != options.client_id
+                        || new_options.video_client_id != options.video_client_id
+                    {
+                        utils::clear_activity(&mut is_activity_set, client);
+                    }
+                    if new_options.client_id != options.client_id {
+                        if !is_first_time_audio {
+                            utils::close_client(&mut client_audio);
+                        }
+                        client_audio = DiscordIpcClient::new(&new_options.client_id);
+                        is_first_time_audio = true;
+                    }
+                    if new_options.video_client_id != options.video_client_id {
+                        if !is_first_time_video {
+                            utils::close_client(&mut client_video);
+                        }
+                        client_video = DiscordIpcClient::new(&new_options.video_client_id);
+                        is_first_time_video = true;
+                    }
+                    client = &mut client_audio;
 
-                if allowlist_finder.is_ok() {
-                    break;
+                    notifier.events = new_settings.notifications.clone();
+                    settings = new_settings;
+                    options = new_options;
+                    info!("Configuration reloaded.");
+                    is_interrupted = true;
+                }
+                Err(err) => {
+                    error!("{}", err);
+                    warn!("Config file rejected, keeping previous settings.");
+                    notifier.send(
+                        "config",
+                        "Config file rejected, keeping previous settings",
+                        &err,
+                    );
                 }
             }
-            allowlist_finder
-        } else {
-            player.find_active()
+        }
+
+        // Quiet hours, checked again as soon as any rule starts or ends
+        let now = Local::now();
+        let schedule_rule = schedule::active(&options.schedule, &now);
+        events.wake_after(schedule::next_change(&options.schedule, &now));
+        control::update(&status, |status| {
+            status.schedule = schedule_rule
+                .map(|rule| rule.rule().to_string())
+                .unwrap_or_default();
+        });
+        // Players allowed by the schedule replace the allowlist
+        let allowlist = match schedule_rule.and_then(|rule| rule.players()) {
+            Some(players) => players,
+            None => options.allowlist.as_slice(),
         };
 
-        // Connect with player
+        // Players with their own protocol, e.g. MPD, are used instead of MPRIS or media-control
+        #[cfg(target_os = "macos")]
+        let is_external_source = external_source.is_some();
+        #[cfg(target_os = "linux")]
+        let mut mpris_player;
         #[cfg(target_os = "linux")]
-        let player = match player_finder {
-            Ok(player) => {
-                if player_notif != 1 {
-                    println!("Found active player with MPRIS support.");
-                    player_notif = 1;
+        let mut mpris_finder = None;
+        #[cfg(target_os = "macos")]
+        let mut media_control;
+        // Metadata read while checking an external source, reused by the first update
+        let mut checked_media_info = None;
+        let player: &mut dyn Source = match external_source.as_deref_mut() {
+            Some(source) => {
+                // List available players and exit
+                if settings.list_players {
+                    println!();
+                    println!(
+                        "Listing players is only supported with MPRIS. Current source: {}",
+                        source.name()
+                    );
+                    return Ok(());
+                }
+
+                match source.get_media_info() {
+                    Ok(media_info) => {
+                        if player_notif != 1 {
+                            info!(player = source.name().as_str(); "Found active player: {}", source.name());
+                            notifier.send("player", "Player found", &source.name());
+                            player_notif = 1;
+                        }
+                        checked_media_info = Some(media_info);
+                        source
+                    }
+                    Err(err) => {
+                        if player_notif != 2 {
+                            info!("{} Waiting for {}...", err, source.name());
+
+                            if player_notif == 1 {
+                                notifier.send("player", "Player lost", "Waiting for any player...");
+                            }
+                            player_notif = 2;
+                            discord_notif = false;
+                        }
+
+                        is_interrupted = true;
+                        utils::clear_activity(&mut is_activity_set, client);
+                        control::clear_player(&status);
+                        events.sleep(options.interval);
+                        continue;
+                    }
                 }
-                player
             }
-            Err(_) => {
-                if player_notif != 2 {
-                    if allowlist_enabled {
-                        println!(
-                            "Could not find any active player from your allowlist with MPRIS support. Waiting for any player from your allowlist..."
-                        );
-                    } else {
-                        println!(
-                            "Could not find any player with MPRIS support. Waiting for any player..."
-                        );
+            #[cfg(target_os = "linux")]
+            None => {
+                // On Linux try to connect to MPRIS
+                #[cfg(target_os = "linux")]
+                let finder = match PlayerFinder::new() {
+                    Ok(finder) => {
+                        dbus_notif = false;
+                        finder
                     }
+                    Err(err) => {
+                        if !dbus_notif {
+                            error!("Could not connect to D-Bus: {}", err);
+                            dbus_notif = true;
+                        }
+                        events.sleep(options.interval);
+                        continue;
+                    }
+                };
 
-                    player_notif = 2;
-                    discord_notif = false;
+                // List available players and exit
+                if settings.list_players {
+                    match finder.find_all() {
+                        Ok(player_list) => {
+                            if player_list.is_empty() {
+                                println!("Could not find any player with MPRIS support.");
+                            } else {
+                                println!("");
+                                println!("────────────────────────────────────────────────────");
+                                println!("List of available music players with MPRIS support:");
+                                for music_player in &player_list {
+                                    println!(" * {}", music_player.identity());
+                                }
+                                println!("");
+                                println!("Use the name to choose from which source the script should take data for the Discord status.");
+                                println!("Usage instructions:");
+                                println!("");
+                                println!(
+                                    r#" music-discord-rpc -a "{}""#,
+                                    player_list[0].identity()
+                                );
+                                println!("");
+                                println!("You can use the -a argument multiple times to add more than one player to the allowlist:");
+                                println!("");
+                                println!(
+                                    r#" music-discord-rpc -a "{}" -a "Second Player" -a "Any other player""#,
+                                    player_list[0].identity()
+                                );
+                            }
+                        }
+                        Err(_) => {
+                            println!("Could not find any player with MPRIS support.");
+                        }
+                    };
+
+                    return Ok(());
                 }
 
-                is_interrupted = true;
-                utils::clear_activity(&mut is_activity_set, &mut client);
-                sleep(Duration::from_secs(interval));
-                continue;
-            }
-        };
+                // Find active player (and filter them by name if enabled), the player chosen in the tray menu goes first
+                let player_finder = player_tracker.select(
+                    &finder,
+                    settings.player_policy.as_deref().unwrap_or_default(),
+                    events.preferred_player.as_deref(),
+                    allowlist,
+                    &options.blocklist,
+                );
 
-        // On macOS use media info fetching function to determine if anything is playing now
-        #[cfg(target_os = "macos")]
-        let player = match utils::get_currently_playing() {
-            Ok(player) => {
-                if allowlist_enabled {
-                    let mut is_player_on_allowlist = false;
-                    for allowlist_entry in &settings.allowlist {
-                        if *allowlist_entry == player.player_id {
-                            is_player_on_allowlist = true;
-                            break;
+                // Connect with player
+                let player = match player_finder {
+                    Ok(player) => {
+                        if player_notif != 1 {
+                            info!(player = player.identity(); "Found active player with MPRIS support.");
+                            notifier.send("player", "Player found", player.identity());
+                            player_notif = 1;
                         }
+                        player
                     }
-                    if !is_player_on_allowlist {
+                    Err(_) => {
                         if player_notif != 2 {
-                            println!(
-                            	"Could not find any active player from your allowlist. Waiting for any player from your allowlist..."
-                            );
+                            if options.allowlist_enabled {
+                                info!(
+                                    "Could not find any active player from your allowlist with MPRIS support. Waiting for any player from your allowlist..."
+                                );
+                            } else {
+                                info!(
+                                    "Could not find any player with MPRIS support. Waiting for any player..."
+                                );
+                            }
+
+                            if player_notif == 1 {
+                                notifier.send("player", "Player lost", "Waiting for any player...");
+                            }
                             player_notif = 2;
                             discord_notif = false;
                         }
 
                         is_interrupted = true;
                         utils::clear_activity(&mut is_activity_set, &mut client);
-                        sleep(Duration::from_secs(interval));
+                        control::clear_player(&status);
+                        events.sleep(options.interval);
                         continue;
                     }
-                }
+                };
 
-                if player_notif != 1 {
-                    println!("Found active player using media-control.");
-                    player_notif = 1;
-                }
-                player
+                mpris_player = source::Mpris(player);
+                mpris_finder = Some(finder);
+                &mut mpris_player
             }
-            Err(e) => {
-                if player_notif != 2 {
-                    println!("{}", e);
+            #[cfg(target_os = "macos")]
+            None => {
+                // List available players and exit
+                if settings.list_players {
+                    println!("");
+                    println!("Displaying the list of players is not supported on macOS.");
+                    println!(
+                        "However, it's possible to show the name of the currently detected player."
+                    );
 
-                    player_notif = 2;
-                    discord_notif = false;
+                    match utils::get_currently_playing() {
+                        Ok(player) => {
+                            println!("Player name: {}", player.player_id);
+                            println!("");
+                            println!(
+                                "You can use this name together with the -a flag to add this player to the allowlist:"
+                            );
+                            println!(r#" music-discord-rpc -a "{}""#, player.player_id);
+                            println!("");
+                            println!("You can use the -a argument multiple times to add more than one player to the allowlist:");
+                            println!(
+                                r#" music-discord-rpc -a "{}" -a "Second Player" -a "Any other player""#,
+                                player.player_id
+                            );
+                        }
+                        Err(_) => {
+                            println!("No player detected.");
+                        }
+                    };
+
+                    return Ok(());
                 }
 
-                is_interrupted = true;
-                utils::clear_activity(&mut is_activity_set, &mut client);
-                sleep(Duration::from_secs(interval));
-                continue;
+                // On macOS use media info fetching function to determine if anything is playing now
+                let player = match utils::get_currently_playing() {
+                    Ok(player) => {
+                        let is_listed = |patterns: &[player_match::PlayerPattern]| {
+                            patterns
+                                .iter()
+                                .any(|pattern| pattern.matches(&player.player_id, "", ""))
+                        };
+                        if (!allowlist.is_empty() && !is_listed(allowlist))
+                            || is_listed(&options.blocklist)
+                        {
+                            if player_notif != 2 {
+                                info!(
+                                	"Could not find any active player from your allowlist. Waiting for any player from your allowlist..."
+                                );
+                                if player_notif == 1 {
+                                    notifier.send(
+                                        "player",
+                                        "Player lost",
+                                        "Waiting for any player...",
+                                    );
+                                }
+                                player_notif = 2;
+                                discord_notif = false;
+                            }
+
+                            is_interrupted = true;
+                            utils::clear_activity(&mut is_activity_set, &mut client);
+                            events.sleep(options.interval);
+                            continue;
+                        }
+
+                        if player_notif != 1 {
+                            info!(player = player.player_id.as_str(); "Found active player using media-control.");
+                            notifier.send("player", "Player found", &player.player_id);
+                            player_notif = 1;
+                        }
+                        player
+                    }
+                    Err(e) => {
+                        if player_notif != 2 {
+                            info!("{}", e);
+
+                            if player_notif == 1 {
+                                notifier.send("player", "Player lost", "Waiting for any player...");
+                            }
+                            player_notif = 2;
+                            discord_notif = false;
+                        }
+
+                        is_interrupted = true;
+                        utils::clear_activity(&mut is_activity_set, &mut client);
+                        control::clear_player(&status);
+                        events.sleep(options.interval);
+                        continue;
+                    }
+                };
+
+                media_control = source::MediaControl {
+                    player_id: player.player_id,
+                };
+                &mut media_control
             }
         };
 
-        #[cfg(target_os = "linux")]
-        let mut player_name = player.identity().to_string();
-        #[cfg(target_os = "macos")]
-        let mut player_name = player.player_id.clone();
+        // Quiet hours that only allow some players, also checks external sources and macOS
+        if schedule_rule
+            .and_then(|rule| rule.players())
+            .is_some_and(|players| !player.matches(players))
+        {
+            debug!(
+                "{} is not allowed by the schedule, skipping...",
+                player.name()
+            );
+            is_interrupted = true;
+            utils::clear_activity(&mut is_activity_set, client);
+            control::clear_player(&status);
+            events.sleep(options.interval);
+            continue;
+        }
 
-        // Use video presence if player is in video_players list
-        let is_video_player = settings
-            .video_players
-            .iter()
-            .any(|video_player_name| video_player_name == &player_name);
+        let mut player_name = player.name();
+
+        // Use video presence if player is in video_players list or the source knows it is a video
+        let is_video_source = player.is_video();
+        let is_video_player = is_video_source || player.matches(&options.video_players);
         if is_video_player {
             client = &mut client_video;
-            debug_log!(settings.debug_log, "Using video player presence");
+            debug!("Using video player presence");
         } else {
             client = &mut client_audio;
-            debug_log!(settings.debug_log, "Using audio player presence");
+            debug!("Using audio player presence");
         }
 
         #[cfg(target_os = "macos")]
-        {
+        if !is_external_source {
             player_name = utils::app_name_from_bundle_id(player_name.as_str());
         }
 
         let mut player_id = utils::sanitize_name(&player_name);
 
-        debug_log!(settings.debug_log, "player_name: {}", player_name);
-        debug_log!(settings.debug_log, "player_id: {}", player_id);
-        debug_log!(
-            settings.debug_log,
-            "force_player_name: {}",
-            force_player_name
-        );
-        debug_log!(settings.debug_log, "force_player_id: {}", force_player_id);
+        debug!("player_name: {}", player_name);
+        debug!("player_id: {}", player_id);
+        debug!("force_player_name: {}", options.force_player_name);
+        debug!("force_player_id: {}", options.force_player_id);
 
         // Display player ID and exit
         if settings.get_player_id {
@@ -411,30 +678,46 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             return Ok(());
         }
 
-        #[cfg(target_os = "macos")]
-        let last_player_id = player.player_id.clone();
-
         // Set different name and ID for RPC if enabled by argument
-        if !force_player_name.is_empty() {
-            player_name = force_player_name.to_string();
+        if !options.force_player_name.is_empty() {
+            player_name = options.force_player_name.to_string();
         }
-        if !force_player_id.is_empty() {
-            player_id = force_player_id.to_string();
+        if !options.force_player_id.is_empty() {
+            player_id = options.force_player_id.to_string();
         }
 
+        let player_info = presence::PlayerInfo {
+            name: player_name.clone(),
+            id: player_id.clone(),
+            is_video: is_video_player,
+        };
+
+        control::update(&status, |status| {
+            status.player = player_name.clone();
+            status.player_id = player_id.clone();
+        });
+
         // Connect with Discord
         if (is_first_time_audio && !is_video_player) || (is_first_time_video && is_video_player) {
             match client.connect() {
                 Ok(_) => {
-                    println!("Connected to Discord.");
+                    info!("Connected to Discord.");
+                    notifier.send("discord", "Connected to Discord", "");
+                    control::update(&status, |status| status.discord_connected = true);
                     discord_notif = false;
                 }
                 Err(_) => {
+                    control::update(&status, |status| status.discord_connected = false);
                     if !discord_notif {
-                        println!("Could not connect to Discord. Waiting for discord to start...");
+                        warn!("Could not connect to Discord. Waiting for discord to start...");
+                        notifier.send(
+                            "discord",
+                            "Could not connect to Discord",
+                            "Waiting for Discord to start...",
+                        );
                         discord_notif = true;
                     }
-                    sleep(Duration::from_secs(interval));
+                    events.sleep(options.interval);
                     continue;
                 }
             };
@@ -447,64 +730,118 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             match client.reconnect() {
                 Ok(_) => {
                     if discord_notif {
-                        println!("Reconnected to Discord.");
+                        info!("Reconnected to Discord.");
+                        metrics::discord_reconnect();
+                        notifier.send("discord", "Reconnected to Discord", "");
                     }
+                    control::update(&status, |status| status.discord_connected = true);
                     is_interrupted = true;
                     discord_notif = false;
                 }
                 Err(_) => {
+                    control::update(&status, |status| status.discord_connected = false);
                     if !discord_notif {
-                        println!("Could not reconnect to Discord. Waiting for discord to start...");
+                        warn!("Could not reconnect to Discord. Waiting for discord to start...");
+                        notifier.send(
+                            "discord",
+                            "Disconnected from Discord",
+                            "Waiting for Discord to start...",
+                        );
                         discord_notif = true;
                     }
-                    sleep(Duration::from_secs(interval));
+                    events.sleep(options.interval);
                     continue;
                 }
             };
         }
 
         loop {
-            debug_log!(
-                settings.debug_log,
-                "───────────────────────────────Loop─2───────────────────────────────────"
-            );
+            trace!("───────────────────────────────Loop─2───────────────────────────────────");
 
-            // Get metadata from player
+            // Go back to the outer loop to shutdown, reload settings or refresh
+            if events.interrupted() {
+                break;
+            }
+            // Only fresh on the first pass
+            let checked_media_info = checked_media_info.take();
+
+            // Activity hidden with pause-presence, stay connected and wait for resume
+            if events.paused {
+                debug!("Presence paused, skipping...");
+                is_interrupted = true;
+                utils::clear_activity(&mut is_activity_set, client);
+                events.sleep(options.interval);
+                continue;
+            }
+
+            // Presence turned off by the schedule, wait for its end like when paused
+            if schedule_rule.is_some_and(|rule| rule.is_off()) {
+                debug!("Presence turned off by the schedule, skipping...");
+                is_interrupted = true;
+                utils::clear_activity(&mut is_activity_set, client);
+                events.sleep(options.interval);
+                continue;
+            }
+
+            // Another MPRIS player is preferred now, e.g. it started playing
             #[cfg(target_os = "linux")]
-            let media_info = match utils::get_currently_playing(&player, settings.debug_log) {
-                Ok(metadata) => metadata,
-                Err(err) => {
-                    println!("Could not get metadata from player: {}", err);
-                    utils::clear_activity(&mut is_activity_set, &mut client);
+            if let Some(finder) = &mpris_finder {
+                if player_tracker.changed(
+                    finder,
+                    settings.player_policy.as_deref().unwrap_or_default(),
+                    events.preferred_player.as_deref(),
+                    allowlist,
+                    &options.blocklist,
+                ) {
+                    events.refresh = true;
                     break;
                 }
-            };
-            #[cfg(target_os = "macos")]
-            let media_info = match utils::get_currently_playing() {
+            }
+
+            // Get metadata from player
+            let media_info = match checked_media_info.map_or_else(|| player.get_media_info(), Ok) {
                 Ok(metadata) => metadata,
                 Err(err) => {
-                    println!("Could not get metadata from player: {}", err);
+                    warn!("Could not get metadata from player: {}", err);
                     utils::clear_activity(&mut is_activity_set, &mut client);
                     break;
                 }
             };
-            debug_log!(settings.debug_log, "{:#?}", media_info);
+            trace!("{:?}", media_info);
 
-            // Fix allowlist on macos, if player ID changes then break loop
-            #[cfg(target_os = "macos")]
-            if media_info.player_id != last_player_id {
-                debug_log!(settings.debug_log, "Detected player change.");
+            // Switched between audio and video files, use the other activity
+            if player.is_video() != is_video_source {
+                debug!("Detected switch between audio and video.");
                 utils::clear_activity(&mut is_activity_set, client);
+                events.refresh = true;
                 break;
             }
 
             if settings.only_when_playing && !media_info.is_playing {
                 is_interrupted = true;
                 utils::clear_activity(&mut is_activity_set, client);
-                sleep(Duration::from_secs(interval));
+                events.sleep(options.interval);
                 continue;
             }
 
+            // Content filters can hide the track or parts of it
+            let (media_info, is_album_hidden) = match filter::apply(
+                &options.filters,
+                media_info,
+                &player_name,
+                &options.filter_placeholder,
+            ) {
+                Some(filtered) => filtered,
+                None => {
+                    debug!("Track hidden by a filter.");
+                    is_interrupted = true;
+                    utils::clear_activity(&mut is_activity_set, client);
+                    control::clear_track(&status);
+                    events.sleep(options.interval);
+                    continue;
+                }
+            };
+
             let album_id = format!("{} - {}", media_info.album_artist, media_info.album);
 
             // If all metadata values are unknown then break
@@ -512,33 +849,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 && (media_info.album.to_lowercase() == "unknown album")
                 && (media_info.title.to_lowercase() == "unknown title")
             {
-                debug_log!(settings.debug_log, "Unknown metadata, skipping...");
-                sleep(Duration::from_secs(interval));
+                debug!("Unknown metadata, skipping...");
+                events.sleep(options.interval);
                 break;
             }
 
             // If artist or track is empty then break
             if (media_info.artist.len() == 0) | (media_info.title.len() == 0) {
-                debug_log!(settings.debug_log, "Unknown metadata, skipping...");
-                sleep(Duration::from_secs(interval));
+                debug!("Unknown metadata, skipping...");
+                events.sleep(options.interval);
                 break;
             }
 
             let mut metadata_changed: bool = false;
-            debug_log!(settings.debug_log, "Checking if metadata changed:");
-            debug_log!(settings.debug_log, "{} - {last_title}", media_info.title);
-            debug_log!(settings.debug_log, "{} - {last_album}", media_info.album);
-            debug_log!(settings.debug_log, "{} - {last_artist}", media_info.artist);
-            debug_log!(
-                settings.debug_log,
-                "{} - {last_album_artist}",
-                media_info.album_artist
-            );
-            debug_log!(
-                settings.debug_log,
+            debug!("Checking if metadata changed:");
+            debug!("{} - {last_title}", media_info.title);
+            debug!("{} - {last_album}", media_info.album);
+            debug!("{} - {last_artist}", media_info.artist);
+            debug!("{} - {last_album_artist}", media_info.album_artist);
+            debug!(
                 "is_playing: {} - {}",
-                media_info.is_playing,
-                last_is_playing
+                media_info.is_playing, last_is_playing
             );
             if (media_info.title != last_title)
                 | (media_info.album != last_album)
@@ -549,318 +880,108 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 metadata_changed = true;
             }
 
-            debug_log!(
-                settings.debug_log,
+            debug!(
                 "track_position: {} - {}",
-                media_info.position,
-                last_track_position
+                media_info.position, last_track_position
             );
 
             // Check if song repeated
             if (media_info.position < last_track_position) && !metadata_changed {
-                debug_log!(settings.debug_log, "Detected a potential song seek/replay");
+                debug!("Detected a potential song seek/replay");
                 metadata_changed = true;
             }
             last_track_position = media_info.position; // update it before loop continue
-            debug_log!(settings.debug_log, "metadata_changed: {}", metadata_changed);
+            debug!("metadata_changed: {}", metadata_changed);
 
             if !metadata_changed && !is_interrupted {
-                debug_log!(
-                    settings.debug_log,
-                    "The same metadata and status, skipping..."
-                );
+                debug!("The same metadata and status, skipping...");
 
-                sleep(Duration::from_secs(interval));
+                events.sleep(options.interval);
                 continue;
             }
 
-            // Get unix time of track start if supported, else return time now
-            let time_start: u64 = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                Ok(n) => n.as_secs().sub(media_info.position),
-                Err(_) => 0,
-            };
-
-            // Fetch album cover
-            if album_id != last_album_id {
-                if lastfm_api_key.is_empty() {
-                    _cover_url = "missing-cover".to_string()
-                } else {
-                    _cover_url = utils::get_cover_url(
-                        &album_id,
-                        media_info.album.as_str(),
-                        _cover_url,
-                        cache_enabled,
-                        &mut album_cache,
-                        media_info.album_artist.as_str(),
-                        &lastfm_api_key,
-                    );
-
-                    // Fallback for Apple Music for album names with " - EP" and " - Single"
-                    if _cover_url.is_empty() || _cover_url == "missing-cover" {
-                        let album_name = media_info.album.trim();
-                        let album_name_without_suffix = if album_name.ends_with(" - EP") {
-                            &album_name[..album_name.len() - 5]
-                        } else if album_name.ends_with(" - Single") {
-                            &album_name[..album_name.len() - 9]
-                        } else {
-                            ""
-                        };
-
-                        if !album_name_without_suffix.is_empty() {
-                            debug_log!(
-                            settings.debug_log,
-                            "Album cover not found, attempting to use album name without the 'EP' or 'Single' suffix (Apple Music)."
-                            );
-                            debug_log!(
-                                settings.debug_log,
-                                "{} => {}",
-                                album_name,
-                                album_name_without_suffix
-                            );
-
-                            _cover_url = utils::get_cover_url(
-                                &album_id,
-                                album_name_without_suffix,
-                                _cover_url,
-                                cache_enabled,
-                                &mut album_cache,
-                                media_info.album_artist.as_str(),
-                                &lastfm_api_key,
-                            );
-                        }
-                    }
-                }
-
-                // Use Musicbrainz cover if Last.fm fails
-                if !settings.disable_musicbrainz_cover {
-                    if _cover_url.is_empty() || _cover_url == "missing-cover" {
-                        _cover_url = utils::get_cover_url_musicbrainz(
-                            &album_id,
-                            media_info.album.as_str(),
-                            _cover_url,
-                            cache_enabled,
-                            &mut album_cache,
-                            media_info.album_artist.as_str(),
-                        );
-                    }
-                }
+            // Fetch album cover, providers are tried in the order set by the user.
+            // Hidden albums have no name to look up, any cover would be wrong.
+            if is_album_hidden {
+                _cover_url = String::from("missing-cover");
+                cover_source = String::new();
+            } else if album_id != last_album_id {
+                (_cover_url, cover_source) =
+                    presence::fetch_cover(&media_info, &album_id, &options, &mut album_cache);
             }
+            let (image, image_source) =
+                presence::select_image(&_cover_url, &cover_source, &media_info, &settings);
 
-            let image: String = if _cover_url.is_empty() || _cover_url == "missing-cover" {
-                match media_info.art_url.is_empty() {
-                    true => "missing-cover".to_string(),
-                    false => {
-                        if media_info.art_url.starts_with("http") && !settings.disable_mpris_art_url
-                        {
-                            media_info.art_url
-                        } else {
-                            "missing-cover".to_string()
-                        }
-                    }
-                }
+            // Set activity
+            let payload = presence::build(
+                &media_info,
+                &image,
+                &player_info,
+                presence::get_time_start(&media_info),
+                &settings,
+                &options,
+            );
+            let song_name: String = format!("{} - {}", media_info.artist, media_info.title);
+            let status_text = if media_info.is_playing {
+                "playing"
             } else {
-                _cover_url.clone()
+                "paused"
             };
 
             // Save last refresh info
-            last_title = media_info.title.clone();
-            last_album = media_info.album.clone();
-            last_artist = media_info.artist.clone();
+            last_title = media_info.title;
+            last_album = media_info.album;
+            last_artist = media_info.artist;
             last_album_artist = media_info.album_artist;
             last_album_id = album_id.to_string();
             last_is_playing = media_info.is_playing;
 
-            // Set activity
-            let song_name: String = format!("{} - {}", media_info.artist, media_info.title);
-            let title = if media_info.title.len() > 1 {
-                media_info.title
-            } else {
-                format!("{} ", media_info.title) // Discord activity min 2 char len bug fix
-            };
-            let artist = match rpc_name.as_str() {
-                "artist" => {
-                    if media_info.artist.len() > 1 {
-                        media_info.artist
-                    } else {
-                        format!("{} ", media_info.artist) // Discord activity min 2 char len bug fix
-                    }
-                }
-                _ => format!("by: {}", media_info.artist),
-            };
-            let album = format!("album: {}", media_info.album);
-            let status_text: String = if media_info.is_playing {
-                "playing".to_string()
-            } else {
-                "paused".to_string()
-            };
-
-            let mut assets = activity::Assets::new().large_image(&image);
-
-            if !settings.hide_album_name {
-                assets = assets.large_text(&album);
-            }
-
-            // Icon displayed next to the album cover
-            match small_image.as_str() {
-                "player" => {
-                    if !settings.disable_mpris_art_url && image.contains("ytimg.com/") {
-                        assets = assets.small_image("youtube").small_text("YouTube")
-                    } else {
-                        assets = assets.small_image(&player_id).small_text(&player_name)
-                    }
-                }
-                "lastfmAvatar" => {
-                    if !lastfm_avatar.is_empty() {
-                        assets = assets
-                            .small_image(&lastfm_avatar)
-                            .small_text(&lastfm_icon_text);
-                    }
-                }
-                "none" => {}
-                _ => assets = assets.small_image(&status_text).small_text(&status_text),
-            }
-
-            // Display paused icon anyway if playpack is paused or stopped
-            if status_text != "playing" {
-                assets = assets.small_image(&status_text).small_text(&status_text)
-            }
-
-            let mut payload = activity::Activity::new()
-                .details(&title)
-                .assets(assets)
-                .activity_type(if is_video_player {
-                    activity::ActivityType::Watching
-                } else {
-                    activity::ActivityType::Listening
-                });
-
-            // "Listening to ..."
-            match rpc_name.as_str() {
-                "none" => payload = payload.status_display_type(StatusDisplayType::Name),
-                "track" => payload = payload.status_display_type(StatusDisplayType::Details),
-                "artist" | _ => payload = payload.status_display_type(StatusDisplayType::State),
-            }
-
-            // Don't display Unknown Artist for videos
-            if !(is_video_player && (artist.to_lowercase() == "by: unknown artist")
-                || artist.to_lowercase() == "unknown artist")
-            {
-                payload = payload.state(&artist);
-            }
-
-            payload = if media_info.is_track_position && (media_info.duration > 0) {
-                let time_end = time_start + media_info.duration;
-                if media_info.is_playing {
-                    payload.timestamps(
-                        activity::Timestamps::new()
-                            .start(time_start.try_into().unwrap())
-                            .end(time_end.try_into().unwrap()),
-                    )
-                } else {
-                    payload.timestamps(
-                        activity::Timestamps::new().start(time_start.try_into().unwrap()),
-                    )
-                }
-            } else {
-                payload.timestamps(activity::Timestamps::new().end(time_start.try_into().unwrap()))
-            };
-
-            // Create urls for activity links
-            let yt_url: String = format!(
-                "https://www.youtube.com/results?search_query={}",
-                url_escape::encode_component(&song_name)
-            );
-            let lastfm_url: String = format!(
-                "https://www.last.fm/user/{}",
-                url_escape::encode_component(&lastfm_name)
-            );
-            let listenbrainz_url: String = format!(
-                "https://listenbrainz.org/user/{}/",
-                url_escape::encode_component(&listenbrainz_name)
-            );
-
-            // Add YouTube URL to song title
-            payload = payload.details_url(&yt_url);
-
-            // Add activity buttons
-            let mut buttons = Vec::new();
-            let mut first_button = "";
-            for button in &settings.button {
-                let initial_len = buttons.len();
-                if initial_len == 2 {
-                    break;
-                }
-
-                // Make sure buttons wont repeat
-                if initial_len > 0 {
-                    if first_button == button {
-                        continue;
-                    }
-                }
-
-                match button.as_str() {
-                    "yt" => {
-                        buttons.push(activity::Button::new(
-                            "Search this song on YouTube",
-                            &yt_url,
-                        ));
-                    }
-                    "lastfm" => {
-                        if lastfm_name.len() > 0 {
-                            buttons.push(activity::Button::new("Last.fm profile", &lastfm_url));
-                        }
-                    }
-                    "listenbrainz" => {
-                        if listenbrainz_name.len() > 0 {
-                            buttons.push(activity::Button::new(
-                                "Listenbrainz profile",
-                                &listenbrainz_url,
-                            ));
-                        }
-                    }
-                    "mprisUrl" => {
-                        if media_info.url.is_empty() {
-                            // if mpris url is empty or not set convert button to yt button
-                            buttons.push(activity::Button::new(
-                                "Search this song on YouTube",
-                                &yt_url,
-                            ));
-                        } else {
-                            if is_video_player {
-                                buttons.push(activity::Button::new("Watch Now", &media_info.url));
-                            } else {
-                                buttons.push(activity::Button::new("Play Now", &media_info.url));
-                            }
-                        }
-                    }
-                    "shamelessAd" => {
-                        buttons.push(activity::Button::new(
-                            "Get This RPC",
-                            "https://github.com/patryk-ku/music-discord-rpc",
-                        ));
-                    }
-                    _ => continue,
-                }
-
-                // Make sure buttons wont repeat
-                if initial_len < buttons.len() {
-                    first_button = button;
-                }
-            }
-
-            payload = match buttons.is_empty() {
-                false => payload.buttons(buttons),
-                true => payload,
-            };
-
-            match client.set_activity(payload) {
+            match client.set_activity(payload.to_activity()) {
                 Ok(_) => {
                     is_interrupted = false;
                     is_activity_set = true;
-                    println!("=> Set activity [{status_text}]: {song_name}");
+                    metrics::presence_update(true);
+                    info!(
+                        track = song_name.as_str(),
+                        player = player_name.as_str(),
+                        provider = image_source.as_str();
+                        "=> Set activity [{status_text}]: {song_name}"
+                    );
+                    control::update(&status, |status| {
+                        status.title = last_title.clone();
+                        status.artist = last_artist.clone();
+                        status.album = last_album.clone();
+                        status.is_playing = last_is_playing;
+                        status.duration = media_info.duration;
+                        status.position = match media_info.is_track_position {
+                            true => media_info.position,
+                            false => 0,
+                        };
+                        status.position_updated = utils::unix_time();
+                        status.cover_url = image.clone();
+                        status.cover_source = image_source.clone();
+                        status.path = media_info.path.clone();
+                    });
+
+                    // Only new tracks, not pausing or seeking
+                    if song_name != last_notified_track {
+                        notifier.send_track(
+                            &last_title,
+                            &format!("{}\n{}", last_artist, last_album),
+                            &image,
+                        );
+                        last_notified_track = song_name.clone();
+                    }
                 }
                 Err(_) => {
-                    println!("Could not set activity.");
+                    error!("Could not set activity.");
+                    metrics::presence_update(false);
+                    notifier.send(
+                        "discord",
+                        "Disconnected from Discord",
+                        "Could not set activity.",
+                    );
+                    control::update(&status, |status| status.discord_connected = false);
                     is_interrupted = true;
                     is_activity_set = false;
                     client.close()?;
@@ -868,9 +989,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             };
 
-            sleep(Duration::from_secs(interval));
+            events.sleep(options.interval);
         }
 
-        sleep(Duration::from_secs(interval));
+        events.sleep(options.interval);
+    }
+
+    // Clean up before exit
+    info!("Shutting down...");
+    if !is_first_time_audio {
+        utils::close_client(&mut client_audio);
+    }
+    if !is_first_time_video {
+        utils::close_client(&mut client_video);
+    }
+    if options.cache_enabled && album_cache.dump().is_err() {
+        error!("[cache] error, unable to write to cache file.");
     }
+    if is_control_listening {
+        control::remove_socket();
+    }
+
+    Ok(())
 }
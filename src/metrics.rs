@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::control::SharedStatus;
+use crate::http::{self, Request};
+
+// Counters exposed in the Prometheus text format
+struct Metrics {
+    presence_updates: BTreeMap<&'static str, u64>,
+    discord_reconnects: u64,
+    cover_lookups: BTreeMap<(String, &'static str), u64>,
+    cache_hits: u64,
+    cache_misses: u64,
+    // Sum of seconds and number of requests
+    http_requests: BTreeMap<String, (f64, u64)>,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    presence_updates: BTreeMap::new(),
+    discord_reconnects: 0,
+    cover_lookups: BTreeMap::new(),
+    cache_hits: 0,
+    cache_misses: 0,
+    http_requests: BTreeMap::new(),
+});
+
+fn update<F: FnOnce(&mut Metrics)>(f: F) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        f(&mut metrics);
+    }
+}
+
+pub fn presence_update(sent: bool) {
+    let result = if sent { "sent" } else { "failed" };
+    update(|metrics| *metrics.presence_updates.entry(result).or_insert(0) += 1);
+}
+
+pub fn discord_reconnect() {
+    update(|metrics| metrics.discord_reconnects += 1);
+}
+
+pub fn cover_lookup(provider: &str, found: bool) {
+    let result = if found { "found" } else { "not_found" };
+    update(|metrics| {
+        *metrics
+            .cover_lookups
+            .entry((provider.to_string(), result))
+            .or_insert(0) += 1
+    });
+}
+
+pub fn cache_lookup(hit: bool) {
+    update(|metrics| match hit {
+        true => metrics.cache_hits += 1,
+        false => metrics.cache_misses += 1,
+    });
+}
+
+pub fn http_request(provider: &str, duration: Duration) {
+    update(|metrics| {
+        let (seconds, count) = metrics
+            .http_requests
+            .entry(provider.to_string())
+            .or_insert((0.0, 0));
+        *seconds += duration.as_secs_f64();
+        *count += 1;
+    });
+}
+
+// Serve metrics on http://127.0.0.1:<port>/metrics
+pub fn start(port: u16, status: SharedStatus) {
+    let address = format!("127.0.0.1:{}", port);
+    http::serve("metrics", &address, move |request, stream| {
+        handle_request(request, stream, &status)
+    });
+}
+
+fn handle_request(request: Request, stream: TcpStream, status: &SharedStatus) {
+    if request.method != "GET" {
+        return http::method_not_allowed(stream);
+    }
+    match request.path.as_str() {
+        "/metrics" => http::respond(
+            stream,
+            "200 OK",
+            "text/plain; version=0.0.4",
+            render(status).as_bytes(),
+        ),
+        _ => http::not_found(stream),
+    }
+}
+
+fn render(status: &SharedStatus) -> String {
+    let status = match status.lock() {
+        Ok(status) => status.clone(),
+        Err(_) => Default::default(),
+    };
+    let metrics = match METRICS.lock() {
+        Ok(metrics) => metrics,
+        Err(_) => return String::new(),
+    };
+
+    let mut output = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, values: Vec<(String, String)>| {
+        let _ = writeln!(output, "# HELP music_discord_rpc_{} {}", name, help);
+        let _ = writeln!(output, "# TYPE music_discord_rpc_{} {}", name, kind);
+        for (labels, value) in values {
+            let _ = writeln!(output, "music_discord_rpc_{}{} {}", name, labels, value);
+        }
+    };
+
+    metric(
+        "presence_updates_total",
+        "counter",
+        "Activity updates sent to Discord.",
+        ["sent", "failed"]
+            .iter()
+            .map(|result| {
+                let count = metrics.presence_updates.get(result).unwrap_or(&0);
+                (format!("{{result=\"{}\"}}", result), count.to_string())
+            })
+            .collect(),
+    );
+    metric(
+        "discord_reconnects_total",
+        "counter",
+        "Reconnections to Discord after the connection was lost.",
+        vec![(String::new(), metrics.discord_reconnects.to_string())],
+    );
+    metric(
+        "cover_lookups_total",
+        "counter",
+        "Album cover lookups by the provider that found the cover and result.",
+        metrics
+            .cover_lookups
+            .iter()
+            .map(|((provider, result), count)| {
+                (
+                    format!("{{provider=\"{}\",result=\"{}\"}}", provider, result),
+                    count.to_string(),
+                )
+            })
+            .collect(),
+    );
+    metric(
+        "cache_hits_total",
+        "counter",
+        "Album covers found in the cache.",
+        vec![(String::new(), metrics.cache_hits.to_string())],
+    );
+    metric(
+        "cache_misses_total",
+        "counter",
+        "Album covers not found in the cache.",
+        vec![(String::new(), metrics.cache_misses.to_string())],
+    );
+    metric(
+        "http_request_duration_seconds",
+        "summary",
+        "Duration of HTTP requests to cover providers.",
+        metrics
+            .http_requests
+            .iter()
+            .flat_map(|(provider, (seconds, count))| {
+                [
+                    (
+                        format!("_sum{{provider=\"{}\"}}", provider),
+                        seconds.to_string(),
+                    ),
+                    (
+                        format!("_count{{provider=\"{}\"}}", provider),
+                        count.to_string(),
+                    ),
+                ]
+            })
+            .collect(),
+    );
+    metric(
+        "discord_connected",
+        "gauge",
+        "Whether the app is connected to Discord.",
+        vec![(String::new(), (status.discord_connected as u8).to_string())],
+    );
+    metric(
+        "presence_paused",
+        "gauge",
+        "Whether the activity is hidden with pause-presence.",
+        vec![(String::new(), (status.presence_paused as u8).to_string())],
+    );
+    metric(
+        "player_info",
+        "gauge",
+        "Player the activity is taken from.",
+        match status.player.is_empty() {
+            true => Vec::new(),
+            false => vec![(
+                format!(
+                    "{{player=\"{}\",player_id=\"{}\"}}",
+                    escape(&status.player),
+                    escape(&status.player_id)
+                ),
+                String::from("1"),
+            )],
+        },
+    );
+    metric(
+        "playing",
+        "gauge",
+        "Whether the current track is playing.",
+        vec![(String::new(), (status.is_playing as u8).to_string())],
+    );
+    output
+}
+
+// Label values can contain any text, e.g. player names
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control;
+
+    #[test]
+    fn cover_lookups() {
+        cover_lookup("lastfm", true);
+        cover_lookup("none", false);
+        let output = render(&control::new_status());
+        assert!(output.contains(
+            "music_discord_rpc_cover_lookups_total{provider=\"lastfm\",result=\"found\"} 1\n"
+        ));
+        assert!(output.contains(
+            "music_discord_rpc_cover_lookups_total{provider=\"none\",result=\"not_found\"} 1\n"
+        ));
+    }
+}
@@ -0,0 +1,288 @@
+use serde_json::{Map, Value};
+
+// Current version of the config file format. Increase it when adding a migration.
+pub const CONFIG_VERSION: u64 = 1;
+
+// Version of parsed config file, files without the "version" key are version 0
+pub fn config_version(config: &Map<String, Value>) -> u64 {
+    config
+        .get("version")
+        .and_then(|version| version.as_u64())
+        .unwrap_or(0)
+}
+
+// Rewrite deprecated keys of parsed config file, so old files keep working
+pub fn migrate_config(config: &mut Map<String, Value>) {
+    let version = config_version(config);
+
+    // v1: "disable_musicbrainz_cover" was replaced by "cover_providers"
+    if version < 1 {
+        if let Some(disabled) = config.remove("disable_musicbrainz_cover") {
+            if !config.contains_key("cover_providers") {
+                let mut providers = vec![Value::from("lastfm")];
+                if disabled != Value::Bool(true) {
+                    providers.push(Value::from("musicbrainz"));
+                }
+                config.insert(String::from("cover_providers"), Value::Array(providers));
+            }
+        }
+    }
+
+    config.insert(String::from("version"), Value::from(CONFIG_VERSION));
+}
+
+// Upgrade text of the config file: apply migrations, set the version and add
+// options missing from the file as comments. User values and comments are kept.
+pub fn upgrade_config(yaml_str: &str, template: &str, version: u64) -> String {
+    let mut lines: Vec<String> = yaml_str.lines().map(String::from).collect();
+
+    if version < 1 {
+        migrate_musicbrainz_cover(&mut lines);
+    }
+
+    // Set version
+    let version_line = format!("version: {}", CONFIG_VERSION);
+    match lines
+        .iter()
+        .position(|line| line_key(line) == Some("version"))
+    {
+        Some(index) => lines[index] = version_line,
+        None => {
+            let section = find_section(template, "version").unwrap_or_default();
+            let section: Vec<String> = section.lines().map(String::from).collect();
+
+            // Before the first option set in the file
+            match lines.iter().position(|line| line_key(line).is_some()) {
+                Some(index) => {
+                    let mut insert = section;
+                    insert.push(String::new());
+                    lines.splice(index..index, insert);
+                }
+                None => {
+                    if lines.last().is_some_and(|line| !line.is_empty()) {
+                        lines.push(String::new());
+                    }
+                    lines.extend(section);
+                }
+            }
+        }
+    }
+
+    // Add new options
+    let present: Vec<String> = lines
+        .iter()
+        .filter_map(|line| commented_key(line).or(line_key(line)))
+        .map(String::from)
+        .collect();
+    for section in template.split("\n\n") {
+        let keys: Vec<&str> = section
+            .lines()
+            .filter_map(|line| commented_key(line).or(line_key(line)))
+            .collect();
+        if keys.is_empty() || keys.iter().any(|key| present.iter().any(|p| p == key)) {
+            continue;
+        }
+
+        if lines.last().is_some_and(|line| !line.is_empty()) {
+            lines.push(String::new());
+        }
+        for line in section.lines() {
+            match line.starts_with('#') {
+                true => lines.push(line.to_string()),
+                false => lines.push(format!("# {}", line)),
+            }
+        }
+    }
+
+    let mut upgraded = lines.join("\n");
+    upgraded.push('\n');
+    upgraded
+}
+
+// v1: replace "disable_musicbrainz_cover" with "cover_providers" list
+fn migrate_musicbrainz_cover(lines: &mut Vec<String>) {
+    let index = match lines
+        .iter()
+        .position(|line| line_key(line) == Some("disable_musicbrainz_cover"))
+    {
+        Some(index) => index,
+        None => return,
+    };
+
+    // Also remove the comment describing the old option if it is still there
+    let mut start = index;
+    if index > 0 && lines[index - 1].starts_with("# Prevent MusicBrainz to be used") {
+        start -= 1;
+    }
+
+    // The new option is already set, only the old one is removed
+    if lines
+        .iter()
+        .any(|line| line_key(line) == Some("cover_providers"))
+    {
+        lines.drain(start..=index);
+        return;
+    }
+
+    let disabled = lines[index]
+        .split_once(':')
+        .map(|(_, value)| value.split('#').next().unwrap_or("").trim() == "true")
+        .unwrap_or(false);
+
+    let mut replacement = vec![
+        String::from("# Sources of album covers, the first one that finds the cover is used [possible values: lastfm, musicbrainz]"),
+        String::from("cover_providers:"),
+        String::from("  - lastfm"),
+    ];
+    if !disabled {
+        replacement.push(String::from("  - musicbrainz"));
+    }
+    lines.splice(start..=index, replacement);
+}
+
+// Key set in the line, e.g. "interval" for "interval: 10"
+fn line_key(line: &str) -> Option<&str> {
+    let (key, rest) = line.split_once(':')?;
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    // Skip links and other text that only looks like a key
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    Some(key)
+}
+
+// Key of commented out option, e.g. "button" for "# button:"
+fn commented_key(line: &str) -> Option<&str> {
+    line_key(line.strip_prefix("# ")?)
+}
+
+fn find_section<'a>(template: &'a str, key: &str) -> Option<&'a str> {
+    template
+        .split("\n\n")
+        .find(|section| section.lines().any(|line| line_key(line) == Some(key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TEMPLATE: &str = "# Version of the config file format, used to upgrade it
+version: 1
+
+# Activity refresh rate (min: 5, default: 10)
+interval: 10
+
+# Sources of album covers
+cover_providers:
+  - lastfm
+  - musicbrainz";
+
+    fn migrated(config: Value) -> Value {
+        let mut config = config.as_object().unwrap().clone();
+        migrate_config(&mut config);
+        Value::Object(config)
+    }
+
+    fn upgraded(yaml_str: &str) -> String {
+        upgrade_config(yaml_str, TEMPLATE, 0)
+    }
+
+    #[test]
+    fn musicbrainz_cover_disabled() {
+        assert_eq!(
+            migrated(json!({"disable_musicbrainz_cover": true})),
+            json!({"cover_providers": ["lastfm"], "version": 1})
+        );
+        assert_eq!(
+            upgraded("interval: 10\n# Prevent MusicBrainz to be used as a cover source\ndisable_musicbrainz_cover: true # no\n"),
+            "# Version of the config file format, used to upgrade it
+version: 1
+
+interval: 10
+# Sources of album covers, the first one that finds the cover is used [possible values: lastfm, musicbrainz]
+cover_providers:
+  - lastfm
+"
+        );
+    }
+
+    #[test]
+    fn musicbrainz_cover_enabled() {
+        assert_eq!(
+            migrated(json!({"disable_musicbrainz_cover": false})),
+            json!({"cover_providers": ["lastfm", "musicbrainz"], "version": 1})
+        );
+        assert!(upgraded("disable_musicbrainz_cover: false\n")
+            .contains("\ncover_providers:\n  - lastfm\n  - musicbrainz\n"));
+    }
+
+    #[test]
+    fn cover_providers_already_set() {
+        assert_eq!(
+            migrated(
+                json!({"disable_musicbrainz_cover": true, "cover_providers": ["musicbrainz"]})
+            ),
+            json!({"cover_providers": ["musicbrainz"], "version": 1})
+        );
+
+        let upgraded = upgraded(
+            "# My covers
+cover_providers:
+  - musicbrainz
+
+# Prevent MusicBrainz to be used as a cover source
+disable_musicbrainz_cover: true
+interval: 10
+",
+        );
+        assert_eq!(
+            upgraded,
+            "# My covers
+# Version of the config file format, used to upgrade it
+version: 1
+
+cover_providers:
+  - musicbrainz
+
+interval: 10
+"
+        );
+        assert!(serde_yaml::from_str::<Value>(&upgraded).is_ok());
+    }
+
+    #[test]
+    fn other_comments_are_kept() {
+        let upgraded = upgraded("# Covers from Last.fm only\ndisable_musicbrainz_cover: true\n");
+        assert!(upgraded.contains("# Covers from Last.fm only\n# Sources of album covers"));
+    }
+
+    #[test]
+    fn current_files_are_not_migrated() {
+        let mut config = json!({"version": 1, "disable_musicbrainz_cover": true})
+            .as_object()
+            .unwrap()
+            .clone();
+        migrate_config(&mut config);
+        assert_eq!(config_version(&config), 1);
+        assert!(config.contains_key("disable_musicbrainz_cover"));
+        assert_eq!(config_version(&Map::new()), 0);
+    }
+
+    #[test]
+    fn missing_options_are_added_as_comments() {
+        assert_eq!(
+            upgrade_config("version: 1\ninterval: 5\n", TEMPLATE, 1),
+            "version: 1
+interval: 5
+
+# Sources of album covers
+# cover_providers:
+#   - lastfm
+#   - musicbrainz
+"
+        );
+    }
+}
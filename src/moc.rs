@@ -0,0 +1,157 @@
+use std::process::Command;
+
+use crate::source::Source;
+use crate::utils::{self, MediaInfo, NowPlayingResult};
+
+// Music On Console, read from the output of "mocp --info". Its socket
+// protocol is internal and changes between versions.
+pub struct Moc;
+
+impl Source for Moc {
+    fn name(&self) -> String {
+        String::from("MOC")
+    }
+
+    fn get_media_info(&mut self) -> NowPlayingResult {
+        let output = match Command::new("mocp").arg("--info").output() {
+            Ok(output) => output,
+            Err(err) => return Err(format!("Could not run mocp: {}", err).into()),
+        };
+        if !output.status.success() {
+            return Err("MOC server is not running.".into());
+        }
+        media_info(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+fn media_info(output: &str) -> NowPlayingResult {
+    // "Key: value" lines
+    let value = |key: &str| -> Option<String> {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string())
+    };
+
+    let state = value("State").unwrap_or_default();
+    if state != "PLAY" && state != "PAUSE" {
+        return Err("MOC is not playing anything.".into());
+    }
+
+    let file = value("File").unwrap_or_default();
+    let is_stream = file.starts_with("http://") || file.starts_with("https://");
+
+    // "Title" is the formatted line shown in MOC, "SongTitle" is the tag
+    let title = match value("SongTitle").or(value("Title")) {
+        Some(title) => title,
+        None => utils::file_stem(&file),
+    };
+    let artist = value("Artist").unwrap_or_else(|| String::from("Unknown Artist"));
+    let album = value("Album").unwrap_or_else(|| String::from("Unknown Album"));
+
+    let seconds = |key: &str| -> Option<u64> { value(key)?.parse().ok() };
+    let position = seconds("CurrentSec");
+
+    Ok(MediaInfo {
+        title,
+        album_artist: artist.clone(),
+        artist,
+        album,
+        is_playing: state == "PLAY",
+        duration: seconds("TotalSec").unwrap_or(0),
+        position: position.unwrap_or(0),
+        is_track_position: position.is_some(),
+        url: match is_stream {
+            true => file.clone(),
+            false => String::new(),
+        },
+        path: match is_stream {
+            true => String::new(),
+            false => file,
+        },
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tagged_files() {
+        // Output of mocp --info
+        let media_info = media_info(
+            "State: PLAY
+File: /music/Radiohead/OK Computer/02 Paranoid Android.flac
+Title: 2 Radiohead - Paranoid Android (OK Computer)
+Artist: Radiohead
+SongTitle: Paranoid Android
+Album: OK Computer
+TotalTime: 06:23
+TimeLeft: 05:01
+TotalSec: 383
+CurrentTime: 01:22
+CurrentSec: 82
+Bitrate: 1024kbps
+AvgBitrate: 1024kbps
+Rate: 44kHz
+",
+        )
+        .unwrap();
+        assert_eq!(media_info.title, "Paranoid Android");
+        assert_eq!(media_info.artist, "Radiohead");
+        assert_eq!(media_info.album_artist, "Radiohead");
+        assert_eq!(media_info.album, "OK Computer");
+        assert!(media_info.is_playing);
+        assert_eq!(media_info.duration, 383);
+        assert_eq!(media_info.position, 82);
+        assert_eq!(
+            media_info.path,
+            "/music/Radiohead/OK Computer/02 Paranoid Android.flac"
+        );
+    }
+
+    #[test]
+    fn streams_and_untagged_files() {
+        let stream = media_info(
+            "State: PLAY
+File: https://radio.example.org/stream.mp3
+Title: Example Radio
+Artist: 
+SongTitle: 
+Album: 
+TotalTime: 
+TotalSec: 
+CurrentTime: 01:01
+CurrentSec: 61
+",
+        )
+        .unwrap();
+        assert_eq!(stream.title, "Example Radio");
+        assert_eq!(stream.artist, "Unknown Artist");
+        assert_eq!(stream.duration, 0);
+        assert_eq!(stream.url, "https://radio.example.org/stream.mp3");
+
+        let file = media_info(
+            "State: PAUSE
+File: /music/Some Track.ogg
+Title: 
+TotalSec: 200
+CurrentSec: 10
+",
+        )
+        .unwrap();
+        assert_eq!(file.title, "Some Track");
+        assert!(!file.is_playing);
+    }
+
+    #[test]
+    fn stopped() {
+        assert_eq!(
+            media_info("State: STOP\n").err().unwrap().to_string(),
+            "MOC is not playing anything."
+        );
+    }
+}
@@ -0,0 +1,362 @@
+use log::debug;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use crate::events::Event;
+use crate::settings::Cli;
+use crate::source::Source;
+use crate::utils::{self, MediaInfo, NowPlayingResult};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Music Player Daemon, see https://mpd.readthedocs.io/en/latest/protocol.html
+pub struct Mpd {
+    address: Address,
+    password: Option<String>,
+    connection: Option<Connection>,
+}
+
+#[derive(Clone)]
+enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Mpd {
+    pub fn new(settings: &Cli, events: Sender<Event>) -> Mpd {
+        let host = settings.mpd_host.clone().unwrap_or_default();
+        let address = if host.starts_with('/') || host.starts_with('~') {
+            Address::Unix(utils::expand_home(&host))
+        } else {
+            Address::Tcp(format!("{}:{}", host, settings.mpd_port.unwrap_or(6600)))
+        };
+        let password = settings.mpd_password.clone();
+
+        watch_player(address.clone(), password.clone(), events);
+
+        Mpd {
+            address,
+            password,
+            connection: None,
+        }
+    }
+
+    fn query(&mut self) -> NowPlayingResult {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => {
+                let connection = Connection::open(&self.address, self.password.as_deref())?;
+                self.connection.insert(connection)
+            }
+        };
+
+        let status = connection.command("status")?;
+        if field(&status, "state").unwrap_or("stop") == "stop" {
+            return Err("MPD is not playing anything.".into());
+        }
+        let song = connection.command("currentsong")?;
+        Ok(media_info(&status, &song))
+    }
+}
+
+impl Source for Mpd {
+    fn name(&self) -> String {
+        String::from("MPD")
+    }
+
+    fn get_media_info(&mut self) -> NowPlayingResult {
+        let result = self.query();
+        // Connect again next time, e.g. after MPD was restarted
+        if let Err(err) = &result {
+            if err.downcast_ref::<io::Error>().is_some() {
+                self.connection = None;
+            }
+        }
+        result
+    }
+}
+
+fn media_info(status: &[(String, String)], song: &[(String, String)]) -> MediaInfo {
+    let file = field(song, "file").unwrap_or_default();
+
+    // Streams usually have only the station name, local files without tags only the path
+    let title = match field(song, "Title").or(field(song, "Name")) {
+        Some(title) => title.to_string(),
+        None => utils::file_stem(file),
+    };
+    let artist = field(song, "Artist")
+        .unwrap_or("Unknown Artist")
+        .to_string();
+    let album = field(song, "Album").unwrap_or("Unknown Album").to_string();
+    let album_artist = field(song, "AlbumArtist").unwrap_or(&artist).to_string();
+
+    let seconds = |value: Option<&str>| -> u64 {
+        value
+            .and_then(|value| value.parse::<f64>().ok())
+            .unwrap_or(0.0) as u64
+    };
+    // Older versions only have "time" with "elapsed:duration" in whole seconds
+    let time = field(status, "time").and_then(|time| time.split_once(':'));
+    let position = match field(status, "elapsed") {
+        Some(elapsed) => seconds(Some(elapsed)),
+        None => seconds(time.map(|(elapsed, _)| elapsed)),
+    };
+    let duration = match field(status, "duration").or(field(song, "duration")) {
+        Some(duration) => seconds(Some(duration)),
+        None => seconds(time.map(|(_, duration)| duration)),
+    };
+
+    let url = match file.starts_with("http://") || file.starts_with("https://") {
+        true => file.to_string(),
+        false => String::new(),
+    };
+
+    MediaInfo {
+        title,
+        artist,
+        album_artist,
+        album,
+        is_playing: field(status, "state") == Some("play"),
+        duration,
+        position,
+        is_track_position: true,
+        url,
+        musicbrainz_album_id: field(song, "MUSICBRAINZ_ALBUMID")
+            .unwrap_or_default()
+            .to_string(),
+        ..Default::default()
+    }
+}
+
+// First value of the given key, tags like Artist can be repeated
+fn field<'a>(response: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    response
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.as_str())
+        .filter(|value| !value.is_empty())
+}
+
+// Wake up the main loop every time the playback changes, on a separate
+// connection because "idle" blocks until something happens
+fn watch_player(address: Address, password: Option<String>, events: Sender<Event>) {
+    thread::spawn(move || loop {
+        match Connection::open(&address, password.as_deref()) {
+            Ok(mut connection) => {
+                connection.set_timeout(None);
+                debug!("[mpd] Connected, waiting for changes.");
+                loop {
+                    match connection.command("idle player") {
+                        Ok(_) => {
+                            if events.send(Event::PlayerChanged).is_err() {
+                                return;
+                            }
+                        }
+                        Err(err) => {
+                            debug!("[mpd] Connection lost: {}", err);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(err) => debug!("[mpd] Could not connect: {}", err),
+        }
+        thread::sleep(Duration::from_secs(5));
+    });
+}
+
+trait Stream: Read + Write + Send {
+    fn set_timeout(&self, timeout: Option<Duration>);
+}
+
+impl Stream for TcpStream {
+    fn set_timeout(&self, timeout: Option<Duration>) {
+        let _ = self.set_read_timeout(timeout);
+        let _ = self.set_write_timeout(timeout);
+    }
+}
+
+impl Stream for UnixStream {
+    fn set_timeout(&self, timeout: Option<Duration>) {
+        let _ = self.set_read_timeout(timeout);
+        let _ = self.set_write_timeout(timeout);
+    }
+}
+
+struct Connection {
+    reader: BufReader<Box<dyn Stream>>,
+}
+
+impl Connection {
+    fn open(
+        address: &Address,
+        password: Option<&str>,
+    ) -> Result<Connection, Box<dyn std::error::Error>> {
+        let stream: Box<dyn Stream> = match address {
+            Address::Tcp(address) => {
+                let socket_address = match address.to_socket_addrs()?.next() {
+                    Some(socket_address) => socket_address,
+                    None => return Err(format!("Could not resolve {}", address).into()),
+                };
+                Box::new(TcpStream::connect_timeout(&socket_address, TIMEOUT)?)
+            }
+            Address::Unix(path) => Box::new(UnixStream::connect(path)?),
+        };
+        stream.set_timeout(Some(TIMEOUT));
+
+        let mut connection = Connection {
+            reader: BufReader::new(stream),
+        };
+        let greeting = connection.read_line()?;
+        if !greeting.starts_with("OK MPD ") {
+            return Err(format!("Not an MPD server: {}", greeting).into());
+        }
+        if let Some(password) = password {
+            connection.command(&format!("password {}", quote(password)))?;
+        }
+        Ok(connection)
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.reader.get_ref().set_timeout(timeout);
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end_matches('\n').to_string())
+    }
+
+    // Send a command and read "key: value" lines until OK. MPD errors (ACK)
+    // are returned as plain errors, lost connections as io::Error.
+    fn command(
+        &mut self,
+        command: &str,
+    ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+        let stream = self.reader.get_mut();
+        stream.write_all(format!("{}\n", command).as_bytes())?;
+        stream.flush()?;
+
+        let mut response = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if line == "OK" {
+                return Ok(response);
+            }
+            if let Some(error) = line.strip_prefix("ACK ") {
+                return Err(format!("MPD error: {}.", error).into());
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                response.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Connection to a fake server that replies with the given lines
+    fn connection(replies: &str) -> Connection {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        server.write_all(replies.as_bytes()).unwrap();
+        server.shutdown(std::net::Shutdown::Write).unwrap();
+        thread::spawn(move || {
+            let mut commands = String::new();
+            let _ = server.read_to_string(&mut commands);
+        });
+        Connection {
+            reader: BufReader::new(Box::new(client)),
+        }
+    }
+
+    const STATUS: &str = "volume: 100\nrepeat: 0\nrandom: 0\nsingle: 0\nconsume: 0\n\
+        partition: default\nplaylist: 4\nplaylistlength: 12\nmixrampdb: 0\nstate: play\n\
+        song: 2\nsongid: 3\ntime: 83:383\nelapsed: 82.512\nbitrate: 320\n\
+        duration: 383.493\naudio: 44100:24:2\nnextsong: 3\nnextsongid: 4\nOK\n";
+    const CURRENT_SONG: &str = "file: Radiohead/OK Computer/02 Paranoid Android.flac\n\
+        Last-Modified: 2024-03-01T18:22:10Z\nFormat: 44100:24:2\nArtist: Radiohead\n\
+        Artist: Other Artist\nAlbumArtist: Radiohead\nTitle: Paranoid Android\n\
+        Album: OK Computer\nTrack: 2\nDate: 1997\n\
+        MUSICBRAINZ_ALBUMID: 0b6b4ba0-d36f-47bd-b4ea-6a5b91842d29\n\
+        Time: 383\nduration: 383.493\nPos: 2\nId: 3\nOK\n";
+
+    #[test]
+    fn status_and_current_song() {
+        let mut connection = connection(&format!("{}{}", STATUS, CURRENT_SONG));
+        let status = connection.command("status").unwrap();
+        let song = connection.command("currentsong").unwrap();
+        let media_info = media_info(&status, &song);
+
+        assert_eq!(media_info.title, "Paranoid Android");
+        assert_eq!(media_info.artist, "Radiohead");
+        assert_eq!(media_info.album_artist, "Radiohead");
+        assert_eq!(media_info.album, "OK Computer");
+        assert!(media_info.is_playing);
+        assert_eq!(media_info.position, 82);
+        assert_eq!(media_info.duration, 383);
+        assert_eq!(media_info.url, "");
+        assert_eq!(
+            media_info.musicbrainz_album_id,
+            "0b6b4ba0-d36f-47bd-b4ea-6a5b91842d29"
+        );
+    }
+
+    #[test]
+    fn streams_and_untagged_files() {
+        let status = connection("state: pause\ntime: 61:0\nOK\n")
+            .command("status")
+            .unwrap();
+        let stream = connection(
+            "file: https://radio.example.org/stream.mp3\nName: Example Radio\nPos: 0\nId: 1\nOK\n",
+        )
+        .command("currentsong")
+        .unwrap();
+        let radio = media_info(&status, &stream);
+        assert_eq!(radio.title, "Example Radio");
+        assert_eq!(radio.artist, "Unknown Artist");
+        assert_eq!(radio.album, "Unknown Album");
+        assert!(!radio.is_playing);
+        assert_eq!(radio.position, 61);
+        assert_eq!(radio.duration, 0);
+        assert_eq!(radio.url, "https://radio.example.org/stream.mp3");
+
+        let file = connection("file: music/Some Track.ogg\nTitle: \nOK\n")
+            .command("currentsong")
+            .unwrap();
+        assert_eq!(media_info(&status, &file).title, "Some Track");
+    }
+
+    #[test]
+    fn idle_and_errors() {
+        let mut connection = connection("changed: player\nOK\nACK [50@0] {play} No such song\n");
+        assert_eq!(
+            connection.command("idle player").unwrap(),
+            [(String::from("changed"), String::from("player"))]
+        );
+        assert_eq!(
+            connection.command("play 99").err().unwrap().to_string(),
+            "MPD error: [50@0] {play} No such song."
+        );
+        // Lost connections are io::Error, so the source connects again
+        let err = connection.command("status").err().unwrap();
+        assert!(err.downcast_ref::<io::Error>().is_some());
+    }
+
+    #[test]
+    fn quoted_passwords() {
+        assert_eq!(quote(r#"pa"ss\word"#), r#""pa\"ss\\word""#);
+    }
+}
@@ -0,0 +1,302 @@
+use log::debug;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::events::Event;
+use crate::settings::Cli;
+use crate::source::Source;
+use crate::utils::{self, MediaInfo, NowPlayingResult};
+
+// Properties watched over the JSON IPC, see https://mpv.io/manual/stable/#json-ipc
+const PROPERTIES: [&str; 9] = [
+    "media-title",
+    "metadata",
+    "pause",
+    "time-pos",
+    "duration",
+    "path",
+    "filename",
+    "filename/no-ext",
+    "current-tracks/video",
+];
+
+// mpv started with --input-ipc-server=<socket>
+pub struct Mpv {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    connected: bool,
+    properties: HashMap<String, Value>,
+}
+
+impl State {
+    // Store the property from a "property-change" event and return its name,
+    // replies to commands and other events are ignored
+    fn update(&mut self, line: &str) -> Option<String> {
+        let message: Value = serde_json::from_str(line).unwrap_or_default();
+        if message["event"] != "property-change" {
+            return None;
+        }
+        let name = message["name"].as_str()?;
+        self.properties
+            .insert(name.to_string(), message["data"].clone());
+        Some(name.to_string())
+    }
+}
+
+impl Mpv {
+    pub fn new(settings: &Cli, events: Sender<Event>) -> Mpv {
+        let socket = utils::expand_home(settings.mpv_socket.as_deref().unwrap_or_default());
+        let state = Arc::new(Mutex::new(State::default()));
+        watch_properties(socket, state.clone(), events);
+        Mpv { state }
+    }
+
+    fn property(&self, name: &str) -> Value {
+        match self.state.lock() {
+            Ok(state) => state.properties.get(name).cloned().unwrap_or_default(),
+            Err(_) => Value::Null,
+        }
+    }
+}
+
+impl Source for Mpv {
+    fn name(&self) -> String {
+        String::from("mpv")
+    }
+
+    fn get_media_info(&mut self) -> NowPlayingResult {
+        let state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return Err("mpv state is not available.".into()),
+        };
+        if !state.connected {
+            return Err("mpv is not running.".into());
+        }
+        let property = |name: &str| state.properties.get(name).unwrap_or(&Value::Null);
+        let path = property("path").as_str().unwrap_or_default();
+        if path.is_empty() {
+            return Err("Nothing is playing in mpv.".into());
+        }
+
+        // Tag names depend on the file format, e.g. "artist" or "ARTIST"
+        let metadata = property("metadata");
+        let tag = |names: &[&str]| -> Option<String> {
+            let tags = metadata.as_object()?;
+            names.iter().find_map(|name| {
+                tags.iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                    .and_then(|(_, value)| value.as_str())
+                    .filter(|value| !value.is_empty())
+                    .map(|value| value.to_string())
+            })
+        };
+
+        // Without a title tag mpv uses the file name, show it without the extension
+        let media_title = property("media-title").as_str().unwrap_or_default();
+        let title = match media_title == property("filename").as_str().unwrap_or_default() {
+            true => property("filename/no-ext").as_str().unwrap_or(media_title),
+            false => media_title,
+        };
+
+        let artist = tag(&["artist"]).unwrap_or_else(|| String::from("Unknown Artist"));
+        let album = tag(&["album"]).unwrap_or_else(|| String::from("Unknown Album"));
+        let album_artist = tag(&["album_artist", "albumartist"]).unwrap_or(artist.clone());
+
+        let position = property("time-pos").as_f64();
+        let is_stream = path.starts_with("http://") || path.starts_with("https://");
+
+        Ok(MediaInfo {
+            title: title.to_string(),
+            artist,
+            album_artist,
+            album,
+            is_playing: !property("pause").as_bool().unwrap_or(false),
+            duration: property("duration").as_f64().unwrap_or(0.0) as u64,
+            position: position.unwrap_or(0.0) as u64,
+            is_track_position: position.is_some(),
+            url: match is_stream {
+                true => path.to_string(),
+                false => String::new(),
+            },
+            musicbrainz_album_id: tag(&["musicbrainz_albumid", "musicbrainz album id"])
+                .unwrap_or_default(),
+            path: match is_stream {
+                true => String::new(),
+                false => path.to_string(),
+            },
+            ..Default::default()
+        })
+    }
+
+    // Audio files only have a video track for the embedded cover
+    fn is_video(&self) -> bool {
+        let video = self.property("current-tracks/video");
+        video.is_object() && !video["albumart"].as_bool().unwrap_or(false)
+    }
+}
+
+// Keep the properties up to date and wake up the main loop when they change.
+// mpv is started and closed often, so the socket is checked again every few seconds.
+fn watch_properties(socket: PathBuf, state: Arc<Mutex<State>>, events: Sender<Event>) {
+    thread::spawn(move || loop {
+        if let Ok(mut stream) = UnixStream::connect(&socket) {
+            debug!("[mpv] Connected to {}", socket.display());
+            if let Ok(mut state) = state.lock() {
+                state.connected = true;
+            }
+
+            for (id, name) in PROPERTIES.iter().enumerate() {
+                let command = json!({ "command": ["observe_property", id + 1, name] });
+                if writeln!(stream, "{}", command).is_err() {
+                    break;
+                }
+            }
+
+            let reader = BufReader::new(&stream);
+            for line in reader.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                let name = match state.lock() {
+                    Ok(mut state) => state.update(&line),
+                    Err(_) => None,
+                };
+                // Position changes all the time, it is read with the next update
+                let is_changed = name.is_some_and(|name| name != "time-pos");
+                if is_changed && events.send(Event::PlayerChanged).is_err() {
+                    return;
+                }
+            }
+
+            debug!("[mpv] Disconnected.");
+            if let Ok(mut state) = state.lock() {
+                *state = State::default();
+            }
+            if events.send(Event::PlayerChanged).is_err() {
+                return;
+            }
+        }
+        thread::sleep(Duration::from_secs(5));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Replies and events as sent by mpv after observing the properties
+    fn mpv(lines: &[&str]) -> Mpv {
+        let mut state = State {
+            connected: true,
+            ..Default::default()
+        };
+        for line in lines {
+            state.update(line);
+        }
+        Mpv {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    const AUDIO_FILE: [&str; 10] = [
+        r#"{"request_id":0,"error":"success"}"#,
+        r#"{"event":"property-change","id":1,"name":"media-title","data":"Paranoid Android"}"#,
+        r#"{"event":"property-change","id":2,"name":"metadata","data":{"ARTIST":"Radiohead","ALBUM":"OK Computer","album_artist":"Radiohead","MUSICBRAINZ_ALBUMID":"0b6b4ba0-d36f-47bd-b4ea-6a5b91842d29","TITLE":"Paranoid Android"}}"#,
+        r#"{"event":"property-change","id":3,"name":"pause","data":false}"#,
+        r#"{"event":"property-change","id":4,"name":"time-pos","data":82.512}"#,
+        r#"{"event":"property-change","id":5,"name":"duration","data":383.493}"#,
+        r#"{"event":"property-change","id":6,"name":"path","data":"/music/02 Paranoid Android.flac"}"#,
+        r#"{"event":"property-change","id":7,"name":"filename","data":"02 Paranoid Android.flac"}"#,
+        r#"{"event":"property-change","id":9,"name":"current-tracks/video","data":{"id":1,"type":"video","albumart":true,"codec":"mjpeg"}}"#,
+        r#"{"event":"playback-restart"}"#,
+    ];
+
+    #[test]
+    fn audio_files() {
+        let mut mpv = mpv(&AUDIO_FILE);
+        let media_info = mpv.get_media_info().unwrap();
+        assert_eq!(media_info.title, "Paranoid Android");
+        assert_eq!(media_info.artist, "Radiohead");
+        assert_eq!(media_info.album_artist, "Radiohead");
+        assert_eq!(media_info.album, "OK Computer");
+        assert!(media_info.is_playing);
+        assert_eq!(media_info.position, 82);
+        assert!(media_info.is_track_position);
+        assert_eq!(media_info.duration, 383);
+        assert_eq!(media_info.path, "/music/02 Paranoid Android.flac");
+        assert_eq!(media_info.url, "");
+        assert_eq!(
+            media_info.musicbrainz_album_id,
+            "0b6b4ba0-d36f-47bd-b4ea-6a5b91842d29"
+        );
+        // The video track is the embedded cover
+        assert!(!mpv.is_video());
+    }
+
+    #[test]
+    fn videos_without_tags() {
+        let mut mpv = mpv(&[
+            r#"{"event":"property-change","id":1,"name":"media-title","data":"holiday.mkv"}"#,
+            r#"{"event":"property-change","id":2,"name":"metadata","data":{"ENCODER":"libebml v1.4.2"}}"#,
+            r#"{"event":"property-change","id":3,"name":"pause","data":true}"#,
+            r#"{"event":"property-change","id":4,"name":"time-pos"}"#,
+            r#"{"event":"property-change","id":6,"name":"path","data":"/videos/holiday.mkv"}"#,
+            r#"{"event":"property-change","id":7,"name":"filename","data":"holiday.mkv"}"#,
+            r#"{"event":"property-change","id":8,"name":"filename/no-ext","data":"holiday"}"#,
+            r#"{"event":"property-change","id":9,"name":"current-tracks/video","data":{"id":1,"type":"video","albumart":false,"codec":"h264"}}"#,
+        ]);
+        let media_info = mpv.get_media_info().unwrap();
+        assert_eq!(media_info.title, "holiday");
+        assert_eq!(media_info.artist, "Unknown Artist");
+        assert_eq!(media_info.album, "Unknown Album");
+        assert!(!media_info.is_playing);
+        assert!(!media_info.is_track_position);
+        assert!(mpv.is_video());
+    }
+
+    #[test]
+    fn streams() {
+        let mut mpv = mpv(&[
+            r#"{"event":"property-change","id":1,"name":"media-title","data":"Example Radio"}"#,
+            r#"{"event":"property-change","id":6,"name":"path","data":"https://radio.example.org/stream.mp3"}"#,
+        ]);
+        let media_info = mpv.get_media_info().unwrap();
+        assert_eq!(media_info.title, "Example Radio");
+        assert_eq!(media_info.url, "https://radio.example.org/stream.mp3");
+        assert_eq!(media_info.path, "");
+    }
+
+    #[test]
+    fn idle_player() {
+        let mut state = State::default();
+        assert_eq!(
+            state.update(r#"{"event":"property-change","id":6,"name":"path"}"#),
+            Some(String::from("path"))
+        );
+        assert_eq!(state.update(r#"{"event":"idle"}"#), None);
+        assert_eq!(state.update("not json"), None);
+        let mut mpv = Mpv {
+            state: Arc::new(Mutex::new(state)),
+        };
+        assert_eq!(
+            mpv.get_media_info().err().unwrap().to_string(),
+            "mpv is not running."
+        );
+        mpv.state.lock().unwrap().connected = true;
+        assert_eq!(
+            mpv.get_media_info().err().unwrap().to_string(),
+            "Nothing is playing in mpv."
+        );
+    }
+}
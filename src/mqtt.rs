@@ -0,0 +1,288 @@
+use log::{debug, error, info, warn};
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::control::SharedStatus;
+use crate::now_playing::{self, NowPlaying};
+use crate::utils;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// The position changes every second while playing, on its own it is sent at
+// most this often
+const POSITION_INTERVAL: Duration = Duration::from_secs(15);
+
+// Options of the MQTT output, see README
+pub struct Mqtt {
+    // mqtt://host:port, port is optional
+    pub broker: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic_prefix: String,
+    pub home_assistant: bool,
+}
+
+// Publish the current track as retained topics under the prefix
+pub fn start(mqtt: Mqtt, status: SharedStatus) {
+    let (host, port) = match parse_broker(&mqtt.broker) {
+        Some(address) => address,
+        None => {
+            error!("[mqtt] Invalid broker address: {}", mqtt.broker);
+            return;
+        }
+    };
+    let prefix = mqtt.topic_prefix.trim_end_matches('/').to_string();
+    let availability_topic = format!("{}/availability", prefix);
+
+    let mut options = MqttOptions::new(
+        format!("music-discord-rpc-{}", std::process::id()),
+        host,
+        port,
+    );
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        &availability_topic,
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &mqtt.username {
+        options.set_credentials(username, mqtt.password.clone().unwrap_or_default());
+    }
+
+    let (client, mut connection) = Client::new(options, 64);
+
+    // Everything is published again after every (re)connection, in case the
+    // broker lost retained messages
+    let connected = Arc::new(AtomicBool::new(false));
+    let resend = Arc::new(AtomicBool::new(false));
+    {
+        let connected = connected.clone();
+        let resend = resend.clone();
+        let broker = mqtt.broker.clone();
+        thread::spawn(move || {
+            for event in connection.iter() {
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("[mqtt] Connected to {}", broker);
+                        connected.store(true, Ordering::Relaxed);
+                        resend.store(true, Ordering::Relaxed);
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        if connected.swap(false, Ordering::Relaxed) {
+                            warn!("[mqtt] Disconnected: {}", err);
+                        } else {
+                            debug!("[mqtt] Could not connect to {}: {}", broker, err);
+                        }
+                        thread::sleep(Duration::from_secs(5));
+                    }
+                }
+            }
+        });
+    }
+
+    thread::spawn(move || {
+        // Values the broker has, only recorded after they were queued
+        let mut sent: HashMap<String, String> = HashMap::new();
+        let mut position_sent: Option<Instant> = None;
+        loop {
+            if connected.load(Ordering::Relaxed) {
+                if resend.swap(false, Ordering::Relaxed) {
+                    sent.clear();
+                    position_sent = None;
+                    publish(&client, &availability_topic, String::from("online"));
+                    if mqtt.home_assistant {
+                        send_discovery(&client, &prefix);
+                    }
+                }
+
+                let now_playing = NowPlaying::new(&now_playing::current(&status));
+                let values = topics(&prefix, &now_playing);
+                let is_position_due =
+                    position_sent.is_none_or(|sent| sent.elapsed() >= POSITION_INTERVAL);
+                for (topic, value) in pending(values, &sent, is_position_due) {
+                    if publish(&client, &topic, value.clone()) {
+                        if is_position(&topic) {
+                            position_sent = Some(Instant::now());
+                        }
+                        sent.insert(topic, value);
+                    }
+                }
+            }
+
+            thread::sleep(Duration::from_millis(500));
+        }
+    });
+}
+
+fn topics(prefix: &str, now_playing: &NowPlaying) -> Vec<(String, String)> {
+    [
+        ("state", now_playing.status().to_string()),
+        ("title", now_playing.title.clone()),
+        ("artist", now_playing.artist.clone()),
+        ("album", now_playing.album.clone()),
+        ("cover_url", now_playing.cover_url.clone()),
+        ("player", now_playing.player.clone()),
+        ("position", now_playing.position.to_string()),
+        ("duration", now_playing.duration.to_string()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (format!("{}/{}", prefix, name), value))
+    .collect()
+}
+
+fn is_position(topic: &str) -> bool {
+    topic.ends_with("/position")
+}
+
+// Values that changed since they were sent. The position is only sent together
+// with other changes, e.g. after a seek the state or track changes too, or
+// when it is due.
+fn pending(
+    values: Vec<(String, String)>,
+    sent: &HashMap<String, String>,
+    is_position_due: bool,
+) -> Vec<(String, String)> {
+    let mut changed: Vec<(String, String)> = values
+        .into_iter()
+        .filter(|(topic, value)| sent.get(topic) != Some(value))
+        .collect();
+    let has_other_changes = changed.iter().any(|(topic, _)| !is_position(topic));
+    if !is_position_due && !has_other_changes {
+        changed.retain(|(topic, _)| !is_position(topic));
+    }
+    changed
+}
+
+// True if the message was queued, it is sent again later otherwise
+fn publish(client: &Client, topic: &str, payload: String) -> bool {
+    match client.try_publish(topic, QoS::AtLeastOnce, true, payload) {
+        Ok(_) => true,
+        Err(err) => {
+            debug!("[mqtt] Could not publish {}: {}", topic, err);
+            false
+        }
+    }
+}
+
+// Home Assistant MQTT discovery, creates a device with a sensor for every topic
+// and an image entity with the album cover
+fn send_discovery(client: &Client, prefix: &str) {
+    let node_id = utils::sanitize_name(prefix);
+    let device = json!({
+        "identifiers": [node_id],
+        "name": "Music Discord RPC",
+        "manufacturer": "music-discord-rpc",
+        "sw_version": VERSION,
+    });
+
+    let sensors = [
+        ("state", "State", "mdi:play-pause", None),
+        ("title", "Title", "mdi:music", None),
+        ("artist", "Artist", "mdi:account-music", None),
+        ("album", "Album", "mdi:album", None),
+        ("player", "Player", "mdi:application", None),
+        ("position", "Position", "mdi:timer-outline", Some("s")),
+        ("duration", "Duration", "mdi:timer", Some("s")),
+    ];
+    for (name, title, icon, unit) in sensors {
+        let mut config = json!({
+            "name": title,
+            "unique_id": format!("{}_{}", node_id, name),
+            "state_topic": format!("{}/{}", prefix, name),
+            "availability_topic": format!("{}/availability", prefix),
+            "icon": icon,
+            "device": device,
+        });
+        if let Some(unit) = unit {
+            config["unit_of_measurement"] = unit.into();
+        }
+        publish(
+            client,
+            &format!("homeassistant/sensor/{}/{}/config", node_id, name),
+            config.to_string(),
+        );
+    }
+
+    let cover = json!({
+        "name": "Album cover",
+        "unique_id": format!("{}_cover", node_id),
+        "url_topic": format!("{}/cover_url", prefix),
+        "availability_topic": format!("{}/availability", prefix),
+        "device": device,
+    });
+    publish(
+        client,
+        &format!("homeassistant/image/{}/cover/config", node_id),
+        cover.to_string(),
+    );
+}
+
+// "mqtt://host:port", "host:port" or "host", the default port is 1883
+pub fn parse_broker(broker: &str) -> Option<(String, u16)> {
+    let address = broker.strip_prefix("mqtt://").unwrap_or(broker);
+    let address = address.trim_end_matches('/');
+    if address.is_empty() || address.contains("://") {
+        return None;
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) => Some((host.to_string(), port.parse().ok()?)),
+        None => Some((address.to_string(), 1883)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(position: u64, title: &str) -> Vec<(String, String)> {
+        vec![
+            (String::from("mdr/title"), title.to_string()),
+            (String::from("mdr/position"), position.to_string()),
+        ]
+    }
+
+    fn sent(position: u64, title: &str) -> HashMap<String, String> {
+        values(position, title).into_iter().collect()
+    }
+
+    #[test]
+    fn only_changed_values_are_sent() {
+        assert_eq!(pending(values(10, "Song"), &sent(10, "Song"), true), []);
+        assert_eq!(pending(values(10, "Song"), &HashMap::new(), false).len(), 2);
+    }
+
+    #[test]
+    fn position_is_throttled() {
+        assert_eq!(pending(values(11, "Song"), &sent(10, "Song"), false), []);
+        assert_eq!(
+            pending(values(11, "Song"), &sent(10, "Song"), true),
+            values(11, "Song")[1..]
+        );
+        // Sent right away together with other changes
+        assert_eq!(
+            pending(values(0, "Other"), &sent(10, "Song"), false),
+            values(0, "Other")
+        );
+    }
+
+    #[test]
+    fn brokers() {
+        assert_eq!(
+            parse_broker("mqtt://localhost:1884"),
+            Some((String::from("localhost"), 1884))
+        );
+        assert_eq!(
+            parse_broker("192.168.1.2"),
+            Some((String::from("192.168.1.2"), 1883))
+        );
+        assert_eq!(parse_broker("http://localhost"), None);
+        assert_eq!(parse_broker("localhost:port"), None);
+    }
+}
@@ -0,0 +1,208 @@
+#[cfg(target_os = "linux")]
+use dbus::arg::{PropMap, Variant};
+#[cfg(target_os = "linux")]
+use dbus::blocking::Connection;
+#[cfg(target_os = "linux")]
+use log::warn;
+use reqwest::blocking::Client;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// The cover is downloaded in the main loop, a slow server must not hold up the activity
+const COVER_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Desktop notifications sent through org.freedesktop.Notifications (Linux only)
+pub struct Notifier {
+    // Enabled events
+    pub events: Vec<String>,
+    // Cover of the current track is downloaded here, notification servers can't show remote images
+    cache_dir: PathBuf,
+    client: Client,
+    cover_file: Option<PathBuf>,
+    cover_url: String,
+    // Each event replaces its previous notification instead of stacking them
+    replaces_ids: HashMap<String, u32>,
+    #[cfg(target_os = "linux")]
+    connection: Option<Connection>,
+}
+
+impl Notifier {
+    pub fn new(events: &[String], cache_dir: PathBuf) -> Notifier {
+        Notifier {
+            events: events.to_vec(),
+            cache_dir,
+            client: Client::builder()
+                .timeout(COVER_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            cover_file: None,
+            cover_url: String::new(),
+            replaces_ids: HashMap::new(),
+            #[cfg(target_os = "linux")]
+            connection: None,
+        }
+    }
+
+    pub fn is_enabled(&self, event: &str) -> bool {
+        cfg!(target_os = "linux") && self.events.iter().any(|enabled| enabled == event)
+    }
+
+    // Send notification if the event is enabled
+    pub fn send(&mut self, event: &str, summary: &str, body: &str) {
+        if self.is_enabled(event) {
+            self.notify(event, summary, body, "");
+        }
+    }
+
+    // Send track notification with the album cover as image
+    pub fn send_track(&mut self, summary: &str, body: &str, cover_url: &str) {
+        if !self.is_enabled("track") {
+            return;
+        }
+
+        let image = match self.download_cover(cover_url) {
+            Some(cover_file) => cover_file.to_string_lossy().to_string(),
+            None => String::new(),
+        };
+        self.notify("track", summary, body, &image);
+    }
+
+    fn download_cover(&mut self, cover_url: &str) -> Option<PathBuf> {
+        if !cover_url.starts_with("http") {
+            return None;
+        }
+        if self.cover_url == cover_url {
+            return self.cover_file.clone();
+        }
+
+        let bytes = self
+            .client
+            .get(cover_url)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|res| res.bytes())
+            .ok()?;
+
+        // New file name for every cover, some notification servers cache images by path
+        let mut hasher = DefaultHasher::new();
+        cover_url.hash(&mut hasher);
+        let cover_file = self
+            .cache_dir
+            .join(format!("notification-cover-{:x}", hasher.finish()));
+        let _ = fs::create_dir_all(&self.cache_dir);
+        fs::write(&cover_file, bytes).ok()?;
+
+        if let Some(old_cover_file) = self.cover_file.replace(cover_file.clone()) {
+            let _ = fs::remove_file(old_cover_file);
+        }
+        self.cover_url = cover_url.to_string();
+        Some(cover_file)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn notify(&mut self, event: &str, summary: &str, body: &str, image: &str) {
+        if self.connection.is_none() {
+            self.connection = match Connection::new_session() {
+                Ok(connection) => Some(connection),
+                Err(err) => {
+                    warn!("Could not send notification: {}", err);
+                    return;
+                }
+            };
+        }
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        let mut hints = PropMap::new();
+        if !image.is_empty() {
+            hints.insert(
+                String::from("image-path"),
+                Variant(Box::new(image.to_string())),
+            );
+        }
+
+        let proxy = connection.with_proxy(
+            "org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            Duration::from_secs(2),
+        );
+        let replaces_id = self.replaces_ids.get(event).copied().unwrap_or(0);
+        let result: Result<(u32,), dbus::Error> = proxy.method_call(
+            "org.freedesktop.Notifications",
+            "Notify",
+            (
+                "Music Discord RPC",
+                replaces_id,
+                "media-playback-start",
+                summary,
+                body,
+                Vec::<String>::new(),
+                hints,
+                -1i32,
+            ),
+        );
+        match result {
+            Ok((id,)) => {
+                self.replaces_ids.insert(event.to_string(), id);
+            }
+            Err(err) => warn!("Could not send notification: {}", err),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn notify(&mut self, _event: &str, _summary: &str, _body: &str, _image: &str) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    // Cover server that answers after the delay
+    fn serve_cover(delay: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                thread::sleep(delay);
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\ncover",
+                );
+            }
+        });
+        format!("http://{}/cover.jpg", address)
+    }
+
+    #[test]
+    fn covers_are_downloaded_once() {
+        let cache_dir = std::env::temp_dir().join(format!("notifications-{}", std::process::id()));
+        let mut notifier = Notifier::new(&[], cache_dir.clone());
+        let cover_url = serve_cover(Duration::ZERO);
+
+        let cover_file = notifier.download_cover(&cover_url).unwrap();
+        assert_eq!(fs::read(&cover_file).unwrap(), b"cover");
+        assert_eq!(notifier.download_cover(&cover_url), Some(cover_file));
+        assert_eq!(notifier.download_cover("missing-cover"), None);
+        let _ = fs::remove_dir_all(cache_dir);
+    }
+
+    #[test]
+    fn slow_covers_are_skipped() {
+        let mut notifier = Notifier::new(&[], std::env::temp_dir());
+        let cover_url = serve_cover(COVER_TIMEOUT * 3);
+        let start = Instant::now();
+        assert_eq!(notifier.download_cover(&cover_url), None);
+        assert!(start.elapsed() < COVER_TIMEOUT * 2);
+    }
+}
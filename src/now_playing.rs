@@ -0,0 +1,163 @@
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::control::{SharedStatus, Status};
+use crate::http::{self, Request};
+use crate::settings;
+use crate::utils;
+
+// Overlay page for OBS browser sources, see README for the available options
+const OVERLAY_HTML: &str = include_str!("overlay.html");
+
+// Comment sent to keep idle event streams open through proxies
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+// Response of GET /now-playing and data of every /events message, also used by
+// the other outputs
+#[derive(Serialize)]
+pub struct NowPlaying {
+    pub active: bool,
+    pub player: String,
+    pub player_id: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub is_playing: bool,
+    pub cover_url: String,
+    pub cover_source: String,
+    pub path: String,
+    pub duration: u64,
+    pub position: u64,
+    pub progress: f64,
+    pub presence_paused: bool,
+}
+
+impl NowPlaying {
+    pub fn new(status: &Status) -> NowPlaying {
+        // Position is only updated with the activity, count the time that passed since then
+        let mut position = status.position;
+        if status.is_playing && status.position_updated > 0 {
+            position += utils::unix_time().saturating_sub(status.position_updated);
+        }
+        if status.duration > 0 {
+            position = position.min(status.duration);
+        }
+
+        NowPlaying {
+            active: !status.title.is_empty(),
+            player: status.player.clone(),
+            player_id: status.player_id.clone(),
+            title: status.title.clone(),
+            artist: status.artist.clone(),
+            album: status.album.clone(),
+            is_playing: status.is_playing,
+            cover_url: match status.cover_url.as_str() {
+                "missing-cover" => String::new(),
+                url => url.to_string(),
+            },
+            cover_source: status.cover_source.clone(),
+            path: status.path.clone(),
+            duration: status.duration,
+            position,
+            progress: match status.duration {
+                0 => 0.0,
+                duration => position as f64 / duration as f64,
+            },
+            presence_paused: status.presence_paused,
+        }
+    }
+
+    pub fn status(&self) -> &'static str {
+        match (self.active, self.is_playing) {
+            (false, _) => "stopped",
+            (true, true) => "playing",
+            (true, false) => "paused",
+        }
+    }
+}
+
+pub fn current(status: &SharedStatus) -> Status {
+    match status.lock() {
+        Ok(status) => status.clone(),
+        Err(_) => Status::default(),
+    }
+}
+
+// Serve the current track for stream overlays on http://127.0.0.1:<port>
+pub fn start(port: u16, status: SharedStatus) {
+    let address = format!("127.0.0.1:{}", port);
+    http::serve("now-playing", &address, move |request, stream| {
+        handle_request(request, stream, &status)
+    });
+}
+
+fn handle_request(request: Request, stream: TcpStream, status: &SharedStatus) {
+    if request.method != "GET" {
+        return http::method_not_allowed(stream);
+    }
+
+    match request.path.as_str() {
+        "/" | "/overlay" => http::respond(
+            stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            OVERLAY_HTML.as_bytes(),
+        ),
+        "/overlay.css" => http::respond(
+            stream,
+            "200 OK",
+            "text/css; charset=utf-8",
+            &read_user_css(),
+        ),
+        "/now-playing" => {
+            let body =
+                serde_json::to_string(&NowPlaying::new(&current(status))).unwrap_or_default();
+            http::respond(stream, "200 OK", "application/json", body.as_bytes())
+        }
+        "/events" => stream_events(stream, status),
+        _ => http::not_found(stream),
+    }
+}
+
+// Custom styles for the overlay, saved next to the config file
+fn read_user_css() -> Vec<u8> {
+    let css_file = match settings::get_config_file() {
+        Some(config_file) => config_file.with_file_name("overlay.css"),
+        None => return Vec::new(),
+    };
+    fs::read(css_file).unwrap_or_default()
+}
+
+// Server-sent events with the current track, sent again every time it changes
+fn stream_events(mut stream: TcpStream, status: &SharedStatus) {
+    if !http::start_stream(&mut stream, "text/event-stream") {
+        return;
+    }
+
+    let mut last_status = None;
+    let mut last_message = Instant::now();
+    loop {
+        let current_status = current(status);
+        let message = if last_status.as_ref() != Some(&current_status) {
+            let data = serde_json::to_string(&NowPlaying::new(&current_status)).unwrap_or_default();
+            last_status = Some(current_status);
+            format!("data: {}\n\n", data)
+        } else if last_message.elapsed() >= KEEP_ALIVE_INTERVAL {
+            String::from(": keep-alive\n\n")
+        } else {
+            String::new()
+        };
+
+        if !message.is_empty() {
+            if stream.write_all(message.as_bytes()).is_err() {
+                break;
+            }
+            last_message = Instant::now();
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
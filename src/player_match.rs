@@ -0,0 +1,185 @@
+use regex::Regex;
+
+#[cfg(target_os = "linux")]
+use mpris::Player;
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+// Entry of the allowlist, blocklist or video_players, e.g. "Chrome",
+// "bus:firefox.*" or "desktop:/^(chromium|chrome)$/"
+pub struct PlayerPattern {
+    target: Target,
+    pattern: Pattern,
+}
+
+#[derive(PartialEq)]
+enum Target {
+    Identity,
+    BusName,
+    DesktopEntry,
+}
+
+enum Pattern {
+    Exact(String),
+    // Globs are turned into regular expressions too
+    Regex(Regex),
+}
+
+impl PlayerPattern {
+    // "[identity:|bus:|desktop:]pattern", the pattern is matched exactly
+    // (ignoring case), as a glob if it contains * or ?, or as a regular
+    // expression between slashes
+    pub fn parse(entry: &str) -> Result<PlayerPattern, String> {
+        let (target, pattern) = match entry.split_once(':') {
+            Some(("identity", pattern)) => (Target::Identity, pattern),
+            Some(("bus", pattern)) => (Target::BusName, pattern),
+            Some(("desktop", pattern)) => (Target::DesktopEntry, pattern),
+            _ => (Target::Identity, entry),
+        };
+
+        let regex = if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') {
+            Some(pattern[1..pattern.len() - 1].to_string())
+        } else if pattern.contains(['*', '?']) {
+            let regex = regex::escape(pattern)
+                .replace("\\*", ".*")
+                .replace("\\?", ".");
+            Some(format!("(?i)^{}$", regex))
+        } else {
+            None
+        };
+
+        let pattern = match regex {
+            Some(regex) => match Regex::new(&regex) {
+                Ok(regex) => Pattern::Regex(regex),
+                Err(err) => return Err(format!("invalid pattern: {}", err)),
+            },
+            None => Pattern::Exact(pattern.to_string()),
+        };
+        Ok(PlayerPattern { target, pattern })
+    }
+
+    // Bus name and desktop entry are only known for MPRIS players
+    pub fn matches(&self, identity: &str, bus_name: &str, desktop_entry: &str) -> bool {
+        match self.target {
+            Target::Identity => self.matches_value(identity),
+            // Both the full name and the part after the MPRIS prefix, e.g. "firefox.instance_1_84"
+            Target::BusName => {
+                !bus_name.is_empty()
+                    && (self.matches_value(bus_name)
+                        || bus_name
+                            .strip_prefix(MPRIS_PREFIX)
+                            .is_some_and(|name| self.matches_value(name)))
+            }
+            Target::DesktopEntry => !desktop_entry.is_empty() && self.matches_value(desktop_entry),
+        }
+    }
+
+    fn matches_value(&self, value: &str) -> bool {
+        match &self.pattern {
+            Pattern::Exact(pattern) => pattern.to_lowercase() == value.to_lowercase(),
+            Pattern::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+// Patterns from settings, entries were already validated when they were loaded
+pub fn parse_all(entries: &[String]) -> Vec<PlayerPattern> {
+    entries
+        .iter()
+        .filter_map(|entry| PlayerPattern::parse(entry).ok())
+        .collect()
+}
+
+// Index of the first pattern matching the player
+#[cfg(target_os = "linux")]
+pub fn find(patterns: &[PlayerPattern], player: &Player) -> Option<usize> {
+    // Reading the desktop entry is another D-Bus call
+    let desktop_entry = match patterns
+        .iter()
+        .any(|pattern| pattern.target == Target::DesktopEntry)
+    {
+        true => player
+            .get_desktop_entry()
+            .ok()
+            .flatten()
+            .unwrap_or_default(),
+        false => String::new(),
+    };
+    patterns
+        .iter()
+        .position(|pattern| pattern.matches(player.identity(), player.bus_name(), &desktop_entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(entry: &str, identity: &str, bus_name: &str, desktop_entry: &str) -> bool {
+        PlayerPattern::parse(entry)
+            .unwrap()
+            .matches(identity, bus_name, desktop_entry)
+    }
+
+    #[test]
+    fn exact_names_ignore_case() {
+        assert!(matches("VLC Media Player", "VLC media player", "", ""));
+        assert!(matches("identity:spotify", "Spotify", "", ""));
+        assert!(!matches("Chrome", "Chromium", "", ""));
+    }
+
+    #[test]
+    fn globs() {
+        assert!(matches("Chrom*", "chromium", "", ""));
+        assert!(matches("mp?", "MPV", "", ""));
+        assert!(!matches("mp?", "mpd2", "", ""));
+        // Other regex characters are literal
+        assert!(matches("a.b*", "a.bc", "", ""));
+        assert!(!matches("a.b*", "axbc", "", ""));
+    }
+
+    #[test]
+    fn regular_expressions() {
+        assert!(matches("/^Chrom(e|ium)$/", "Chromium", "", ""));
+        assert!(!matches("/^Chrom(e|ium)$/", "chromium", "", ""));
+        assert!(matches("/(?i)^chrom(e|ium)$/", "Chromium", "", ""));
+        assert!(matches("/^(mpv|vlc){1,2}$/", "mpvvlc", "", ""));
+        assert!(PlayerPattern::parse("/(/")
+            .err()
+            .unwrap()
+            .starts_with("invalid pattern: "));
+    }
+
+    #[test]
+    fn bus_names() {
+        let bus_name = "org.mpris.MediaPlayer2.firefox.instance_1_84";
+        assert!(matches("bus:firefox.instance*", "Firefox", bus_name, ""));
+        assert!(matches(
+            "bus:org.mpris.MediaPlayer2.firefox.*",
+            "Firefox",
+            bus_name,
+            ""
+        ));
+        assert!(matches(
+            "bus:firefox.instance_1_84",
+            "Firefox",
+            bus_name,
+            ""
+        ));
+        assert!(!matches("bus:firefox", "Firefox", bus_name, ""));
+        // Not known outside of MPRIS
+        assert!(!matches("bus:*", "Firefox", "", ""));
+    }
+
+    #[test]
+    fn desktop_entries() {
+        assert!(matches(
+            "desktop:org.kde.kdeconnect*",
+            "Phone",
+            "",
+            "org.kde.kdeconnect.app"
+        ));
+        assert!(!matches("desktop:vlc", "vlc", "", ""));
+        // Unknown prefixes are part of the name
+        assert!(matches("Player: Live", "player: live", "", ""));
+    }
+}
@@ -0,0 +1,313 @@
+use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::blocking::Connection;
+use dbus::message::{MatchRule, SignalArgs};
+use log::{debug, error};
+use mpris::{FindingError, PlaybackStatus, Player, PlayerFinder};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::Event;
+use crate::player_match::{self, PlayerPattern};
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+// Keeps track of all MPRIS players, so the presented one can be replaced as
+// soon as the player policy prefers another one
+pub struct Tracker {
+    // Bus name of the presented player
+    current: Option<String>,
+    // Players that are playing now and when they started
+    started: HashMap<String, Instant>,
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        Tracker {
+            current: None,
+            started: HashMap::new(),
+        }
+    }
+
+    // Player to present: the preferred one first, then the one chosen by the
+    // policy from the allowlist (in its order) or from all players that are
+    // not on the blocklist
+    pub fn select(
+        &mut self,
+        finder: &PlayerFinder,
+        policy: &str,
+        preferred_player: Option<&str>,
+        allowlist: &[PlayerPattern],
+        blocklist: &[PlayerPattern],
+    ) -> Result<Player, FindingError> {
+        if let Some(preferred_player) = preferred_player {
+            if let Ok(player) = finder.find_by_name(preferred_player) {
+                self.current = Some(player.bus_name().to_string());
+                return Ok(player);
+            }
+        }
+
+        let mut players = finder.find_all()?;
+        players.retain(|player| player_match::find(blocklist, player).is_none());
+        if !allowlist.is_empty() {
+            let mut positions: Vec<(Player, usize)> = players
+                .into_iter()
+                .filter_map(|player| {
+                    let position = player_match::find(allowlist, &player)?;
+                    Some((player, position))
+                })
+                .collect();
+            positions.sort_by_key(|(_, position)| *position);
+            players = positions.into_iter().map(|(player, _)| player).collect();
+        }
+
+        let statuses: Vec<PlaybackStatus> = players
+            .iter()
+            .map(|player| {
+                player
+                    .get_playback_status()
+                    .unwrap_or(PlaybackStatus::Stopped)
+            })
+            .collect();
+        self.update_started(&players, &statuses);
+
+        let current = self.current.as_deref().and_then(|bus_name| {
+            players
+                .iter()
+                .position(|player| player.bus_name() == bus_name)
+        });
+        let started: Vec<Option<Instant>> = players
+            .iter()
+            .map(|player| self.started.get(player.bus_name()).copied())
+            .collect();
+        let index = choose(policy, &statuses, &started, current, !allowlist.is_empty());
+
+        match index {
+            Some(index) => {
+                let player = players.swap_remove(index);
+                self.current = Some(player.bus_name().to_string());
+                Ok(player)
+            }
+            None => Err(FindingError::NoPlayerFound),
+        }
+    }
+
+    // True if the policy now chooses another player than the presented one
+    pub fn changed(
+        &mut self,
+        finder: &PlayerFinder,
+        policy: &str,
+        preferred_player: Option<&str>,
+        allowlist: &[PlayerPattern],
+        blocklist: &[PlayerPattern],
+    ) -> bool {
+        let current = self.current.clone();
+        match self.select(finder, policy, preferred_player, allowlist, blocklist) {
+            Ok(player) => {
+                let is_changed = current.as_deref() != Some(player.bus_name());
+                if is_changed {
+                    debug!(
+                        "Player policy \"{}\" prefers {} now.",
+                        policy,
+                        player.identity()
+                    );
+                }
+                is_changed
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn update_started(&mut self, players: &[Player], statuses: &[PlaybackStatus]) {
+        let now = Instant::now();
+        let mut started = HashMap::new();
+        for (player, status) in players.iter().zip(statuses) {
+            if *status == PlaybackStatus::Playing {
+                let bus_name = player.bus_name().to_string();
+                let since = self.started.get(&bus_name).copied().unwrap_or(now);
+                started.insert(bus_name, since);
+            }
+        }
+        self.started = started;
+    }
+}
+
+// Index of the player chosen by the policy. Players are in the allowlist
+// order, started tells when each playing one started to play.
+fn choose(
+    policy: &str,
+    statuses: &[PlaybackStatus],
+    started: &[Option<Instant>],
+    current: Option<usize>,
+    has_allowlist: bool,
+) -> Option<usize> {
+    let is_playing = |index: &usize| statuses[*index] == PlaybackStatus::Playing;
+    let is_paused = |index: &usize| statuses[*index] == PlaybackStatus::Paused;
+    let first_playing = (0..statuses.len()).find(is_playing);
+    // Same order as PlayerFinder::find_active
+    let first_active = first_playing
+        .or((0..statuses.len()).find(is_paused))
+        .or((!statuses.is_empty()).then_some(0));
+
+    match policy {
+        "sticky" => current.or(first_active),
+        "recent" => (0..statuses.len())
+            .filter(is_playing)
+            .max_by_key(|index| (started[*index], Some(*index) == current))
+            .or(current)
+            .or(first_active),
+        "playing" => current
+            .filter(is_playing)
+            .or(first_playing)
+            .or(current)
+            .or(first_active),
+        _ => match has_allowlist {
+            true => (!statuses.is_empty()).then_some(0),
+            false => first_active,
+        },
+    }
+}
+
+// Wake up the main loop when any player changes its state or appears on or
+// disappears from the session bus, instead of waiting for the interval
+pub fn watch(events: Sender<Event>) {
+    thread::spawn(move || {
+        if let Err(err) = listen(events) {
+            error!("Could not watch MPRIS players: {}", err);
+        }
+    });
+}
+
+fn listen(events: Sender<Event>) -> Result<(), dbus::Error> {
+    let connection = Connection::new_session()?;
+
+    let sender = events.clone();
+    let properties_changed =
+        PropertiesPropertiesChanged::match_rule(None, Some(&"/org/mpris/MediaPlayer2".into()))
+            .static_clone();
+    connection.add_match(
+        properties_changed,
+        move |changed: PropertiesPropertiesChanged, _: &Connection, _: &dbus::Message| {
+            changed.interface_name != "org.mpris.MediaPlayer2.Player"
+                || sender.send(Event::PlayerChanged).is_ok()
+        },
+    )?;
+
+    let name_owner_changed = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+        .with_sender("org.freedesktop.DBus");
+    connection.add_match(
+        name_owner_changed,
+        move |(name, _, _): (String, String, String), _: &Connection, _: &dbus::Message| {
+            !name.starts_with(MPRIS_PREFIX) || events.send(Event::PlayerChanged).is_ok()
+        },
+    )?;
+
+    loop {
+        connection.process(Duration::from_secs(60))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PlaybackStatus::{Paused, Playing, Stopped};
+
+    fn choose_without_times(
+        policy: &str,
+        statuses: &[PlaybackStatus],
+        current: Option<usize>,
+    ) -> Option<usize> {
+        choose(
+            policy,
+            statuses,
+            &vec![None; statuses.len()],
+            current,
+            false,
+        )
+    }
+
+    #[test]
+    fn allowlist_policy() {
+        // Without an allowlist the first playing, then the first paused player
+        assert_eq!(
+            choose_without_times("allowlist", &[Stopped, Paused, Playing], None),
+            Some(2)
+        );
+        assert_eq!(
+            choose_without_times("allowlist", &[Stopped, Paused], None),
+            Some(1)
+        );
+        assert_eq!(
+            choose_without_times("allowlist", &[Stopped, Stopped], Some(1)),
+            Some(0)
+        );
+        // With an allowlist always its first running player
+        let started = [None, None];
+        assert_eq!(
+            choose("allowlist", &[Stopped, Playing], &started, None, true),
+            Some(0)
+        );
+        assert_eq!(choose("allowlist", &[], &[], None, true), None);
+    }
+
+    #[test]
+    fn recent_policy() {
+        let now = Instant::now();
+        let earlier = now - Duration::from_secs(60);
+        let statuses = [Playing, Paused, Playing];
+        let started = [Some(earlier), None, Some(now)];
+        assert_eq!(
+            choose("recent", &statuses, &started, Some(0), false),
+            Some(2)
+        );
+        // Nothing plays, stay with the current player
+        assert_eq!(
+            choose_without_times("recent", &[Paused, Paused], Some(1)),
+            Some(1)
+        );
+        // Started at the same time, keep the current one
+        let started = [Some(now), Some(now)];
+        assert_eq!(
+            choose("recent", &[Playing, Playing], &started, Some(0), false),
+            Some(0)
+        );
+        assert_eq!(
+            choose("recent", &[Playing, Playing], &started, Some(1), false),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn playing_policy() {
+        assert_eq!(
+            choose_without_times("playing", &[Playing, Playing], Some(1)),
+            Some(1)
+        );
+        assert_eq!(
+            choose_without_times("playing", &[Playing, Paused], Some(1)),
+            Some(0)
+        );
+        assert_eq!(
+            choose_without_times("playing", &[Paused, Paused], Some(1)),
+            Some(1)
+        );
+        assert_eq!(
+            choose_without_times("playing", &[Stopped, Paused], None),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn sticky_policy() {
+        assert_eq!(
+            choose_without_times("sticky", &[Playing, Paused], Some(1)),
+            Some(1)
+        );
+        assert_eq!(
+            choose_without_times("sticky", &[Paused, Playing], None),
+            Some(1)
+        );
+        assert_eq!(choose_without_times("sticky", &[], None), None);
+    }
+}
@@ -0,0 +1,867 @@
+use chrono::{DateTime, Local};
+use discord_rich_presence::activity::{self, StatusDisplayType};
+use log::debug;
+use pickledb::PickleDb;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process;
+use std::sync::mpsc;
+
+#[cfg(target_os = "linux")]
+use mpris::PlayerFinder;
+
+use crate::filter;
+use crate::metrics;
+#[cfg(target_os = "linux")]
+use crate::players;
+use crate::settings::{Cli, Options};
+use crate::source::{self, Source};
+use crate::utils::{self, MediaInfo};
+
+// Activity sent to Discord. Built separately from the Discord types, so it
+// can also be printed by the preview command.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Presence {
+    pub client_id: String,
+    pub activity_type: String,
+    pub status_display_type: String,
+    pub details: String,
+    pub details_url: String,
+    pub state: Option<String>,
+    pub large_image: String,
+    pub large_text: Option<String>,
+    pub small_image: Option<String>,
+    pub small_text: Option<String>,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    pub buttons: Vec<Button>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Button {
+    pub label: String,
+    pub url: String,
+}
+
+// Player the metadata comes from, after --force-player-name and --force-player-id
+pub struct PlayerInfo {
+    pub name: String,
+    pub id: String,
+    pub is_video: bool,
+}
+
+// Input of "preview --from-json", the same fields as MediaInfo and the player name
+#[derive(Deserialize)]
+struct PreviewInput {
+    #[serde(default)]
+    player: String,
+    #[serde(flatten)]
+    media_info: MediaInfo,
+}
+
+// Find album cover, providers are tried in the order set by the user.
+// Returns the cover url (or "missing-cover") and where it comes from.
+pub fn fetch_cover(
+    media_info: &MediaInfo,
+    album_id: &str,
+    options: &Options,
+    album_cache: &mut PickleDb,
+) -> (String, String) {
+    let is_cached = options.cache_enabled && album_cache.exists(album_id);
+    let mut cover_url = "missing-cover".to_string();
+    let mut cover_source = String::new();
+    for provider in &options.cover_providers {
+        if !cover_url.is_empty() && cover_url != "missing-cover" {
+            break;
+        }
+
+        match provider.as_str() {
+            "lastfm" => {
+                if options.lastfm_api_key.is_empty() {
+                    continue;
+                }
+
+                cover_url = utils::get_cover_url(
+                    album_id,
+                    media_info.album.as_str(),
+                    cover_url,
+                    options.cache_enabled,
+                    album_cache,
+                    media_info.album_artist.as_str(),
+                    &options.lastfm_api_key,
+                );
+
+                // Fallback for Apple Music for album names with " - EP" and " - Single"
+                if cover_url.is_empty() || cover_url == "missing-cover" {
+                    let album_name = media_info.album.trim();
+                    let album_name_without_suffix = album_name
+                        .strip_suffix(" - EP")
+                        .or(album_name.strip_suffix(" - Single"))
+                        .unwrap_or("");
+
+                    if !album_name_without_suffix.is_empty() {
+                        debug!(
+                            "Album cover not found, attempting to use album name without the 'EP' or 'Single' suffix (Apple Music)."
+                        );
+                        debug!("{} => {}", album_name, album_name_without_suffix);
+
+                        cover_url = utils::get_cover_url(
+                            album_id,
+                            album_name_without_suffix,
+                            cover_url,
+                            options.cache_enabled,
+                            album_cache,
+                            media_info.album_artist.as_str(),
+                            &options.lastfm_api_key,
+                        );
+                    }
+                }
+            }
+            "musicbrainz" => {
+                cover_url = utils::get_cover_url_musicbrainz(
+                    album_id,
+                    media_info.album.as_str(),
+                    cover_url,
+                    options.cache_enabled,
+                    album_cache,
+                    media_info.album_artist.as_str(),
+                    media_info.musicbrainz_album_id.as_str(),
+                );
+            }
+            // Cover url of the media server, never cached
+            "server" if media_info.art_url.starts_with("http") => {
+                cover_url = media_info.art_url.clone();
+            }
+            _ => {}
+        }
+
+        if !cover_url.is_empty() && cover_url != "missing-cover" {
+            cover_source = match is_cached && provider != "server" {
+                true => String::from("cache"),
+                false => provider.clone(),
+            };
+        }
+    }
+
+    // One lookup per album, whichever provider found the cover
+    match cover_source.is_empty() {
+        true => metrics::cover_lookup("none", false),
+        false => metrics::cover_lookup(&cover_source, true),
+    }
+    (cover_url, cover_source)
+}
+
+// Image displayed as the album cover, the art url of the player is used
+// when no provider found the cover. Returns the image and its source.
+pub fn select_image(
+    cover_url: &str,
+    cover_source: &str,
+    media_info: &MediaInfo,
+    settings: &Cli,
+) -> (String, String) {
+    if !cover_url.is_empty() && cover_url != "missing-cover" {
+        return (cover_url.to_string(), cover_source.to_string());
+    }
+    if media_info.art_url.starts_with("http") && !settings.disable_mpris_art_url {
+        return (media_info.art_url.clone(), String::from("mpris"));
+    }
+    ("missing-cover".to_string(), String::new())
+}
+
+// Unix time of track start if supported, else time now
+pub fn get_time_start(media_info: &MediaInfo) -> u64 {
+    utils::unix_time().saturating_sub(media_info.position)
+}
+
+// Create the activity from metadata and settings
+pub fn build(
+    media_info: &MediaInfo,
+    image: &str,
+    player: &PlayerInfo,
+    time_start: u64,
+    settings: &Cli,
+    options: &Options,
+) -> Presence {
+    let mut presence = Presence {
+        client_id: match player.is_video {
+            true => options.video_client_id.clone(),
+            false => options.client_id.clone(),
+        },
+        activity_type: match player.is_video {
+            true => String::from("watching"),
+            false => String::from("listening"),
+        },
+        large_image: image.to_string(),
+        ..Default::default()
+    };
+
+    let song_name: String = format!("{} - {}", media_info.artist, media_info.title);
+    presence.details = if media_info.title.len() > 1 {
+        media_info.title.clone()
+    } else {
+        format!("{} ", media_info.title) // Discord activity min 2 char len bug fix
+    };
+    let artist = match options.rpc_name.as_str() {
+        "artist" => {
+            if media_info.artist.len() > 1 {
+                media_info.artist.clone()
+            } else {
+                format!("{} ", media_info.artist) // Discord activity min 2 char len bug fix
+            }
+        }
+        _ => format!("by: {}", media_info.artist),
+    };
+    let status_text: String = if media_info.is_playing {
+        "playing".to_string()
+    } else {
+        "paused".to_string()
+    };
+
+    if !settings.hide_album_name && !media_info.album.is_empty() {
+        presence.large_text = Some(format!("album: {}", media_info.album));
+    }
+
+    // Icon displayed next to the album cover
+    let mut small_image = match options.small_image.as_str() {
+        "player" => {
+            if !settings.disable_mpris_art_url && image.contains("ytimg.com/") {
+                Some((String::from("youtube"), String::from("YouTube")))
+            } else {
+                Some((player.id.clone(), player.name.clone()))
+            }
+        }
+        "lastfmAvatar" => {
+            if !options.lastfm_avatar.is_empty() {
+                Some((
+                    options.lastfm_avatar.clone(),
+                    options.lastfm_icon_text.clone(),
+                ))
+            } else {
+                None
+            }
+        }
+        "none" => None,
+        _ => Some((status_text.clone(), status_text.clone())),
+    };
+
+    // Display paused icon anyway if playpack is paused or stopped
+    if status_text != "playing" {
+        small_image = Some((status_text.clone(), status_text.clone()));
+    }
+    if let Some((small_image, small_text)) = small_image {
+        presence.small_image = Some(small_image);
+        presence.small_text = Some(small_text);
+    }
+
+    // "Listening to ..."
+    presence.status_display_type = match options.rpc_name.as_str() {
+        "none" => String::from("name"),
+        "track" => String::from("details"),
+        _ => String::from("state"),
+    };
+
+    // Don't display Unknown Artist for videos
+    if !(player.is_video && (artist.to_lowercase() == "by: unknown artist")
+        || artist.to_lowercase() == "unknown artist")
+    {
+        presence.state = Some(artist);
+    }
+
+    let time_start = time_start as i64;
+    if media_info.is_track_position && (media_info.duration > 0) {
+        presence.start = Some(time_start);
+        if media_info.is_playing {
+            presence.end = Some(time_start + media_info.duration as i64);
+        }
+    } else {
+        presence.end = Some(time_start);
+    }
+
+    // Create urls for activity links
+    let yt_url: String = format!(
+        "https://www.youtube.com/results?search_query={}",
+        url_escape::encode_component(&song_name)
+    );
+    let lastfm_url: String = format!(
+        "https://www.last.fm/user/{}",
+        url_escape::encode_component(&options.lastfm_name)
+    );
+    let listenbrainz_url: String = format!(
+        "https://listenbrainz.org/user/{}/",
+        url_escape::encode_component(&options.listenbrainz_name)
+    );
+
+    // Add YouTube URL to song title
+    presence.details_url = yt_url.clone();
+
+    // Add activity buttons
+    let button = |label: &str, url: &str| Button {
+        label: label.to_string(),
+        url: url.to_string(),
+    };
+    let mut first_button = "";
+    for name in &settings.button {
+        let initial_len = presence.buttons.len();
+        if initial_len == 2 {
+            break;
+        }
+
+        // Make sure buttons wont repeat
+        if initial_len > 0 && first_button == name {
+            continue;
+        }
+
+        match name.as_str() {
+            "yt" => {
+                presence
+                    .buttons
+                    .push(button("Search this song on YouTube", &yt_url));
+            }
+            "lastfm" => {
+                if !options.lastfm_name.is_empty() {
+                    presence
+                        .buttons
+                        .push(button("Last.fm profile", &lastfm_url));
+                }
+            }
+            "listenbrainz" => {
+                if !options.listenbrainz_name.is_empty() {
+                    presence
+                        .buttons
+                        .push(button("Listenbrainz profile", &listenbrainz_url));
+                }
+            }
+            "mprisUrl" => {
+                if media_info.url.is_empty() {
+                    // if mpris url is empty or not set convert button to yt button
+                    presence
+                        .buttons
+                        .push(button("Search this song on YouTube", &yt_url));
+                } else if player.is_video {
+                    presence.buttons.push(button("Watch Now", &media_info.url));
+                } else {
+                    presence.buttons.push(button("Play Now", &media_info.url));
+                }
+            }
+            "shamelessAd" => {
+                presence.buttons.push(button(
+                    "Get This RPC",
+                    "https://github.com/patryk-ku/music-discord-rpc",
+                ));
+            }
+            _ => continue,
+        }
+
+        // Make sure buttons wont repeat
+        if initial_len < presence.buttons.len() {
+            first_button = name;
+        }
+    }
+
+    presence
+}
+
+impl Presence {
+    // Payload for the Discord client
+    pub fn to_activity(&self) -> activity::Activity<'_> {
+        let mut assets = activity::Assets::new().large_image(&self.large_image);
+        if let Some(large_text) = &self.large_text {
+            assets = assets.large_text(large_text);
+        }
+        if let Some(small_image) = &self.small_image {
+            assets = assets.small_image(small_image);
+        }
+        if let Some(small_text) = &self.small_text {
+            assets = assets.small_text(small_text);
+        }
+
+        let mut timestamps = activity::Timestamps::new();
+        if let Some(start) = self.start {
+            timestamps = timestamps.start(start);
+        }
+        if let Some(end) = self.end {
+            timestamps = timestamps.end(end);
+        }
+
+        let mut payload = activity::Activity::new()
+            .details(&self.details)
+            .details_url(&self.details_url)
+            .assets(assets)
+            .timestamps(timestamps)
+            .activity_type(match self.activity_type.as_str() {
+                "watching" => activity::ActivityType::Watching,
+                _ => activity::ActivityType::Listening,
+            })
+            .status_display_type(match self.status_display_type.as_str() {
+                "name" => StatusDisplayType::Name,
+                "details" => StatusDisplayType::Details,
+                _ => StatusDisplayType::State,
+            });
+
+        if let Some(state) = &self.state {
+            payload = payload.state(state);
+        }
+        if !self.buttons.is_empty() {
+            payload = payload.buttons(
+                self.buttons
+                    .iter()
+                    .map(|button| activity::Button::new(&button.label, &button.url))
+                    .collect(),
+            );
+        }
+        payload
+    }
+
+    // Text printed by the preview command
+    fn to_text(&self) -> String {
+        let or_none = |value: &Option<String>| match value {
+            Some(value) => value.clone(),
+            None => String::from("-"),
+        };
+        let time = |timestamp: Option<i64>| match timestamp {
+            Some(timestamp) => match DateTime::from_timestamp(timestamp, 0) {
+                Some(time) => format!(
+                    "{} ({})",
+                    timestamp,
+                    time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+                ),
+                None => timestamp.to_string(),
+            },
+            None => String::from("-"),
+        };
+
+        let mut lines = vec![
+            format!("Client ID:   {}", self.client_id),
+            format!(
+                "Type:        {} (name shown after it: {})",
+                self.activity_type, self.status_display_type
+            ),
+            format!("Details:     {}", self.details),
+            format!("Details URL: {}", self.details_url),
+            format!("State:       {}", or_none(&self.state)),
+            format!("Large image: {}", self.large_image),
+            format!("Large text:  {}", or_none(&self.large_text)),
+            format!("Small image: {}", or_none(&self.small_image)),
+            format!("Small text:  {}", or_none(&self.small_text)),
+            format!("Start:       {}", time(self.start)),
+            format!("End:         {}", time(self.end)),
+        ];
+        if self.buttons.is_empty() {
+            lines.push(String::from("Buttons:     -"));
+        }
+        for (index, button) in self.buttons.iter().enumerate() {
+            lines.push(format!(
+                "{} {} ({})",
+                if index == 0 {
+                    "Buttons:    "
+                } else {
+                    "            "
+                },
+                button.label,
+                button.url
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+// Read the player once, print the activity that would be sent and exit
+pub fn preview(
+    settings: &Cli,
+    options: &Options,
+    album_cache: &mut PickleDb,
+    from_json: Option<&Path>,
+    json: bool,
+) -> ! {
+    let (player_name, media_info, is_video) = match from_json {
+        Some(input) => {
+            let (player_name, media_info) = read_preview_input(input);
+            let is_video = options
+                .video_players
+                .iter()
+                .any(|pattern| pattern.matches(&player_name, "", ""));
+            (player_name, media_info, is_video)
+        }
+        None => {
+            let mut source = match source::new(settings, mpsc::channel().0) {
+                Some(source) => source,
+                None => read_player(settings, options),
+            };
+            match source.get_media_info() {
+                Ok(media_info) => (
+                    source.name(),
+                    media_info,
+                    source.is_video() || source.matches(&options.video_players),
+                ),
+                Err(err) => {
+                    println!("{}", err);
+                    process::exit(1);
+                }
+            }
+        }
+    };
+    #[cfg(target_os = "macos")]
+    let player_name = utils::app_name_from_bundle_id(player_name.as_str());
+    let player = PlayerInfo {
+        id: match options.force_player_id.is_empty() {
+            true => utils::sanitize_name(&player_name),
+            false => options.force_player_id.clone(),
+        },
+        name: match options.force_player_name.is_empty() {
+            true => player_name,
+            false => options.force_player_name.clone(),
+        },
+        is_video,
+    };
+
+    // Same checks as in the main loop
+    if media_info.artist.is_empty() || media_info.title.is_empty() {
+        println!("Missing artist or title, the activity would not be set.");
+        process::exit(1);
+    }
+    if settings.only_when_playing && !media_info.is_playing {
+        println!(
+            "Playback is paused and only_when_playing is enabled, the activity would be cleared."
+        );
+        process::exit(0);
+    }
+
+    let (media_info, is_album_hidden) = match filter::apply(
+        &options.filters,
+        media_info,
+        &player.name,
+        &options.filter_placeholder,
+    ) {
+        Some(filtered) => filtered,
+        None => {
+            println!("The track is hidden by a filter, the activity would be cleared.");
+            process::exit(0);
+        }
+    };
+
+    let album_id = format!("{} - {}", media_info.album_artist, media_info.album);
+    let (cover_url, cover_source) = match is_album_hidden {
+        true => (String::from("missing-cover"), String::new()),
+        false => fetch_cover(&media_info, &album_id, options, album_cache),
+    };
+    let (image, _) = select_image(&cover_url, &cover_source, &media_info, settings);
+    let presence = build(
+        &media_info,
+        &image,
+        &player,
+        get_time_start(&media_info),
+        settings,
+        options,
+    );
+
+    if json {
+        match serde_json::to_string_pretty(&presence) {
+            Ok(presence) => println!("{}", presence),
+            Err(err) => {
+                println!("Could not serialize activity: {}", err);
+                process::exit(1);
+            }
+        }
+    } else {
+        println!("{}", presence.to_text());
+    }
+    process::exit(0);
+}
+
+// MediaInfo from a file, or from stdin if the path is "-"
+fn read_preview_input(input: &Path) -> (String, MediaInfo) {
+    let mut json_str = String::new();
+    let result = match input.to_str() {
+        Some("-") => io::stdin().read_to_string(&mut json_str).map(|_| ()),
+        _ => fs::read_to_string(input).map(|content| json_str = content),
+    };
+    if let Err(err) = result {
+        println!("Could not read {}: {}", input.display(), err);
+        process::exit(1);
+    }
+
+    match parse_preview_input(&json_str) {
+        Ok(input) => input,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+// Player name and metadata, missing fields are empty
+fn parse_preview_input(json_str: &str) -> Result<(String, MediaInfo), String> {
+    match serde_json::from_str::<PreviewInput>(json_str) {
+        Ok(input) => Ok((input.player, input.media_info)),
+        Err(err) => Err(format!("Invalid metadata: {}", err)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_player(settings: &Cli, options: &Options) -> Box<dyn Source> {
+    let player = match PlayerFinder::new() {
+        Ok(finder) => players::Tracker::new().select(
+            &finder,
+            settings.player_policy.as_deref().unwrap_or_default(),
+            None,
+            &options.allowlist,
+            &options.blocklist,
+        ),
+        Err(err) => {
+            println!("Could not connect to D-Bus: {}", err);
+            process::exit(1);
+        }
+    };
+    let player = match player {
+        Ok(player) => player,
+        Err(_) => {
+            println!("No player detected.");
+            process::exit(1);
+        }
+    };
+
+    Box::new(source::Mpris(player))
+}
+
+#[cfg(target_os = "macos")]
+fn read_player(_settings: &Cli, options: &Options) -> Box<dyn Source> {
+    match utils::get_currently_playing() {
+        Ok(media_info) => {
+            let player = source::MediaControl {
+                player_id: media_info.player_id,
+            };
+            if (options.allowlist_enabled && !player.matches(&options.allowlist))
+                || player.matches(&options.blocklist)
+            {
+                println!("No player from your allowlist detected.");
+                process::exit(1);
+            }
+            Box::new(player)
+        }
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 1_760_000_000;
+
+    fn media_info() -> MediaInfo {
+        MediaInfo {
+            title: String::from("Paranoid Android"),
+            artist: String::from("Radiohead"),
+            album_artist: String::from("Radiohead"),
+            album: String::from("OK Computer"),
+            is_playing: true,
+            duration: 383,
+            position: 82,
+            is_track_position: true,
+            art_url: String::from("https://player.example.org/art.jpg"),
+            url: String::from("https://player.example.org/track/2"),
+            ..Default::default()
+        }
+    }
+
+    fn mpv() -> PlayerInfo {
+        PlayerInfo {
+            name: String::from("mpv"),
+            id: String::from("mpv"),
+            is_video: false,
+        }
+    }
+
+    fn presence(media_info: &MediaInfo, player: &PlayerInfo, settings: &Cli) -> Presence {
+        let options = Options::new(settings, false);
+        build(media_info, "cover", player, START, settings, &options)
+    }
+
+    #[test]
+    fn image_selection_order() {
+        let settings = Cli::default();
+        let media_info = media_info();
+        assert_eq!(
+            select_image(
+                "https://lastfm.example.org/cover.png",
+                "lastfm",
+                &media_info,
+                &settings
+            ),
+            (
+                String::from("https://lastfm.example.org/cover.png"),
+                String::from("lastfm")
+            )
+        );
+        assert_eq!(
+            select_image("missing-cover", "", &media_info, &settings),
+            (
+                String::from("https://player.example.org/art.jpg"),
+                String::from("mpris")
+            )
+        );
+
+        let settings = Cli {
+            disable_mpris_art_url: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            select_image("", "", &media_info, &settings),
+            (String::from("missing-cover"), String::new())
+        );
+        // Local files can't be shown by Discord
+        let local = MediaInfo {
+            art_url: String::from("file:///tmp/cover.jpg"),
+            ..self::media_info()
+        };
+        assert_eq!(
+            select_image("missing-cover", "", &local, &Cli::default()).0,
+            "missing-cover"
+        );
+    }
+
+    #[test]
+    fn fields_of_a_playing_track() {
+        let presence = presence(&media_info(), &mpv(), &Cli::default());
+        assert_eq!(presence.client_id, "1129859263741837373");
+        assert_eq!(presence.activity_type, "listening");
+        assert_eq!(presence.status_display_type, "state");
+        assert_eq!(presence.details, "Paranoid Android");
+        assert_eq!(
+            presence.details_url,
+            "https://www.youtube.com/results?search_query=Radiohead%20-%20Paranoid%20Android"
+        );
+        assert_eq!(presence.state.as_deref(), Some("Radiohead"));
+        assert_eq!(presence.large_image, "cover");
+        assert_eq!(presence.large_text.as_deref(), Some("album: OK Computer"));
+        assert_eq!(presence.small_image.as_deref(), Some("playing"));
+        assert_eq!(presence.start, Some(START as i64));
+        assert_eq!(presence.end, Some(START as i64 + 383));
+        assert!(presence.buttons.is_empty());
+    }
+
+    #[test]
+    fn paused_tracks_and_settings() {
+        let settings = Cli {
+            hide_album_name: true,
+            rpc_name: Some(String::from("track")),
+            small_image: Some(String::from("player")),
+            ..Default::default()
+        };
+        let paused = MediaInfo {
+            is_playing: false,
+            title: String::from("X"),
+            ..media_info()
+        };
+        let presence = presence(&paused, &mpv(), &settings);
+        assert_eq!(presence.details, "X ");
+        assert_eq!(presence.state.as_deref(), Some("by: Radiohead"));
+        assert_eq!(presence.status_display_type, "details");
+        assert_eq!(presence.large_text, None);
+        // The paused icon replaces the player icon
+        assert_eq!(presence.small_image.as_deref(), Some("paused"));
+        assert_eq!(presence.start, Some(START as i64));
+        assert_eq!(presence.end, None);
+
+        let playing = self::presence(&media_info(), &mpv(), &settings);
+        assert_eq!(playing.small_image.as_deref(), Some("mpv"));
+        assert_eq!(playing.small_text.as_deref(), Some("mpv"));
+    }
+
+    #[test]
+    fn videos() {
+        let player = PlayerInfo {
+            is_video: true,
+            ..mpv()
+        };
+        let video = MediaInfo {
+            artist: String::from("Unknown Artist"),
+            is_track_position: false,
+            ..media_info()
+        };
+        let presence = presence(&video, &player, &Cli::default());
+        assert_eq!(presence.activity_type, "watching");
+        assert_eq!(presence.client_id, "1356756023813210293");
+        assert_eq!(presence.state, None);
+        // Without a position only the elapsed time is shown
+        assert_eq!(presence.start, None);
+        assert_eq!(presence.end, Some(START as i64));
+    }
+
+    #[test]
+    fn buttons() {
+        let settings = Cli {
+            button: ["mprisUrl", "yt", "mprisUrl", "shamelessAd"]
+                .map(String::from)
+                .to_vec(),
+            ..Default::default()
+        };
+        let presence = presence(&media_info(), &mpv(), &settings);
+        let labels: Vec<&str> = presence
+            .buttons
+            .iter()
+            .map(|button| button.label.as_str())
+            .collect();
+        assert_eq!(labels, ["Play Now", "Search this song on YouTube"]);
+        assert_eq!(
+            presence.buttons[0].url,
+            "https://player.example.org/track/2"
+        );
+
+        // Profile buttons need the user name, the same button is not added twice
+        let settings = Cli {
+            button: ["lastfm", "yt", "yt", "shamelessAd"]
+                .map(String::from)
+                .to_vec(),
+            ..settings
+        };
+        let presence = self::presence(&media_info(), &mpv(), &settings);
+        let labels: Vec<&str> = presence
+            .buttons
+            .iter()
+            .map(|button| button.label.as_str())
+            .collect();
+        assert_eq!(labels, ["Search this song on YouTube", "Get This RPC"]);
+    }
+
+    #[test]
+    fn preview_output() {
+        let (player, media_info) = parse_preview_input(
+            r#"{"player": "mpv", "title": "Paranoid Android", "artist": "Radiohead", "album": "OK Computer", "is_playing": false}"#,
+        )
+        .unwrap();
+        assert_eq!(player, "mpv");
+        let presence = presence(&media_info, &mpv(), &Cli::default());
+
+        let text = presence.to_text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "Client ID:   1129859263741837373");
+        assert_eq!(
+            lines[1],
+            "Type:        listening (name shown after it: state)"
+        );
+        assert_eq!(lines[2], "Details:     Paranoid Android");
+        assert_eq!(lines[4], "State:       Radiohead");
+        assert_eq!(lines[6], "Large text:  album: OK Computer");
+        assert_eq!(lines[7], "Small image: paused");
+        assert_eq!(lines[9], "Start:       -");
+        assert!(lines[10].starts_with(&format!("End:         {} (", START)));
+        assert_eq!(lines[11], "Buttons:     -");
+
+        let json: serde_json::Value = serde_json::to_value(&presence).unwrap();
+        assert_eq!(json["details"], "Paranoid Android");
+        assert_eq!(json["small_image"], "paused");
+        assert_eq!(json["start"], serde_json::Value::Null);
+        assert_eq!(json["end"], START);
+
+        assert!(parse_preview_input("{\"title\": 5}")
+            .err()
+            .unwrap()
+            .starts_with("Invalid metadata: "));
+    }
+}
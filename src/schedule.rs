@@ -0,0 +1,360 @@
+use chrono::{DateTime, Datelike, Local, Timelike};
+use std::time::Duration;
+
+use crate::player_match::PlayerPattern;
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const DAY: u32 = 24 * 60;
+const WEEK: u32 = 7 * DAY;
+
+// Quiet hours in local time, e.g. "off; days=mon-fri; time=09:00-17:00" or
+// "only; time=22:00-07:00; player=mpv; player=bus:spotify*"
+pub struct Rule {
+    rule: String,
+    action: Action,
+    // Minutes since Monday 00:00 when the rule is active, the end is excluded
+    // and ranges starting on Sunday may end after the end of the week
+    ranges: Vec<(u32, u32)>,
+}
+
+enum Action {
+    // Clear the activity
+    Off,
+    // Only show players matching these patterns
+    Only(Vec<PlayerPattern>),
+}
+
+impl Rule {
+    // "action; field=value; ...", without days the rule is active every day and
+    // without time the whole day. Time ranges may end on the next day.
+    pub fn parse(rule: &str) -> Result<Rule, String> {
+        let mut parts = rule.split(';').map(|part| part.trim());
+        let is_off = match parts.next() {
+            Some("off") => true,
+            Some("only") => false,
+            _ => {
+                return Err(String::from(
+                    "expected \"off\" or \"only\" before the first ;",
+                ))
+            }
+        };
+
+        let mut days = [true; 7];
+        let mut time = (0, DAY);
+        let mut players = Vec::new();
+        for part in parts.filter(|part| !part.is_empty()) {
+            let (field, value) = match part.split_once('=') {
+                Some((field, value)) => (field.trim(), value.trim()),
+                None => return Err(format!("expected \"field=value\", got \"{}\"", part)),
+            };
+            match field {
+                "days" => days = parse_days(value)?,
+                "time" => time = parse_time_range(value)?,
+                "player" if !is_off => players.push(PlayerPattern::parse(value)?),
+                "player" => return Err(String::from("player can only be used with \"only\"")),
+                _ => {
+                    return Err(format!(
+                        "unknown field \"{}\", expected days, time or player",
+                        field
+                    ))
+                }
+            }
+        }
+
+        let action = match is_off {
+            true => Action::Off,
+            false if players.is_empty() => {
+                return Err(String::from("expected at least one player=pattern"))
+            }
+            false => Action::Only(players),
+        };
+
+        let (start, end) = time;
+        let ranges = (0..7)
+            .filter(|day| days[*day as usize])
+            .map(|day| (day * DAY + start, day * DAY + end))
+            .collect();
+
+        Ok(Rule {
+            rule: rule.to_string(),
+            action,
+            ranges,
+        })
+    }
+
+    pub fn rule(&self) -> &str {
+        &self.rule
+    }
+
+    pub fn is_off(&self) -> bool {
+        matches!(self.action, Action::Off)
+    }
+
+    // Players allowed while the rule is active, None if all of them are
+    pub fn players(&self) -> Option<&[PlayerPattern]> {
+        match &self.action {
+            Action::Off => None,
+            Action::Only(players) => Some(players),
+        }
+    }
+
+    fn contains(&self, minute: u32) -> bool {
+        self.ranges.iter().any(|(start, end)| {
+            (*start..*end).contains(&minute) || (*start..*end).contains(&(minute + WEEK))
+        })
+    }
+}
+
+// "mon-fri", "sat,sun" or "mon,wed-fri", ranges may wrap around like "fri-mon"
+fn parse_days(value: &str) -> Result<[bool; 7], String> {
+    let day = |name: &str| {
+        let name = name.trim().to_lowercase();
+        DAYS.iter()
+            .position(|day| *day == name)
+            .ok_or_else(|| format!("unknown day \"{}\", expected mon, tue, ..., sun", name))
+    };
+
+    let mut days = [false; 7];
+    for item in value.split(',') {
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (day(first)?, day(last)?);
+                let mut current = first;
+                loop {
+                    days[current] = true;
+                    if current == last {
+                        break;
+                    }
+                    current = (current + 1) % 7;
+                }
+            }
+            None => days[day(item)?] = true,
+        }
+    }
+    Ok(days)
+}
+
+// "09:00-17:00", the end is on the next day if it is not after the start
+fn parse_time_range(value: &str) -> Result<(u32, u32), String> {
+    let (start, end) = match value.split_once('-') {
+        Some((start, end)) => (parse_time(start)?, parse_time(end)?),
+        None => {
+            return Err(format!(
+                "expected a time range like \"09:00-17:00\", got \"{}\"",
+                value
+            ))
+        }
+    };
+    match end > start {
+        true => Ok((start, end)),
+        false => Ok((start, end + DAY)),
+    }
+}
+
+// Minutes since midnight, "24:00" is the end of the day
+fn parse_time(value: &str) -> Result<u32, String> {
+    let error = || format!("expected a time like \"09:00\", got \"{}\"", value.trim());
+    let (hours, minutes) = value.trim().split_once(':').ok_or_else(error)?;
+    let hours: u32 = hours.parse().map_err(|_| error())?;
+    let minutes: u32 = minutes.parse().map_err(|_| error())?;
+    if minutes >= 60 || hours > 24 || (hours == 24 && minutes > 0) {
+        return Err(error());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+// Rules from settings, they were already validated when they were loaded
+pub fn parse_all(rules: &[String]) -> Vec<Rule> {
+    rules
+        .iter()
+        .filter_map(|rule| Rule::parse(rule).ok())
+        .collect()
+}
+
+fn minute_of_week(now: &DateTime<Local>) -> u32 {
+    now.weekday().num_days_from_monday() * DAY + now.hour() * 60 + now.minute()
+}
+
+// First rule that is active now
+pub fn active<'a>(rules: &'a [Rule], now: &DateTime<Local>) -> Option<&'a Rule> {
+    let minute = minute_of_week(now);
+    rules.iter().find(|rule| rule.contains(minute))
+}
+
+// Time left until any rule starts or ends, None without rules
+pub fn next_change(rules: &[Rule], now: &DateTime<Local>) -> Option<Duration> {
+    let minute = minute_of_week(now);
+    let minutes = rules
+        .iter()
+        .flat_map(|rule| rule.ranges.iter())
+        .flat_map(|(start, end)| [*start, *end])
+        .map(|boundary| match (boundary % WEEK + WEEK - minute) % WEEK {
+            0 => WEEK,
+            minutes => minutes,
+        })
+        .min()?;
+
+    // Counted from the start of the current minute
+    let elapsed = Duration::new(now.second() as u64, now.nanosecond().min(999_999_999));
+    Some(Duration::from_secs(minutes as u64 * 60).saturating_sub(elapsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // 2026-10-19 is a Monday
+    fn at(day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2026, 10, 19 + day, hour, minute, second)
+            .unwrap()
+    }
+
+    fn rules(rules: &[&str]) -> Vec<Rule> {
+        rules
+            .iter()
+            .map(|rule| Rule::parse(rule).unwrap())
+            .collect()
+    }
+
+    fn error(rule: &str) -> String {
+        Rule::parse(rule).err().unwrap()
+    }
+
+    #[test]
+    fn documented_examples() {
+        let rules = rules(&[
+            "off; days=mon-fri; time=09:00-17:00",
+            "only; days=sat,sun; time=22:00-08:00; player=mpv; player=bus:spotify*",
+        ]);
+        assert!(rules[0].is_off());
+        assert_eq!(rules[1].players().map(|players| players.len()), Some(2));
+
+        assert!(active(&rules, &at(0, 8, 59, 59)).is_none());
+        assert!(active(&rules, &at(0, 9, 0, 0)).is_some_and(|rule| rule.is_off()));
+        assert!(active(&rules, &at(4, 16, 59, 0)).is_some());
+        assert!(active(&rules, &at(4, 17, 0, 0)).is_none());
+        assert!(active(&rules, &at(5, 12, 0, 0)).is_none());
+        assert!(active(&rules, &at(5, 23, 0, 0)).is_some_and(|rule| !rule.is_off()));
+    }
+
+    #[test]
+    fn overnight_ranges_end_on_the_next_day() {
+        let rules = rules(&["off; days=fri; time=22:00-07:00"]);
+        assert!(active(&rules, &at(3, 23, 0, 0)).is_none());
+        assert!(active(&rules, &at(4, 22, 0, 0)).is_some());
+        assert!(active(&rules, &at(5, 6, 59, 0)).is_some());
+        assert!(active(&rules, &at(5, 7, 0, 0)).is_none());
+        assert!(active(&rules, &at(5, 22, 30, 0)).is_none());
+    }
+
+    #[test]
+    fn sunday_night_continues_on_monday() {
+        let rules = rules(&["off; days=sun; time=23:00-01:00"]);
+        assert!(active(&rules, &at(6, 23, 30, 0)).is_some());
+        assert!(active(&rules, &at(0, 0, 30, 0)).is_some());
+        assert!(active(&rules, &at(0, 1, 0, 0)).is_none());
+        assert_eq!(
+            next_change(&rules, &at(6, 22, 0, 0)),
+            Some(Duration::from_secs(60 * 60))
+        );
+        assert_eq!(
+            next_change(&rules, &at(6, 23, 30, 0)),
+            Some(Duration::from_secs(90 * 60))
+        );
+    }
+
+    #[test]
+    fn days_lists() {
+        let days = |value: &str| parse_days(value).unwrap();
+        assert_eq!(
+            days("mon-fri"),
+            [true, true, true, true, true, false, false]
+        );
+        assert_eq!(
+            days("sat,sun"),
+            [false, false, false, false, false, true, true]
+        );
+        assert_eq!(
+            days("Mon, wed-fri"),
+            [true, false, true, true, true, false, false]
+        );
+        assert_eq!(
+            days("fri-mon"),
+            [true, false, false, false, true, true, true]
+        );
+        assert_eq!(
+            days("wed-wed"),
+            [false, false, true, false, false, false, false]
+        );
+    }
+
+    #[test]
+    fn next_change_is_the_nearest_boundary() {
+        let rules = rules(&["off; days=mon-fri; time=09:00-17:00"]);
+        // Counted to the second, not to the next interval
+        assert_eq!(
+            next_change(&rules, &at(0, 8, 59, 30)),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            next_change(&rules, &at(0, 9, 0, 0)),
+            Some(Duration::from_secs(8 * 60 * 60))
+        );
+        // Friday evening until Monday morning
+        assert_eq!(
+            next_change(&rules, &at(4, 17, 0, 0)),
+            Some(Duration::from_secs((2 * 24 + 16) * 60 * 60))
+        );
+        // Across midnight
+        let rules = self::rules(&["off; time=00:00-06:00"]);
+        assert_eq!(
+            next_change(&rules, &at(1, 23, 0, 0)),
+            Some(Duration::from_secs(60 * 60))
+        );
+        assert_eq!(next_change(&[], &at(0, 0, 0, 0)), None);
+    }
+
+    #[test]
+    fn whole_days_without_time() {
+        let rules = rules(&["off; days=sat"]);
+        assert!(active(&rules, &at(5, 0, 0, 0)).is_some());
+        assert!(active(&rules, &at(5, 23, 59, 0)).is_some());
+        assert!(active(&rules, &at(6, 0, 0, 0)).is_none());
+        assert!(active(&self::rules(&["off; time=00:00-24:00"]), &at(2, 12, 0, 0)).is_some());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            error("sleep; time=01:00-02:00"),
+            "expected \"off\" or \"only\" before the first ;"
+        );
+        assert_eq!(error("off; days"), "expected \"field=value\", got \"days\"");
+        assert_eq!(
+            error("off; weekday=mon"),
+            "unknown field \"weekday\", expected days, time or player"
+        );
+        assert_eq!(
+            error("off; days=xyz"),
+            "unknown day \"xyz\", expected mon, tue, ..., sun"
+        );
+        assert_eq!(
+            error("off; time=25:00-01:00"),
+            "expected a time like \"09:00\", got \"25:00\""
+        );
+        assert_eq!(
+            error("off; time=09:00"),
+            "expected a time range like \"09:00-17:00\", got \"09:00\""
+        );
+        assert_eq!(
+            error("off; player=mpv"),
+            "player can only be used with \"only\""
+        );
+        assert_eq!(
+            error("only; days=sat"),
+            "expected at least one player=pattern"
+        );
+    }
+}
@@ -1,20 +1,57 @@
 use clap_serde_derive::{
-    clap::{self, Parser, Subcommand},
+    clap::{
+        self, builder::FalseyValueParser, parser::ValueSource, Arg, ArgAction, ArgMatches, Command,
+        CommandFactory, FromArgMatches, Parser, Subcommand,
+    },
     serde::Serialize,
     ClapSerde,
 };
+use log::{debug, error, info, warn};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
 
-use crate::debug_log;
-use crate::utils::get_config_path;
+use crate::control;
+use crate::filter::{self, Filter};
+use crate::logger;
+use crate::migrate;
+use crate::mqtt;
+use crate::player_match::{self, PlayerPattern};
+use crate::schedule;
+use crate::utils::{get_config_path, get_lastfm_avatar};
+use crate::validate;
+
+const DEFAULT_CLIENT_ID: &str = "1129859263741837373";
+const DEFAULT_VIDEO_CLIENT_ID: &str = "1356756023813210293";
+
+// Prefix of environment variables used to change settings, e.g. MUSIC_DISCORD_RPC_INTERVAL
+const ENV_PREFIX: &str = "MUSIC_DISCORD_RPC_";
+
+// Args that are only read from the command line (the ones with #[serde(skip_deserializing)])
+const CLI_ONLY: [&str; 4] = ["list_players", "get_player_id", "debug_log", "reset_config"];
+
+// Keys that are only used in the config file and have no args
+const CONFIG_ONLY: [&str; 1] = ["version"];
+
+// Settings hidden by "config show", matched by the end of their names
+const SECRET_SUFFIXES: [&str; 3] = ["_key", "_password", "_token"];
+
+// Lists split on commas in command line args and environment variables, only
+// the ones with fixed values, all other values may contain commas
+const COMMA_SEPARATED: [&str; 3] = ["button", "cover_providers", "notifications"];
 
 #[derive(Parser, ClapSerde, Serialize, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(
+    after_help = "Every flag can be turned off with its --no-* counterpart, e.g. --no-hide-album-name.\nEvery option can also be set with an environment variable, e.g. MUSIC_DISCORD_RPC_INTERVAL=10."
+)]
 pub struct Cli {
     /// Activity refresh rate (min: 5, default: 10)
     #[arg(short, long, value_name = "seconds", value_parser = clap::value_parser!(u64).range(5..))]
+    #[default(Some(10))]
     pub interval: Option<u64>,
 
     /// Select visible buttons
@@ -31,10 +68,12 @@ pub struct Cli {
 
     /// Select what will be displayed after "Listening to" (default: artist)
     #[arg(short, long, value_name = "value", value_parser = ["artist", "track", "none"])]
+    #[default(Some(String::from("artist")))]
     pub rpc_name: Option<String>,
 
     /// Select the icon displayed next to the album cover (default: playPause)
     #[arg(short, long, value_name = "name", value_parser = ["playPause", "player", "lastfmAvatar", "none"])]
+    #[default(Some(String::from("playPause")))]
     pub small_image: Option<String>,
 
     /// Force a different player id to be displayed than the one actually used
@@ -59,14 +98,68 @@ pub struct Cli {
     #[serde(skip_deserializing)]
     pub get_player_id: bool,
 
-    /// Get status only from given player. Use multiple times to add several players.
-    #[arg(short = 'a', long = "allowlist-add", value_name = "Player Name", value_parser = clap::value_parser!(String))]
+    /// Get status only from given player. Use multiple times to add several players. Also "bus:" and "desktop:" rules with globs or /regex/
+    #[arg(short = 'a', long = "allowlist-add", value_name = "Player Name", value_parser = parse_player_pattern)]
     pub allowlist: Vec<String>,
 
+    /// Never get status from given player, same rules as the allowlist. Use multiple times to add several players.
+    #[arg(long = "blocklist-add", value_name = "Player Name", value_parser = parse_player_pattern)]
+    pub blocklist: Vec<String>,
+
+    /// Which MPRIS player is shown when several are running, it is replaced as soon as the policy prefers another one (Linux only)
+    #[arg(long, value_name = "policy", value_parser = ["allowlist", "recent", "playing", "sticky"])]
+    #[default(Some(String::from("allowlist")))]
+    pub player_policy: Option<String>,
+
     /// Will use the "watching" activity. Use multiple times to add several players.
-    #[arg(short = 'w', long = "video-players", value_name = "Player Name", value_parser = clap::value_parser!(String))]
+    #[arg(short = 'w', long = "video-players", value_name = "Player Name", value_parser = parse_player_pattern)]
     pub video_players: Vec<String>,
 
+    /// Where to get the current track from, "auto" is MPRIS on Linux and media-control on macOS (requires restart)
+    #[arg(long, value_name = "source", value_parser = ["auto", "mpd", "mpv", "cmus", "moc", "subsonic", "jellyfin", "json"])]
+    #[default(Some(String::from("auto")))]
+    pub source: Option<String>,
+
+    /// MPD host or path to its Unix socket (requires restart)
+    #[arg(long, value_name = "host", value_parser = clap::value_parser!(String))]
+    #[default(Some(String::from("localhost")))]
+    pub mpd_host: Option<String>,
+
+    /// MPD port (requires restart)
+    #[arg(long, value_name = "port", value_parser = clap::value_parser!(u16).range(1..))]
+    #[default(Some(6600))]
+    pub mpd_port: Option<u16>,
+
+    /// MPD password (requires restart)
+    #[arg(long, value_name = "password", value_parser = clap::value_parser!(String))]
+    pub mpd_password: Option<String>,
+
+    /// Path to the socket set with --input-ipc-server in mpv (requires restart)
+    #[arg(long, value_name = "path", value_parser = clap::value_parser!(String))]
+    #[default(Some(String::from("/tmp/mpvsocket")))]
+    pub mpv_socket: Option<String>,
+
+    /// Path to the cmus socket, the default location of cmus is used without it (requires restart)
+    #[arg(long, value_name = "path", value_parser = clap::value_parser!(String))]
+    pub cmus_socket: Option<String>,
+
+    /// Url of the Subsonic (e.g. Navidrome) or Jellyfin server (requires restart)
+    #[arg(long, value_name = "url", value_parser = clap::value_parser!(String))]
+    pub server_url: Option<String>,
+
+    /// User whose playback is shown, all users of the server without it (requires restart)
+    #[arg(long, value_name = "user", value_parser = clap::value_parser!(String))]
+    pub server_user: Option<String>,
+
+    /// Jellyfin API key or Subsonic password, the password is only sent as a salted token (requires restart)
+    #[arg(long, value_name = "token", value_parser = clap::value_parser!(String))]
+    pub server_token: Option<String>,
+
+    /// Named pipe with newline-delimited JSON messages, "-" reads them from stdin (requires restart)
+    #[arg(long, value_name = "path", value_parser = clap::value_parser!(String))]
+    #[default(Some(String::from("-")))]
+    pub json_input: Option<String>,
+
     /// Hide album name
     #[arg(long)]
     pub hide_album_name: bool,
@@ -75,6 +168,19 @@ pub struct Cli {
     #[arg(long)]
     pub only_when_playing: bool,
 
+    /// Content filter like "hide; artist=podcast", actions: hide, hide_album, placeholder. Use multiple times to add several filters.
+    #[arg(long = "filter", value_name = "rule", value_parser = parse_filter)]
+    pub filters: Vec<String>,
+
+    /// Text shown instead of the title and artist by the placeholder filter action
+    #[arg(long, value_name = "text", value_parser = clap::value_parser!(String))]
+    #[default(Some(String::from("Private")))]
+    pub filter_placeholder: Option<String>,
+
+    /// Quiet hours in local time like "off; days=mon-fri; time=09:00-17:00", actions: off, only (with player=pattern). Use multiple times to add several rules.
+    #[arg(long = "schedule", value_name = "rule", value_parser = parse_schedule)]
+    pub schedule: Vec<String>,
+
     /// Disable cache (not recommended)
     #[arg(short, long)]
     pub disable_cache: bool,
@@ -83,11 +189,113 @@ pub struct Cli {
     #[arg(long, value_name = "api_key", value_parser = clap::value_parser!(String))]
     pub lastfm_api_key: Option<String>,
 
-    /// Do not use MusicBrainz as a fallback source of album covers
+    /// Sources of album covers in order of priority. Use multiple times to add several providers.
+    #[arg(long = "cover-provider", value_name = "name", value_parser = ["lastfm", "musicbrainz", "server"])]
+    #[default(vec![String::from("lastfm"), String::from("musicbrainz")])]
+    pub cover_providers: Vec<String>,
+
+    /// Do not use MusicBrainz as a fallback source of album covers (deprecated, use --cover-provider)
     #[arg(long)]
     pub disable_musicbrainz_cover: bool,
 
-    /// Show debug log
+    /// Send desktop notifications for the given events. Use multiple times to enable several events (Linux only)
+    #[arg(long = "notify", value_name = "event", value_parser = ["discord", "player", "track", "config"])]
+    pub notifications: Vec<String>,
+
+    /// Show an icon with the current track in the system tray (Linux only)
+    #[arg(long)]
+    pub tray: bool,
+
+    /// Serve Prometheus metrics on http://127.0.0.1:<port>/metrics (requires restart)
+    #[arg(long, value_name = "port", value_parser = clap::value_parser!(u16).range(1..))]
+    pub metrics_port: Option<u16>,
+
+    /// Serve the current track and an overlay for OBS on http://127.0.0.1:<port> (requires restart)
+    #[arg(long, value_name = "port", value_parser = clap::value_parser!(u16).range(1..))]
+    pub now_playing_port: Option<u16>,
+
+    /// Write the current track to a file whenever it changes, optionally with a template after "=". Use multiple times to write several files (requires restart)
+    #[arg(long = "output-file", value_name = "path[=template]", value_parser = clap::value_parser!(String))]
+    pub output_files: Vec<String>,
+
+    /// Download the album cover of the current track to a file (requires restart)
+    #[arg(long, value_name = "path", value_parser = clap::value_parser!(String))]
+    pub output_cover: Option<String>,
+
+    /// Send the current track to this URL when a track starts playing, is paused or stopped (requires restart)
+    #[arg(long, value_name = "url", value_parser = clap::value_parser!(String))]
+    pub webhook_url: Option<String>,
+
+    /// HTTP method of webhook requests (requires restart)
+    #[arg(long, value_name = "method", value_parser = ["POST", "PUT", "PATCH", "GET"])]
+    #[default(Some(String::from("POST")))]
+    pub webhook_method: Option<String>,
+
+    /// Header added to webhook requests, e.g. "Authorization: Bearer token". Use multiple times to add several headers (requires restart)
+    #[arg(long = "webhook-header", value_name = "Name: value", value_parser = parse_header)]
+    pub webhook_headers: Vec<String>,
+
+    /// JSON body of webhook requests with placeholders like {title}, the whole state is sent by default (requires restart)
+    #[arg(long, value_name = "template", value_parser = clap::value_parser!(String))]
+    pub webhook_body: Option<String>,
+
+    /// Send the webhook only after the track did not change for this many seconds (requires restart)
+    #[arg(long, value_name = "seconds", value_parser = clap::value_parser!(u64))]
+    #[default(Some(2))]
+    pub webhook_debounce: Option<u64>,
+
+    /// How many times to retry failed webhook requests (requires restart)
+    #[arg(long, value_name = "count", value_parser = clap::value_parser!(u32).range(..=10))]
+    #[default(Some(3))]
+    pub webhook_retries: Option<u32>,
+
+    /// Publish the current track to this MQTT broker, e.g. "mqtt://localhost:1883" (requires restart)
+    #[arg(long, value_name = "url", value_parser = parse_mqtt_broker)]
+    pub mqtt_broker: Option<String>,
+
+    /// Username for the MQTT broker (requires restart)
+    #[arg(long, value_name = "username", value_parser = clap::value_parser!(String))]
+    pub mqtt_username: Option<String>,
+
+    /// Password for the MQTT broker (requires restart)
+    #[arg(long, value_name = "password", value_parser = clap::value_parser!(String))]
+    pub mqtt_password: Option<String>,
+
+    /// Prefix of MQTT topics (requires restart)
+    #[arg(long, value_name = "prefix", value_parser = clap::value_parser!(String))]
+    #[default(Some(String::from("music-discord-rpc")))]
+    pub mqtt_topic_prefix: Option<String>,
+
+    /// Send Home Assistant MQTT discovery messages, so the sensors appear automatically (requires restart)
+    #[arg(long)]
+    pub mqtt_home_assistant: bool,
+
+    /// Discord application ID used for the "listening" activity
+    #[arg(long, value_name = "id", value_parser = clap::value_parser!(String))]
+    #[default(Some(String::from(DEFAULT_CLIENT_ID)))]
+    pub client_id: Option<String>,
+
+    /// Discord application ID used for the "watching" activity
+    #[arg(long, value_name = "id", value_parser = clap::value_parser!(String))]
+    #[default(Some(String::from(DEFAULT_VIDEO_CLIENT_ID)))]
+    pub video_client_id: Option<String>,
+
+    /// Log level, optionally per module, e.g. "info,tray=debug" [levels: off, error, warn, info, debug, trace]
+    #[arg(long, value_name = "filter", value_parser = parse_log_level)]
+    #[default(Some(String::from("info")))]
+    pub log_level: Option<String>,
+
+    /// Where to write the log, the log file is saved in the cache directory
+    #[arg(long, value_name = "output", value_parser = ["stdout", "file", "journald"])]
+    #[default(Some(String::from("stdout")))]
+    pub log_output: Option<String>,
+
+    /// Format of log messages
+    #[arg(long, value_name = "format", value_parser = ["text", "json"])]
+    #[default(Some(String::from("text")))]
+    pub log_format: Option<String>,
+
+    /// Show debug log (same as --log-level debug)
     #[arg(long)]
     #[serde(skip_deserializing)]
     pub debug_log: bool,
@@ -127,27 +335,92 @@ pub enum Commands {
     },
     /// Use to restart the service and reload the changed configuration file.
     Restart {},
+    /// Show the player, track and Discord connection of the running instance
+    Status {},
+    /// Hide the activity until resume-presence is used
+    PausePresence {},
+    /// Show the activity again after pause-presence
+    ResumePresence {},
+    /// Pause or resume the activity
+    Toggle {},
+    /// Send the activity again
+    Refresh {},
+    /// Reload the configuration file of the running instance
+    Reload {},
+    /// Show this MPRIS player when several are running, or any player without a name (Linux only)
+    Prefer {
+        /// Name of the player, e.g. mpv
+        #[serde(skip_deserializing)]
+        player: Option<String>,
+    },
+    /// Print the activity that would be sent for the current track and exit, without connecting to Discord
+    Preview {
+        /// Print the activity as JSON
+        #[arg(long)]
+        #[serde(skip_deserializing)]
+        json: bool,
+        /// Read track metadata from a JSON file instead of the player ("-" for stdin)
+        #[arg(long, value_name = "file")]
+        #[serde(skip_deserializing)]
+        from_json: Option<PathBuf>,
+    },
+    /// Inspect, validate and upgrade the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
 }
 
-// Use to get config path, create new config or reset existing
-fn create_config_file(force: bool) -> (bool, PathBuf) {
-    let mut config_file = match get_config_path() {
-        Some(path) => path,
-        None => {
-            println!("\x1b[31mWARNING: Failed to determine user config directory.\x1b[0m");
-            return (false, PathBuf::new());
-        }
-    };
-    config_file.push("music-discord-rpc");
+#[derive(Subcommand, Debug, Serialize)]
+pub enum ConfigCommands {
+    /// Print the effective value of every setting and where it comes from
+    Show {},
+    /// Print the location of the config file
+    Path {},
+    /// Validate the config file and report problems with their line and column
+    Check {},
+    /// Upgrade the config file to the current format, keeping your values and comments
+    Upgrade {},
+}
 
-    let config_dir = config_file.clone();
-    config_file.push("config.yaml");
+// Where the effective value of a setting comes from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    ConfigFile,
+    Env,
+    Cli,
+}
 
-    if config_file.exists() && !force {
-        return (true, config_file);
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::ConfigFile => write!(f, "config file"),
+            Source::Env => write!(f, "environment variable"),
+            Source::Cli => write!(f, "command line"),
+        }
     }
+}
+
+// Settings merged from all sources with the origin of every value
+pub struct Merged {
+    pub settings: Cli,
+    pub values: Map<String, Value>,
+    pub sources: HashMap<String, Source>,
+}
 
-    let config_text = r#"# music-discord-rpc configuration file
+// Location of the config file
+pub fn get_config_file() -> Option<PathBuf> {
+    let mut config_file = get_config_path()?;
+    config_file.push("music-discord-rpc");
+    config_file.push("config.yaml");
+    Some(config_file)
+}
+
+// Default config file. Sections are separated by empty lines, "config upgrade"
+// adds the missing ones to existing config files.
+pub const CONFIG_TEMPLATE: &str = r#"# music-discord-rpc configuration file
 
 # You can reset this file using the command:
 # music-discord-rpc --reset-config
@@ -162,6 +435,9 @@ fn create_config_file(force: bool) -> (bool, PathBuf) {
 # You can also disable Last.fm as a cover source by providing an empty string as the key.
 # lastfm_api_key: ""
 
+# Version of this file format, used to upgrade it with: music-discord-rpc config upgrade
+version: 1
+
 # Activity refresh rate in seconds (min 5)
 interval: 10
 
@@ -192,42 +468,176 @@ disable_mpris_art_url: false
 # Only use the status from the following music players
 # Use -l, --list-players to get player exact name to use with this option
 # The order matters and the first is the most important.
+# Entries match the player name (ignoring case), or the D-Bus name with "bus:" and the desktop entry with "desktop:" (Linux only)
+# Use * and ? as wildcards or /.../ for a regular expression, e.g. "bus:firefox.instance*" or "/^Chrom(e|ium)$/"
 # allowlist:
 #   - "VLC Media Player"
 #   - "Chrome"
 #   - "Any other player"
 
-# Will use the "watching" activity
+# Never use the status from the following players, same entries as in the allowlist
+# blocklist:
+#   - "desktop:org.kde.kdeconnect*"
+
+# Which MPRIS player is shown when several are running (Linux only) [possible values: allowlist, recent, playing, sticky]
+# allowlist: the first running player from the allowlist, without it the first playing one
+# recent: the player that started playing most recently, playing: keep the current player while it plays, otherwise any playing one
+# sticky: keep the current player until it is closed
+player_policy: "allowlist"
+
+# Will use the "watching" activity, same entries as in the allowlist
 # Use -l, --list-players to get player exact name to use with this option
 # video_players:
 #   - "VLC Media Player"
 #   - "Chrome"
 
+# Where to get the current track from (requires restart) [possible values: auto, mpd, mpv, cmus, moc, subsonic, jellyfin, json]
+# auto: MPRIS on Linux, media-control on macOS, mpd: Music Player Daemon without MPRIS, mpv: mpv JSON IPC
+# cmus: cmus control socket, moc: Music On Console (mocp --info)
+# subsonic: Subsonic API servers like Navidrome (getNowPlaying), jellyfin: Jellyfin Sessions API
+# json: newline-delimited JSON from stdin or a named pipe, for your own players and scripts
+source: "auto"
+
+# Connection to MPD, the host can also be a path to its Unix socket
+# mpd_host: "localhost"
+# mpd_port: 6600
+# mpd_password: "password"
+
+# Socket of mpv started with --input-ipc-server=/tmp/mpvsocket (or input-ipc-server in mpv.conf)
+# mpv_socket: "/tmp/mpvsocket"
+
+# Socket of cmus, by default $CMUS_SOCKET, $XDG_RUNTIME_DIR/cmus-socket or ~/.config/cmus/socket
+# cmus_socket: "~/.config/cmus/socket"
+
+# Subsonic or Jellyfin server, checked every interval. The token is the API key (Jellyfin, Dashboard > API Keys)
+# or the password (Subsonic, only sent as a salted token). Without the user all users of the server are shown.
+# server_url: "https://music.example.com"
+# server_user: "user"
+# server_token: "token"
+
+# Named pipe (create it with mkfifo) the json source reads from, "-" is stdin
+# json_input: "-"
+
 # Hide the album name to decrease activity height
 hide_album_name: false
 
 # Only send activity when media is playing
 only_when_playing: false
 
-# Prevent MusicBrainz to be used as source of album cover if cover is not available on Last.fm
-disable_musicbrainz_cover: false
+# Content filters checked for every track, "action; field=regex; field=regex" and all conditions must match
+# Actions: hide (clear the activity), hide_album (hide the album name and cover), placeholder (replace the title and artist too)
+# Fields: title, artist, album, domain (of the media url), player, duration ("min-max" in seconds, e.g. "1800-")
+# Regular expressions ignore case, matched filters are shown with --debug-log
+# filters:
+#   - "hide; artist=^(Some Podcast|Another Podcast)$"
+#   - "hide; title=asmr"
+#   - "placeholder; player=chrome|firefox; domain=(meet\\.google|teams\\.microsoft)\\.com"
+#   - "hide_album; duration=3600-"
+# filter_placeholder: "Private"
+
+# Quiet hours in local time, "action; field=value; ..." and the first active rule is used
+# Actions: off (clear the activity), only (show only players matching the player=pattern fields, instead of the allowlist)
+# Fields: days (e.g. "mon-fri" or "sat,sun", every day without it), time ("start-end", may end on the next day, the whole day without it), player (see allowlist)
+# The presence changes exactly when a rule starts or ends, the active rule is shown by the status command
+# schedule:
+#   - "off; days=mon-fri; time=09:00-17:00"
+#   - "only; days=sat,sun; time=22:00-08:00; player=mpv; player=bus:spotify*"
+
+# Sources of album covers, the first one that finds the cover is used [possible values: lastfm, musicbrainz, server]
+# server: the cover of the Jellyfin server (or the art url of the player), Discord must be able to reach it
+cover_providers:
+  - lastfm
+  - musicbrainz
 
 # Disable cache (not recommended)
 disable_cache: false
+
+# Send desktop notifications for selected events (Linux only) [possible values: discord, player, track, config]
+# discord: connected to or disconnected from Discord, player: player found or lost, track: track changed, config: config file is invalid
+# notifications:
+#   - discord
+#   - config
+
+# Show an icon with the current track in the system tray, requires a tray with StatusNotifierItem support (Linux only, requires restart)
+tray: false
+
+# Serve Prometheus metrics on http://127.0.0.1:<port>/metrics (requires restart)
+# metrics_port: 9464
+
+# Serve the current track as JSON and an overlay page for OBS on http://127.0.0.1:<port> (requires restart)
+# now_playing_port: 8974
+
+# Write the current track to files whenever it changes, e.g. for OBS text sources or status bars (requires restart)
+# Format is "path=template", see README for placeholders. Without a template .json files get the whole state, other files "{artist} - {title}"
+# output_files:
+#   - "~/.cache/music-discord-rpc/now_playing.txt={artist} - {title}"
+#   - "~/.cache/music-discord-rpc/now_playing.json"
+# output_cover: "~/.cache/music-discord-rpc/cover.jpg"
+
+# Send the current track to a URL when a track starts playing, is paused or stopped, e.g. to a chat bot or Home Assistant (requires restart)
+# The body is a JSON template with the same placeholders as output_files, the whole state is sent without it
+# webhook_url: "http://127.0.0.1:8123/api/webhook/music"
+# webhook_method: "POST"
+# webhook_headers:
+#   - "Authorization: Bearer token"
+# webhook_body: '{"text": "{artist} - {title}", "event": "{status}"}'
+# Requests are sent after the track did not change for this many seconds and retried when they fail
+# webhook_debounce: 2
+# webhook_retries: 3
+
+# Publish the current track to an MQTT broker as retained topics: <prefix>/state, title, artist, album, cover_url, player, position and duration (requires restart)
+# mqtt_broker: "mqtt://localhost:1883"
+# mqtt_username: "user"
+# mqtt_password: "password"
+mqtt_topic_prefix: "music-discord-rpc"
+# Create sensors in Home Assistant with MQTT discovery
+mqtt_home_assistant: false
+
+# Use your own Discord applications instead of the default ones (e.g. to change the "Listening to Music" name)
+# client_id: "1129859263741837373"
+# video_client_id: "1356756023813210293"
+
+# Log level: off, error, warn, info, debug or trace. Levels can be set per module, e.g. "info,tray=debug,reqwest=warn"
+log_level: "info"
+
+# Where to write the log: stdout, file (rotated, saved in the cache directory) or journald
+log_output: "stdout"
+
+# Format of log messages: text or json
+log_format: "text"
 "#;
 
+// Use to get config path, create new config or reset existing
+fn create_config_file(force: bool) -> (bool, PathBuf) {
+    let config_file = match get_config_file() {
+        Some(path) => path,
+        None => {
+            warn!("Failed to determine user config directory.");
+            return (false, PathBuf::new());
+        }
+    };
+
+    let config_dir = match config_file.parent() {
+        Some(dir) => dir.to_path_buf(),
+        None => return (false, config_file),
+    };
+
+    if config_file.exists() && !force {
+        return (true, config_file);
+    }
+
     match fs::create_dir_all(&config_dir) {
         Err(_) => {
-            println!("[config] Failed to create config directory.");
+            error!("[config] Failed to create config directory.");
             return (false, config_file);
         }
-        Ok(_) => match fs::write(&config_file, config_text) {
-            Ok(_) => println!(
+        Ok(_) => match fs::write(&config_file, CONFIG_TEMPLATE) {
+            Ok(_) => info!(
                 "[config] Created new config file: {}",
                 config_file.display()
             ),
             Err(_) => {
-                println!("[config] Error: Failed to create config file.");
+                error!("[config] Failed to create config file.");
                 return (false, config_file);
             }
         },
@@ -236,11 +646,95 @@ disable_cache: false
     return (true, config_file);
 }
 
-// Used to get settings merged from args and config file
-pub fn load_settings() -> Cli {
-    let args = Cli::parse();
-    debug_log!(args.debug_log, "Debug logs: enabled.");
-    debug_log!(args.debug_log, "args: {:#?}", args);
+// Cli extended with environment variables and --no-* counterparts of flags
+fn build_command() -> Command {
+    let mut negations = Vec::new();
+
+    let command = Cli::command().mut_args(|arg| {
+        let id = arg.get_id().to_string();
+        if CLI_ONLY.contains(&id.as_str()) {
+            return arg;
+        }
+
+        let mut arg = arg
+            .env(format!("{}{}", ENV_PREFIX, id.to_uppercase()))
+            .hide_env_values(true);
+        match arg.get_action() {
+            ArgAction::Append if COMMA_SEPARATED.contains(&id.as_str()) => {
+                arg = arg.value_delimiter(',')
+            }
+            ArgAction::SetTrue => {
+                let long = arg.get_long().unwrap_or(&id).to_string();
+                negations.push(
+                    Arg::new(format!("no_{}", id))
+                        .long(format!("no-{}", long))
+                        .action(ArgAction::SetTrue)
+                        .hide(true),
+                );
+                arg = arg.value_parser(FalseyValueParser::new());
+            }
+            _ => {}
+        }
+        arg
+    });
+
+    command.args(negations)
+}
+
+// Check the filter with the same parser as the logger
+fn parse_log_level(spec: &str) -> Result<String, String> {
+    logger::Filters::parse(spec)?;
+    Ok(spec.to_string())
+}
+
+fn parse_header(header: &str) -> Result<String, String> {
+    match header.split_once(':') {
+        Some((name, _)) if !name.trim().is_empty() => Ok(header.to_string()),
+        _ => Err(String::from("expected \"Name: value\"")),
+    }
+}
+
+fn parse_player_pattern(entry: &str) -> Result<String, String> {
+    PlayerPattern::parse(entry)?;
+    Ok(entry.to_string())
+}
+
+fn parse_filter(rule: &str) -> Result<String, String> {
+    Filter::parse(rule)?;
+    Ok(rule.to_string())
+}
+
+fn parse_schedule(rule: &str) -> Result<String, String> {
+    schedule::Rule::parse(rule)?;
+    Ok(rule.to_string())
+}
+
+fn parse_mqtt_broker(broker: &str) -> Result<String, String> {
+    match mqtt::parse_broker(broker) {
+        Some(_) => Ok(broker.to_string()),
+        None => Err(String::from("expected \"mqtt://host:port\"")),
+    }
+}
+
+fn parse_args() -> (Cli, ArgMatches) {
+    let matches = build_command().get_matches();
+    let args = match Cli::from_arg_matches(&matches) {
+        Ok(args) => args,
+        Err(err) => err.exit(),
+    };
+    (args, matches)
+}
+
+// Used to get settings merged from args and config file. Also returns the
+// error of the config file if it was rejected, so it can be shown later.
+pub fn load_settings() -> (Cli, Option<String>) {
+    let (args, matches) = parse_args();
+    debug!("args: {:?}", args);
+
+    // Commands below exit right away, their messages go straight to the terminal
+    if args.reset_config || matches!(args.suboptions.command, Some(Commands::Config { .. })) {
+        logger::configure(logger::LogConfig::default());
+    }
 
     // Reset config file is user used --reset-config and exit
     if args.reset_config {
@@ -248,116 +742,610 @@ pub fn load_settings() -> Cli {
         process::exit(0);
     }
 
-    let (mut config_exists, config_file) = create_config_file(false);
-    if !config_exists {
-        return args;
+    // Config subcommands work the same on every platform and exit
+    if let Some(Commands::Config { command }) = &args.suboptions.command {
+        match command {
+            ConfigCommands::Show {} => show_config(args, &matches),
+            ConfigCommands::Check {} => check_config_file(),
+            ConfigCommands::Upgrade {} => upgrade_config_file(),
+            ConfigCommands::Path {} => match get_config_file() {
+                Some(config_file) => println!("{}", config_file.display()),
+                None => {
+                    println!("\x1b[31mWARNING: Failed to determine user config directory.\x1b[0m");
+                    process::exit(1);
+                }
+            },
+        }
+        process::exit(0);
     }
 
-    // Read user config file
-    let mut config = match fs::read_to_string(&config_file) {
-        Ok(yaml_str) => match serde_yaml::from_str::<<Cli as ClapSerde>::Opt>(&yaml_str) {
-            Ok(yaml_args) => Cli::from(yaml_args),
+    // Commands for the running instance are sent through the control socket and exit
+    match &args.suboptions.command {
+        Some(Commands::Status {}) => control::send_command("status", None),
+        Some(Commands::PausePresence {}) => control::send_command("pause-presence", None),
+        Some(Commands::ResumePresence {}) => control::send_command("resume-presence", None),
+        Some(Commands::Toggle {}) => control::send_command("toggle", None),
+        Some(Commands::Refresh {}) => control::send_command("refresh", None),
+        Some(Commands::Reload {}) => control::send_command("reload", None),
+        Some(Commands::Prefer { player }) => control::send_command("prefer", player.clone()),
+        _ => {}
+    }
+
+    let mut config_error = None;
+    let (config_exists, config_file) = create_config_file(false);
+    let config = if config_exists {
+        match read_config_file(&config_file) {
+            Ok(config) => {
+                info!("Configuration loaded from file: {}", config_file.display());
+                debug!("config: {:?}", config);
+                Some(config)
+            }
             Err(error) => {
-                println!("Failed to parse config file: {}", error);
-                config_exists = false;
-                Cli::from_clap()
+                error!("{}", error);
+                config_error = Some(error);
+                None
             }
-        },
-        Err(_) => {
-            println!("Failed to read config file.");
-            config_exists = false;
-            Cli::from_clap()
         }
+    } else {
+        None
     };
 
-    if !config_exists {
-        return args;
-    }
-    println!("Configuration loaded from file: {}", config_file.display());
-    debug_log!(args.debug_log, "config: {:#?}", config);
+    (
+        merge_settings(args, &matches, config).settings,
+        config_error,
+    )
+}
 
-    // Logic of merging config with args
-    if args.interval != config.interval && args.interval.is_some() {
-        config.interval = args.interval;
-    }
+// Used to reload settings while running. Unlike load_settings, an invalid
+// config file is returned as error, so the caller can keep the old settings.
+pub fn reload_settings() -> Result<Cli, String> {
+    let (args, matches) = parse_args();
+
+    let (config_exists, config_file) = create_config_file(false);
+    let config = if config_exists {
+        Some(read_config_file(&config_file)?)
+    } else {
+        None
+    };
+
+    Ok(merge_settings(args, &matches, config).settings)
+}
 
-    if args.button != config.button && args.button.len() > 0 {
-        config.button = args.button;
+// Read, validate and parse user config file. Warnings are printed, errors are returned.
+fn read_config_file(config_file: &Path) -> Result<Map<String, Value>, String> {
+    let yaml_str = match fs::read_to_string(config_file) {
+        Ok(yaml_str) => yaml_str,
+        Err(_) => return Err(String::from("Failed to read config file.")),
+    };
+
+    let mut errors = Vec::new();
+    for diagnostic in validate::check_config(&yaml_str, &build_command(), &CLI_ONLY, &CONFIG_ONLY) {
+        if diagnostic.is_error {
+            errors.push(format!("{}:{}", config_file.display(), diagnostic));
+        } else {
+            warn!("[config] {}:{}", config_file.display(), diagnostic);
+        }
     }
+    if !errors.is_empty() {
+        return Err(format!("Invalid config file:\n{}", errors.join("\n")));
+    }
+
+    let mut config = match serde_yaml::from_str::<Value>(&yaml_str) {
+        Ok(Value::Object(config)) => config,
+        Ok(_) => Map::new(),
+        Err(error) => return Err(format!("Failed to parse config file: {}", error)),
+    };
 
-    if args.lastfm_name != config.lastfm_name && args.lastfm_name.is_some() {
-        config.lastfm_name = args.lastfm_name;
+    // Old config files keep working, but let the user know they can be upgraded
+    let version = migrate::config_version(&config);
+    if version < migrate::CONFIG_VERSION {
+        warn!("[config] Config file uses an old format (version {}), run \"music-discord-rpc config upgrade\" to update it.", version);
+    } else if version > migrate::CONFIG_VERSION {
+        warn!("[config] Config file is newer (version {}) than supported by this version of music-discord-rpc (version {}), some options may be ignored.", version, migrate::CONFIG_VERSION);
     }
+    migrate::migrate_config(&mut config);
 
-    if args.listenbrainz_name != config.listenbrainz_name && args.listenbrainz_name.is_some() {
-        config.listenbrainz_name = args.listenbrainz_name;
+    Ok(config)
+}
+
+// Print all problems found in the config file, exit with error code if it can't be used
+fn check_config_file() {
+    let config_file = match get_config_file() {
+        Some(config_file) if config_file.exists() => config_file,
+        _ => {
+            println!("Config file not found.");
+            process::exit(1);
+        }
+    };
+    let yaml_str = match fs::read_to_string(&config_file) {
+        Ok(yaml_str) => yaml_str,
+        Err(_) => {
+            println!("Failed to read config file.");
+            process::exit(1);
+        }
+    };
+
+    let diagnostics = validate::check_config(&yaml_str, &build_command(), &CLI_ONLY, &CONFIG_ONLY);
+    for diagnostic in &diagnostics {
+        println!("{}:{}", config_file.display(), diagnostic);
     }
 
-    if args.rpc_name != config.rpc_name && args.rpc_name.is_some() {
-        config.rpc_name = args.rpc_name;
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.is_error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    if errors > 0 {
+        println!(
+            "\x1b[31mConfig file is invalid: {} error(s), {} warning(s).\x1b[0m",
+            errors, warnings
+        );
+        process::exit(1);
     }
+    println!("Config file is valid ({} warning(s)).", warnings);
+}
+
+// Rewrite the config file in the current format. Old keys are migrated, missing
+// options are added as comments and everything else is left untouched.
+fn upgrade_config_file() {
+    let config_file = match get_config_file() {
+        Some(config_file) if config_file.exists() => config_file,
+        _ => {
+            println!("Config file not found.");
+            process::exit(1);
+        }
+    };
+    let yaml_str = match fs::read_to_string(&config_file) {
+        Ok(yaml_str) => yaml_str,
+        Err(_) => {
+            println!("Failed to read config file.");
+            process::exit(1);
+        }
+    };
 
-    if args.small_image != config.small_image && args.small_image.is_some() {
-        config.small_image = args.small_image;
+    // Refuse to touch files that can't be parsed, the result would be unpredictable
+    let version = match serde_yaml::from_str::<Value>(&yaml_str) {
+        Ok(Value::Object(config)) => migrate::config_version(&config),
+        Ok(Value::Null) => 0,
+        _ => {
+            println!("\x1b[31mConfig file is invalid, fix it first (see: music-discord-rpc config check).\x1b[0m");
+            process::exit(1);
+        }
+    };
+    if version > migrate::CONFIG_VERSION {
+        println!(
+            "Config file is newer (version {}) than supported by this version of music-discord-rpc.",
+            version
+        );
+        process::exit(1);
     }
 
-    if args.force_player_id != config.force_player_id && args.force_player_id.is_some() {
-        config.force_player_id = args.force_player_id;
+    let upgraded = migrate::upgrade_config(&yaml_str, CONFIG_TEMPLATE, version);
+    if upgraded == yaml_str {
+        println!("Config file is up to date.");
+        return;
     }
 
-    if args.force_player_name != config.force_player_name && args.force_player_name.is_some() {
-        config.force_player_name = args.force_player_name;
+    // Keep a copy of the old file just in case
+    let mut backup_file = config_file.clone();
+    backup_file.set_extension("yaml.bak");
+    if fs::copy(&config_file, &backup_file).is_err() {
+        println!("Failed to create backup of the config file.");
+        process::exit(1);
     }
+    match fs::write(&config_file, upgraded) {
+        Ok(_) => {
+            println!(
+                "Config file upgraded to version {}: {}",
+                migrate::CONFIG_VERSION,
+                config_file.display()
+            );
+            println!("Previous version saved as: {}", backup_file.display());
+        }
+        Err(_) => {
+            println!("Failed to write config file.");
+            process::exit(1);
+        }
+    }
+}
 
-    if args.disable_musicbrainz_cover {
-        config.disable_musicbrainz_cover = args.disable_musicbrainz_cover;
+// Merge defaults, config file, environment variables and args, each one overrides the previous
+fn merge_settings(args: Cli, matches: &ArgMatches, config: Option<Map<String, Value>>) -> Merged {
+    let mut values = match serde_json::to_value(Cli::default()) {
+        Ok(Value::Object(values)) => values,
+        _ => Map::new(),
+    };
+    values.remove("suboptions");
+    let mut sources: HashMap<String, Source> = values
+        .keys()
+        .map(|key| (key.clone(), Source::Default))
+        .collect();
+
+    if let Some(config) = config {
+        for (key, value) in config {
+            if values.contains_key(&key) && !CLI_ONLY.contains(&key.as_str()) {
+                values.insert(key.clone(), value);
+                sources.insert(key, Source::ConfigFile);
+            }
+        }
     }
 
-    if args.hide_album_name {
-        config.hide_album_name = args.hide_album_name;
+    let args_values = match serde_json::to_value(&args) {
+        Ok(Value::Object(args_values)) => args_values,
+        _ => Map::new(),
+    };
+    // Environment variables first, then args in the order they were given,
+    // so "--hide-album-name --no-hide-album-name" turns the option off
+    let mut updates = Vec::new();
+    for arg in build_command().get_arguments() {
+        let id = arg.get_id().as_str();
+        let source = match matches.value_source(id) {
+            Some(ValueSource::EnvVariable) => Source::Env,
+            Some(ValueSource::CommandLine) => Source::Cli,
+            _ => continue,
+        };
+
+        // --no-* flags turn off their counterparts
+        let (key, value) = match id.strip_prefix("no_") {
+            Some(key) if !values.contains_key(id) => (key, Value::Bool(false)),
+            _ => (id, args_values.get(id).cloned().unwrap_or(Value::Null)),
+        };
+        let index = matches.index_of(id).unwrap_or(0);
+        updates.push((source == Source::Cli, index, key.to_string(), value, source));
     }
+    updates.sort_by_key(|(is_cli, index, ..)| (*is_cli, *index));
 
-    if args.only_when_playing {
-        config.only_when_playing = args.only_when_playing;
+    for (_, _, key, value, source) in updates {
+        if values.contains_key(&key) {
+            values.insert(key.clone(), value);
+            sources.insert(key, source);
+        }
     }
 
-    if args.disable_cache {
-        config.disable_cache = args.disable_cache;
+    let mut settings =
+        match serde_json::from_value::<<Cli as ClapSerde>::Opt>(Value::Object(values.clone())) {
+            Ok(opt) => Cli::from(opt),
+            Err(error) => {
+                error!("Failed to merge settings: {}", error);
+                Cli::default()
+            }
+        };
+    settings.list_players = args.list_players;
+    settings.get_player_id = args.get_player_id;
+    settings.debug_log = args.debug_log;
+    settings.reset_config = args.reset_config;
+    settings.suboptions = args.suboptions;
+
+    Merged {
+        settings,
+        values,
+        sources,
     }
+}
 
-    if args.list_players {
-        config.list_players = args.list_players;
+// Print effective settings as yaml, with the origin of every value as a comment
+fn show_config(args: Cli, matches: &ArgMatches) {
+    let config = match get_config_file() {
+        Some(config_file) if config_file.exists() => {
+            println!("# Config file: {}", config_file.display());
+            match read_config_file(&config_file) {
+                Ok(config) => Some(config),
+                Err(error) => {
+                    println!("# {}", error);
+                    None
+                }
+            }
+        }
+        _ => {
+            println!("# Config file: not found");
+            None
+        }
+    };
+
+    let merged = merge_settings(args, matches, config);
+    for arg in build_command().get_arguments() {
+        let key = arg.get_id().as_str();
+        if CLI_ONLY.contains(&key) {
+            continue;
+        }
+        let (value, source) = match (merged.values.get(key), merged.sources.get(key)) {
+            (Some(value), Some(source)) => (value, source),
+            _ => continue,
+        };
+
+        // Do not print secrets, users often paste this output in issues
+        let value = mask_secret(key, value);
+
+        let source = match source {
+            Source::Env => format!("{} {}{}", source, ENV_PREFIX, key.to_uppercase()),
+            source => source.to_string(),
+        };
+        println!("{}: {}  # {}", key, value, source);
     }
+}
 
-    if args.get_player_id {
-        config.get_player_id = args.get_player_id;
+// Value of the setting with passwords, keys and header values replaced by stars
+fn mask_secret(key: &str, value: &Value) -> Value {
+    let stars = || Value::String(String::from("********"));
+    match value {
+        Value::String(secret)
+            if SECRET_SUFFIXES.iter().any(|suffix| key.ends_with(suffix)) && !secret.is_empty() =>
+        {
+            stars()
+        }
+        // e.g. "Authorization: Bearer token", only the name is shown
+        Value::Array(headers) if key == "webhook_headers" => Value::Array(
+            headers
+                .iter()
+                .map(
+                    |header| match header.as_str().and_then(|h| h.split_once(':')) {
+                        Some((name, _)) => Value::String(format!("{}: ********", name)),
+                        None => stars(),
+                    },
+                )
+                .collect(),
+        ),
+        value => value.clone(),
     }
+}
+
+// Values derived from the merged settings and used by the main loop
+pub struct Options {
+    pub lastfm_api_key: String,
+    pub interval: u64,
+    pub lastfm_name: String,
+    pub listenbrainz_name: String,
+    pub rpc_name: String,
+    pub small_image: String,
+    pub lastfm_avatar: String,
+    pub lastfm_icon_text: String,
+    pub force_player_name: String,
+    pub force_player_id: String,
+    pub cache_enabled: bool,
+    pub allowlist_enabled: bool,
+    pub allowlist: Vec<PlayerPattern>,
+    pub blocklist: Vec<PlayerPattern>,
+    pub video_players: Vec<PlayerPattern>,
+    pub filters: Vec<Filter>,
+    pub filter_placeholder: String,
+    pub schedule: Vec<schedule::Rule>,
+    pub cover_providers: Vec<String>,
+    pub client_id: String,
+    pub video_client_id: String,
+}
+
+impl Options {
+    pub fn new(settings: &Cli, home_exists: bool) -> Options {
+        // Use api key provided by user
+        let lastfm_api_key = settings
+            .lastfm_api_key
+            .clone()
+            .unwrap_or(crate::LASTFM_API_KEY.into());
+        if lastfm_api_key.is_empty() {
+            warn!("Last.fm API key is not set. Album covers from Last.fm will not be available.");
+        }
+
+        // Main loop interval
+        let mut interval = settings.interval.unwrap_or(10);
+        if interval < 5 {
+            interval = 5
+        }
+        debug!("interval: {}", interval);
+
+        // Nicknames for buttons
+        let lastfm_name = settings.lastfm_name.clone().unwrap_or_default();
+        let listenbrainz_name = settings.listenbrainz_name.clone().unwrap_or_default();
+
+        // "Listening to ..."
+        let rpc_name = settings.rpc_name.clone().unwrap_or(String::from("artist"));
+
+        // Icon displayed next to the album cover
+        let small_image = settings
+            .small_image
+            .clone()
+            .unwrap_or(String::from("playPause"));
+        let mut lastfm_avatar = String::new();
+        if small_image == "lastfmAvatar" && !lastfm_name.is_empty() {
+            lastfm_avatar = get_lastfm_avatar(&lastfm_name, &lastfm_api_key);
+            debug!("lastfm_avatar: {}", lastfm_avatar);
+        }
+        let lastfm_icon_text = if !lastfm_name.is_empty() {
+            lastfm_name.to_string() + " on Last.fm"
+        } else {
+            String::new()
+        };
+
+        // Force player id and name
+        let force_player_name = settings.force_player_name.clone().unwrap_or_default();
+        let force_player_id = settings.force_player_id.clone().unwrap_or_default();
+
+        // Enable/disable use of cache
+        let mut cache_enabled: bool = !settings.disable_cache;
+        if !home_exists {
+            cache_enabled = false;
+        }
+
+        // Allowlist of music players
+        let allowlist_enabled: bool = !settings.allowlist.is_empty();
+        let allowlist = player_match::parse_all(&settings.allowlist);
+        let blocklist = player_match::parse_all(&settings.blocklist);
+        let video_players = player_match::parse_all(&settings.video_players);
+
+        // Content filters
+        let filters = filter::parse_all(&settings.filters);
+        let filter_placeholder = settings.filter_placeholder.clone().unwrap_or_default();
 
-    if args.allowlist != config.allowlist && args.allowlist.len() > 0 {
-        config.allowlist = args.allowlist;
+        // Quiet hours
+        let schedule = schedule::parse_all(&settings.schedule);
+
+        // Album cover sources, the old flag still removes MusicBrainz
+        let mut cover_providers = settings.cover_providers.clone();
+        if settings.disable_musicbrainz_cover {
+            cover_providers.retain(|provider| provider != "musicbrainz");
+        }
+        // Covers of Subsonic need the login, so they are never sent to Discord
+        if settings.source.as_deref() == Some("subsonic")
+            && cover_providers.iter().any(|provider| provider == "server")
+        {
+            warn!("Subsonic servers do not provide covers for Discord, the \"server\" cover provider will not be used.");
+        }
+        debug!("cover_providers: {:?}", cover_providers);
+
+        // Discord applications
+        let client_id = settings
+            .client_id
+            .clone()
+            .unwrap_or(String::from(DEFAULT_CLIENT_ID));
+        let video_client_id = settings
+            .video_client_id
+            .clone()
+            .unwrap_or(String::from(DEFAULT_VIDEO_CLIENT_ID));
+
+        Options {
+            lastfm_api_key,
+            interval,
+            lastfm_name,
+            listenbrainz_name,
+            rpc_name,
+            small_image,
+            lastfm_avatar,
+            lastfm_icon_text,
+            force_player_name,
+            force_player_id,
+            cache_enabled,
+            allowlist_enabled,
+            allowlist,
+            blocklist,
+            video_players,
+            filters,
+            filter_placeholder,
+            schedule,
+            cover_providers,
+            client_id,
+            video_client_id,
+        }
     }
+}
 
-    if args.video_players != config.video_players && args.video_players.len() > 0 {
-        config.video_players = args.video_players;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Values of a list option as parsed from the command line
+    fn parse_list(id: &str, args: &[&str]) -> Vec<String> {
+        let matches = build_command()
+            .try_get_matches_from([&["music-discord-rpc"], args].concat())
+            .unwrap();
+        matches
+            .get_many::<String>(id)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
     }
 
-    if args.lastfm_api_key != config.lastfm_api_key && args.lastfm_api_key.is_some() {
-        config.lastfm_api_key = args.lastfm_api_key;
+    fn check(yaml_str: &str) -> Vec<String> {
+        validate::check_config(yaml_str, &build_command(), &CLI_ONLY, &CONFIG_ONLY)
+            .into_iter()
+            .filter(|diagnostic| diagnostic.is_error)
+            .map(|diagnostic| diagnostic.message)
+            .collect()
     }
 
-    if args.disable_mpris_art_url {
-        config.disable_mpris_art_url = args.disable_mpris_art_url;
+    // Commented out example list of the config template, e.g. "# schedule:"
+    fn example(key: &str) -> String {
+        let header = format!("# {}:", key);
+        let mut lines = CONFIG_TEMPLATE
+            .lines()
+            .skip_while(|line| *line != header)
+            .take_while(|line| *line == header || line.starts_with("#   - "));
+        let mut example = String::new();
+        for line in lines.by_ref() {
+            example += line.trim_start_matches("# ");
+            example += "\n";
+        }
+        example
     }
 
-    if args.debug_log {
-        config.debug_log = args.debug_log;
+    #[test]
+    fn lists_with_fixed_values_are_comma_separated() {
+        assert_eq!(parse_list("button", &["-b", "yt,lastfm"]), ["yt", "lastfm"]);
+        assert_eq!(
+            parse_list(
+                "cover_providers",
+                &["--cover-provider", "musicbrainz,lastfm"]
+            ),
+            ["musicbrainz", "lastfm"]
+        );
     }
 
-    if args.reset_config {
-        config.reset_config = args.reset_config;
+    #[test]
+    fn other_lists_keep_commas() {
+        // A value with commas for every list that is not split
+        let values = [
+            ("allowlist", "Player, The"),
+            ("blocklist", "/^(mpv|vlc){1,2}$/"),
+            ("video_players", "/^(mpv|vlc){1,2}$/"),
+            ("filters", "hide; title=a{1,3}"),
+            (
+                "schedule",
+                "only; days=sat,sun; time=22:00-08:00; player=mpv",
+            ),
+            ("output_files", "/tmp/now_playing.txt={artist}, {title}"),
+            ("webhook_headers", "Accept: text/plain, application/json"),
+        ];
+
+        let command = build_command();
+        let lists = command.get_arguments().filter(|arg| {
+            matches!(arg.get_action(), ArgAction::Append)
+                && !COMMA_SEPARATED.contains(&arg.get_id().as_str())
+        });
+        for arg in lists {
+            let key = arg.get_id().as_str();
+            let (_, value) = values
+                .iter()
+                .find(|(list, _)| *list == key)
+                .unwrap_or_else(|| panic!("no value for {}", key));
+            let flag = format!("--{}", arg.get_long().unwrap());
+            assert_eq!(parse_list(key, &[&flag, value]), [*value], "{}", key);
+            assert_eq!(
+                check(&format!("{}:\n  - \"{}\"\n", key, value)),
+                Vec::<String>::new(),
+                "{}",
+                key
+            );
+
+            let example = example(key);
+            assert!(!example.is_empty(), "no example of {}", key);
+            assert_eq!(check(&example), Vec::<String>::new(), "{}", key);
+        }
     }
 
-    config.suboptions = args.suboptions;
+    #[test]
+    fn secrets_are_masked() {
+        let masked = |key: &str, value: Value| mask_secret(key, &value);
+        assert_eq!(masked("lastfm_api_key", json!("abc")), json!("********"));
+        assert_eq!(masked("mpd_password", json!("abc")), json!("********"));
+        assert_eq!(masked("mqtt_password", json!("abc")), json!("********"));
+        assert_eq!(masked("server_token", json!("abc")), json!("********"));
+        assert_eq!(masked("mpd_password", json!("")), json!(""));
+        assert_eq!(masked("lastfm_name", json!("abc")), json!("abc"));
+        assert_eq!(
+            masked(
+                "webhook_headers",
+                json!(["Authorization: Bearer abc", "broken"])
+            ),
+            json!(["Authorization: ********", "********"])
+        );
+    }
 
-    return config;
+    #[test]
+    fn config_list_items_are_checked_whole() {
+        // Config files are not split, so the item is not a single provider
+        assert_eq!(
+            check("cover_providers:\n  - \"lastfm,musicbrainz\"\n").len(),
+            1
+        );
+    }
 }
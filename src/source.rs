@@ -0,0 +1,96 @@
+use std::sync::mpsc::Sender;
+
+#[cfg(target_os = "linux")]
+use mpris::Player;
+
+use crate::cmus;
+use crate::events::Event;
+use crate::jellyfin;
+use crate::json_input;
+use crate::moc;
+use crate::mpd;
+use crate::mpv;
+#[cfg(target_os = "linux")]
+use crate::player_match;
+use crate::player_match::PlayerPattern;
+use crate::settings::Cli;
+use crate::subsonic;
+use crate::utils::{self, NowPlayingResult};
+
+// Where the track info comes from. MPRIS (Linux) and media-control (macOS)
+// are used by default, other players have their own protocols.
+pub trait Source {
+    // Player name, used like the MPRIS identity in the allowlist and activity
+    fn name(&self) -> String;
+
+    // Currently loaded track, an error means there is no player to show
+    fn get_media_info(&mut self) -> NowPlayingResult;
+
+    // Use the "watching" activity for the current track, e.g. video files in mpv
+    fn is_video(&self) -> bool {
+        false
+    }
+
+    // True if any of the allowlist, blocklist or video_players entries is this player
+    fn matches(&self, patterns: &[PlayerPattern]) -> bool {
+        let name = self.name();
+        patterns
+            .iter()
+            .any(|pattern| pattern.matches(&name, "", ""))
+    }
+}
+
+// Player found on the session bus
+#[cfg(target_os = "linux")]
+pub struct Mpris(pub Player);
+
+#[cfg(target_os = "linux")]
+impl Source for Mpris {
+    fn name(&self) -> String {
+        self.0.identity().to_string()
+    }
+
+    fn get_media_info(&mut self) -> NowPlayingResult {
+        utils::get_currently_playing(&self.0)
+    }
+
+    fn matches(&self, patterns: &[PlayerPattern]) -> bool {
+        player_match::find(patterns, &self.0).is_some()
+    }
+}
+
+// Player reported by media-control, it is lost when another app starts playing
+#[cfg(target_os = "macos")]
+pub struct MediaControl {
+    pub player_id: String,
+}
+
+#[cfg(target_os = "macos")]
+impl Source for MediaControl {
+    fn name(&self) -> String {
+        self.player_id.clone()
+    }
+
+    fn get_media_info(&mut self) -> NowPlayingResult {
+        let media_info = utils::get_currently_playing()?;
+        if media_info.player_id != self.player_id {
+            return Err("Detected player change.".into());
+        }
+        Ok(media_info)
+    }
+}
+
+// Source selected in settings, None for the default one of the system. The
+// sender is used to wake up the main loop when the player reports a change.
+pub fn new(settings: &Cli, events: Sender<Event>) -> Option<Box<dyn Source>> {
+    match settings.source.as_deref() {
+        Some("mpd") => Some(Box::new(mpd::Mpd::new(settings, events))),
+        Some("mpv") => Some(Box::new(mpv::Mpv::new(settings, events))),
+        Some("cmus") => Some(Box::new(cmus::Cmus::new(settings))),
+        Some("moc") => Some(Box::new(moc::Moc)),
+        Some("subsonic") => Some(Box::new(subsonic::Subsonic::new(settings))),
+        Some("jellyfin") => Some(Box::new(jellyfin::Jellyfin::new(settings))),
+        Some("json") => Some(Box::new(json_input::JsonInput::new(settings, events))),
+        _ => None,
+    }
+}
@@ -0,0 +1,284 @@
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::settings::Cli;
+use crate::source::Source;
+use crate::utils::{MediaInfo, NowPlayingResult};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Subsonic API servers like Navidrome, read with getNowPlaying, see
+// https://www.subsonic.org/pages/api.jsp
+pub struct Subsonic {
+    client: Client,
+    url: String,
+    user: String,
+    // Only sent as md5(password + salt) with a new salt for every request
+    password: String,
+    // Id of the current entry, when it was first seen and its position then
+    current: Option<(String, Instant, u64)>,
+}
+
+impl Subsonic {
+    pub fn new(settings: &Cli) -> Subsonic {
+        Subsonic {
+            client: Client::builder()
+                .timeout(Duration::from_secs(10))
+                .user_agent(format!("music-discord-rpc/{}", VERSION))
+                .build()
+                .unwrap_or_default(),
+            url: settings
+                .server_url
+                .clone()
+                .unwrap_or_default()
+                .trim_end_matches('/')
+                .to_string(),
+            user: settings.server_user.clone().unwrap_or_default(),
+            password: settings.server_token.clone().unwrap_or_default(),
+            current: None,
+        }
+    }
+
+    // Endpoint url with the token authentication, it must never leave the
+    // process, the token can be used again by anyone who has the url
+    fn endpoint(&self, method: &str) -> String {
+        let salt = format!(
+            "{:x}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        );
+        let token = md5::compute(format!("{}{}", self.password, salt));
+        format!(
+            "{}/rest/{}?u={}&t={:x}&s={}&v=1.13.0&c=music-discord-rpc&f=json",
+            self.url,
+            method,
+            url_escape::encode_component(&self.user),
+            token,
+            salt
+        )
+    }
+}
+
+impl Source for Subsonic {
+    fn name(&self) -> String {
+        String::from("Subsonic")
+    }
+
+    fn get_media_info(&mut self) -> NowPlayingResult {
+        if self.url.is_empty() {
+            return Err("Server url is not set.".into());
+        }
+        let response: Value = self
+            .client
+            .get(self.endpoint("getNowPlaying"))
+            .send()?
+            .error_for_status()?
+            .json()?;
+        self.media_info(&response)
+    }
+}
+
+impl Subsonic {
+    fn media_info(&mut self, response: &Value) -> NowPlayingResult {
+        let response = &response["subsonic-response"];
+        if response["status"] != "ok" {
+            let message = response["error"]["message"].as_str().unwrap_or("unknown");
+            return Err(format!("Subsonic error: {}.", message).into());
+        }
+
+        // Entries of every user of the server, the most recent one is used
+        let entry = response["nowPlaying"]["entry"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|entry| self.user.is_empty() || entry["username"] == self.user.as_str())
+            .min_by_key(|entry| entry["minutesAgo"].as_u64().unwrap_or(0));
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                self.current = None;
+                return Err("Nothing is playing on the Subsonic server.".into());
+            }
+        };
+
+        let text = |key: &str| -> Option<String> {
+            entry[key]
+                .as_str()
+                .filter(|value| !value.is_empty())
+                .map(|value| value.to_string())
+        };
+        let artist = text("artist").unwrap_or_else(|| String::from("Unknown Artist"));
+        let album = text("album").unwrap_or_else(|| String::from("Unknown Album"));
+        let album_artist = text("displayAlbumArtist").unwrap_or(artist.clone());
+        let duration = entry["duration"].as_u64().unwrap_or(0);
+
+        // The server only knows when the track started in whole minutes, so
+        // the position is counted from the first time the entry was seen
+        let id = text("id").unwrap_or_default();
+        let (since, offset) = match &self.current {
+            Some((current_id, since, offset)) if *current_id == id => (*since, *offset),
+            _ => {
+                let offset = entry["minutesAgo"].as_u64().unwrap_or(0) * 60;
+                self.current = Some((id, Instant::now(), offset));
+                (Instant::now(), offset)
+            }
+        };
+        let mut position = offset + since.elapsed().as_secs();
+        if duration > 0 {
+            position = position.min(duration);
+        }
+
+        Ok(MediaInfo {
+            title: text("title").unwrap_or_else(|| String::from("Unknown Title")),
+            artist,
+            album_artist,
+            album,
+            // Paused tracks are not reported by the API
+            is_playing: true,
+            duration,
+            position,
+            is_track_position: true,
+            // Covers are only served with the login, so they can't be sent to Discord
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn subsonic(user: &str) -> Subsonic {
+        Subsonic {
+            client: Client::new(),
+            url: String::from("https://music.example.org"),
+            user: user.to_string(),
+            password: String::from("sesame"),
+            current: None,
+        }
+    }
+
+    // Response of getNowPlaying from Navidrome
+    fn now_playing() -> Value {
+        json!({
+            "subsonic-response": {
+                "status": "ok",
+                "version": "1.16.1",
+                "type": "navidrome",
+                "serverVersion": "0.53.3",
+                "openSubsonic": true,
+                "nowPlaying": {
+                    "entry": [
+                        {
+                            "id": "2b9f0e6d8c1a",
+                            "parent": "7c1d2e3f",
+                            "isDir": false,
+                            "title": "Paranoid Android",
+                            "album": "OK Computer",
+                            "artist": "Radiohead",
+                            "displayAlbumArtist": "Radiohead",
+                            "track": 2,
+                            "year": 1997,
+                            "coverArt": "mf-2b9f0e6d8c1a",
+                            "duration": 383,
+                            "path": "Radiohead/OK Computer/02 - Paranoid Android.flac",
+                            "username": "alice",
+                            "minutesAgo": 1,
+                            "playerId": 1,
+                            "playerName": "Feishin"
+                        },
+                        {
+                            "id": "5e4d3c2b1a09",
+                            "title": "Other Song",
+                            "artist": "Other Artist",
+                            "duration": 200,
+                            "username": "bob",
+                            "minutesAgo": 0,
+                            "playerId": 2
+                        }
+                    ]
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn entry_of_the_user() {
+        let mut subsonic = subsonic("alice");
+        let media_info = subsonic.media_info(&now_playing()).unwrap();
+        assert_eq!(media_info.title, "Paranoid Android");
+        assert_eq!(media_info.artist, "Radiohead");
+        assert_eq!(media_info.album_artist, "Radiohead");
+        assert_eq!(media_info.album, "OK Computer");
+        assert_eq!(media_info.duration, 383);
+        assert_eq!(media_info.position, 60);
+        assert!(media_info.is_playing);
+        // The cover needs the login, it must not be used
+        assert_eq!(media_info.art_url, "");
+
+        // Most recent entry without a user
+        let media_info = self::subsonic("").media_info(&now_playing()).unwrap();
+        assert_eq!(media_info.title, "Other Song");
+        assert_eq!(media_info.album, "Unknown Album");
+    }
+
+    #[test]
+    fn nothing_playing_and_errors() {
+        let mut subsonic = subsonic("carol");
+        let result = subsonic.media_info(&now_playing());
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Nothing is playing on the Subsonic server."
+        );
+        let empty =
+            json!({"subsonic-response": {"status": "ok", "version": "1.16.1", "nowPlaying": {}}});
+        assert!(subsonic.media_info(&empty).is_err());
+
+        let failed = json!({
+            "subsonic-response": {
+                "status": "failed",
+                "version": "1.16.1",
+                "error": {"code": 40, "message": "Wrong username or password"}
+            }
+        });
+        assert_eq!(
+            subsonic.media_info(&failed).err().unwrap().to_string(),
+            "Subsonic error: Wrong username or password."
+        );
+    }
+
+    #[test]
+    fn new_salt_for_every_request() {
+        let subsonic = subsonic("alice");
+        let query = |url: &str| -> Vec<(String, String)> {
+            let (_, query) = url.split_once('?').unwrap();
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let value = |query: &[(String, String)], key: &str| {
+            query
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.clone())
+                .unwrap()
+        };
+
+        let first = query(&subsonic.endpoint("getNowPlaying"));
+        std::thread::sleep(Duration::from_millis(1));
+        let second = query(&subsonic.endpoint("getNowPlaying"));
+        assert_ne!(value(&first, "s"), value(&second, "s"));
+        for query in [first, second] {
+            let token = md5::compute(format!("sesame{}", value(&query, "s")));
+            assert_eq!(value(&query, "t"), format!("{:x}", token));
+            assert_eq!(value(&query, "u"), "alice");
+            assert!(query.iter().all(|(key, _)| key != "p"));
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use crate::now_playing::NowPlaying;
+
+// Replace {name} placeholders with values of the current track. Unknown
+// placeholders are left as they are. With escape, values are escaped for
+// use inside JSON strings.
+pub fn render(template: &str, now_playing: &NowPlaying, escape: bool) -> String {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output += &rest[..start];
+        rest = &rest[start..];
+
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        // Braces of a JSON template, e.g. {"text": "{title}"}
+        if rest[1..end].contains('{') {
+            output.push('{');
+            rest = &rest[1..];
+            continue;
+        }
+        match value(&rest[1..end], now_playing) {
+            Some(value) if escape => output += &escape_json(&value),
+            Some(value) => output += &value,
+            None => output += &rest[..=end],
+        }
+        rest = &rest[end + 1..];
+    }
+    output += rest;
+    output
+}
+
+fn value(name: &str, now_playing: &NowPlaying) -> Option<String> {
+    let value = match name {
+        "title" => now_playing.title.clone(),
+        "artist" => now_playing.artist.clone(),
+        "album" => now_playing.album.clone(),
+        "player" => now_playing.player.clone(),
+        "player_id" => now_playing.player_id.clone(),
+        "status" => now_playing.status().to_string(),
+        "cover_url" => now_playing.cover_url.clone(),
+        "path" => now_playing.path.clone(),
+        "duration" => format_time(now_playing.duration),
+        "position" => format_time(now_playing.position),
+        _ => return None,
+    };
+    Some(value)
+}
+
+// 3:05, or 1:02:03 for long tracks
+fn format_time(seconds: u64) -> String {
+    match seconds >= 3600 {
+        true => format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ),
+        false => format!("{}:{:02}", seconds / 60, seconds % 60),
+    }
+}
+
+fn escape_json(value: &str) -> String {
+    let quoted = serde_json::Value::from(value).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Status;
+
+    fn now_playing() -> NowPlaying {
+        NowPlaying::new(&Status {
+            player: String::from("mpv"),
+            title: String::from("\"Airbag\""),
+            artist: String::from("Radiohead"),
+            is_playing: true,
+            duration: 3725,
+            position: 65,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn placeholders() {
+        let now_playing = now_playing();
+        assert_eq!(
+            render(
+                "{artist} - {title} ({position}/{duration})",
+                &now_playing,
+                false
+            ),
+            "Radiohead - \"Airbag\" (1:05/1:02:05)"
+        );
+        assert_eq!(
+            render("{status} in {player}", &now_playing, false),
+            "playing in mpv"
+        );
+        // Unknown or unfinished placeholders are kept
+        assert_eq!(
+            render("{year} {album", &now_playing, false),
+            "{year} {album"
+        );
+        assert_eq!(render("{}", &now_playing, false), "{}");
+    }
+
+    #[test]
+    fn json_templates() {
+        assert_eq!(
+            render(
+                r#"{"text": "{artist} - {title}", "nested": {"event": "{status}"}}"#,
+                &now_playing(),
+                true
+            ),
+            r#"{"text": "Radiohead - \"Airbag\"", "nested": {"event": "playing"}}"#
+        );
+    }
+}
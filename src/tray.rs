@@ -0,0 +1,676 @@
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply;
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender as _};
+use dbus::message::MatchRule;
+use dbus::{Message, Path};
+use dbus_crossroads::{Crossroads, IfaceBuilder, MethodErr};
+use log::{error, info, warn};
+use mpris::PlayerFinder;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::control::{self, SharedStatus, Status};
+use crate::events::Event;
+
+// StatusNotifierItem protocol: https://www.freedesktop.org/wiki/Specifications/StatusNotifierItem/
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+const MENU_INTERFACE: &str = "com.canonical.dbusmenu";
+
+// Menu item ids
+const MENU_TRACK: i32 = 1;
+const MENU_PAUSE: i32 = 3;
+const MENU_PLAYERS: i32 = 4;
+const MENU_CONFIG: i32 = 5;
+const MENU_QUIT: i32 = 7;
+const MENU_ANY_PLAYER: i32 = 100;
+
+// Menu item with its properties and children: (ia{sv}av)
+type Layout = (i32, PropMap, Vec<Variant<Box<dyn RefArg>>>);
+// Icon name, icon pixmaps, title and description: (sa(iiay)ss)
+type ToolTip = (String, Vec<(i32, i32, Vec<u8>)>, String, String);
+// Item id, event name, event data and timestamp
+type MenuEvent = (i32, String, Variant<Box<dyn RefArg>>, u32);
+
+// Data of both exported objects, the icon and its menu
+struct Tray {
+    sender: Sender<Event>,
+    status: SharedStatus,
+    config_file: Option<PathBuf>,
+    revision: Arc<AtomicU32>,
+    // Players shown in the "Preferred player" submenu, refreshed when the menu is opened
+    players: Vec<String>,
+}
+
+impl Tray {
+    fn status(&self) -> Status {
+        match self.status.lock() {
+            Ok(status) => status.clone(),
+            Err(_) => Status::default(),
+        }
+    }
+
+    fn send(&self, event: Event) {
+        control::send_event(event, &self.sender, &self.status);
+    }
+
+    fn toggle_presence(&self) {
+        match self.status().presence_paused {
+            true => self.send(Event::Resume),
+            false => self.send(Event::Pause),
+        }
+    }
+
+    fn clicked(&self, id: i32) {
+        match id {
+            MENU_PAUSE => self.toggle_presence(),
+            MENU_ANY_PLAYER => self.send(Event::PreferPlayer(None)),
+            MENU_CONFIG => match &self.config_file {
+                Some(config_file) => {
+                    if let Err(err) = process::Command::new("xdg-open").arg(config_file).spawn() {
+                        warn!("[tray] Could not open config file: {}", err);
+                    }
+                }
+                None => warn!("[tray] Config file not found."),
+            },
+            MENU_QUIT => self.send(Event::Shutdown),
+            id if id > MENU_ANY_PLAYER => {
+                let index = (id - MENU_ANY_PLAYER - 1) as usize;
+                if let Some(player) = self.players.get(index) {
+                    self.send(Event::PreferPlayer(Some(player.clone())));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn update_players(&mut self) -> bool {
+        let players: Vec<String> = match PlayerFinder::new().map(|finder| finder.find_all()) {
+            Ok(Ok(players)) => players
+                .iter()
+                .map(|player| player.identity().to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let changed = players != self.players;
+        self.players = players;
+        changed
+    }
+
+    // Whole menu as (id, properties, children)
+    fn layout(&self, id: i32) -> Option<Layout> {
+        let status = self.status();
+        let track = match status.title.is_empty() {
+            true => String::from("Nothing playing"),
+            false => format!("{} - {}", status.artist, status.title),
+        };
+        let pause_label = match status.presence_paused {
+            true => "Resume presence",
+            false => "Pause presence",
+        };
+
+        let mut players = vec![radio_item(
+            MENU_ANY_PLAYER,
+            "Any player",
+            status.preferred_player.is_empty(),
+        )];
+        for (index, player) in self.players.iter().enumerate() {
+            players.push(radio_item(
+                MENU_ANY_PLAYER + 1 + index as i32,
+                player,
+                &status.preferred_player == player,
+            ));
+        }
+
+        let mut track_item = item(MENU_TRACK, &track);
+        track_item
+            .1
+            .insert(String::from("enabled"), Variant(Box::new(false)));
+        let mut players_item = item(MENU_PLAYERS, "Preferred player");
+        players_item.1.insert(
+            String::from("children-display"),
+            Variant(Box::new(String::from("submenu"))),
+        );
+
+        let menu: Vec<(Layout, Vec<Layout>)> = vec![
+            (track_item, Vec::new()),
+            (separator(2), Vec::new()),
+            (item(MENU_PAUSE, pause_label), Vec::new()),
+            (players_item, players),
+            (item(MENU_CONFIG, "Open config file"), Vec::new()),
+            (separator(6), Vec::new()),
+            (item(MENU_QUIT, "Quit"), Vec::new()),
+        ];
+
+        let mut root = (0, PropMap::new(), Vec::new());
+        for (mut menu_item, children) in menu {
+            if menu_item.0 == id {
+                menu_item.2 = children.into_iter().map(to_variant).collect();
+                return Some(menu_item);
+            }
+            for child in children {
+                if child.0 == id {
+                    return Some(child);
+                }
+                menu_item.2.push(to_variant(child));
+            }
+            root.2.push(to_variant(menu_item));
+        }
+        match id {
+            0 => Some(root),
+            _ => None,
+        }
+    }
+
+    // Properties of every item, used by GetGroupProperties
+    fn properties(&self, id: i32) -> Option<PropMap> {
+        self.layout(id).map(|layout| layout.1)
+    }
+}
+
+fn item(id: i32, label: &str) -> Layout {
+    let mut properties = PropMap::new();
+    properties.insert(String::from("label"), Variant(Box::new(label.to_string())));
+    (id, properties, Vec::new())
+}
+
+fn radio_item(id: i32, label: &str, selected: bool) -> Layout {
+    let mut radio_item = item(id, label);
+    radio_item.1.insert(
+        String::from("toggle-type"),
+        Variant(Box::new(String::from("radio"))),
+    );
+    radio_item.1.insert(
+        String::from("toggle-state"),
+        Variant(Box::new(selected as i32)),
+    );
+    radio_item
+}
+
+fn separator(id: i32) -> Layout {
+    let mut properties = PropMap::new();
+    properties.insert(
+        String::from("type"),
+        Variant(Box::new(String::from("separator"))),
+    );
+    (id, properties, Vec::new())
+}
+
+fn to_variant(layout: Layout) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(layout))
+}
+
+fn icon_name(status: &Status) -> String {
+    match status.presence_paused {
+        true => String::from("media-playback-pause"),
+        false => String::from("media-playback-start"),
+    }
+}
+
+fn tooltip(status: &Status) -> ToolTip {
+    let mut description = match status.title.is_empty() {
+        true => String::from("Nothing playing"),
+        false => format!("{} - {}", status.artist, status.title),
+    };
+    if !status.player.is_empty() {
+        description += &format!("\nPlayer: {}", status.player);
+    }
+    if status.presence_paused {
+        description += "\nPresence paused";
+    }
+    (
+        icon_name(status),
+        Vec::new(),
+        String::from("Music Discord RPC"),
+        description,
+    )
+}
+
+fn register_item(b: &mut IfaceBuilder<Tray>) {
+    b.property("Category")
+        .get(|_, _| Ok(String::from("ApplicationStatus")));
+    b.property("Id")
+        .get(|_, _| Ok(String::from("music-discord-rpc")));
+    b.property("Title")
+        .get(|_, _| Ok(String::from("Music Discord RPC")));
+    b.property("Status").get(|_, _| Ok(String::from("Active")));
+    b.property("WindowId").get(|_, _| Ok(0i32));
+    b.property("IconName")
+        .get(|_, tray: &mut Tray| Ok(icon_name(&tray.status())));
+    b.property("ToolTip")
+        .get(|_, tray: &mut Tray| Ok(tooltip(&tray.status())));
+    b.property("ItemIsMenu").get(|_, _| Ok(false));
+    b.property("Menu").get(|_, _| Ok(Path::from(MENU_PATH)));
+
+    // Left click pauses or resumes the presence, middle click sends it again
+    b.method(
+        "Activate",
+        ("x", "y"),
+        (),
+        |_, tray: &mut Tray, (_, _): (i32, i32)| {
+            tray.toggle_presence();
+            Ok(())
+        },
+    );
+    b.method(
+        "SecondaryActivate",
+        ("x", "y"),
+        (),
+        |_, tray: &mut Tray, (_, _): (i32, i32)| {
+            tray.send(Event::Refresh);
+            Ok(())
+        },
+    );
+    b.method("ContextMenu", ("x", "y"), (), |_, _, (_, _): (i32, i32)| {
+        Ok(())
+    });
+    b.method(
+        "Scroll",
+        ("delta", "orientation"),
+        (),
+        |_, _, (_, _): (i32, String)| Ok(()),
+    );
+
+    b.signal::<(), _>("NewIcon", ());
+    b.signal::<(), _>("NewToolTip", ());
+}
+
+fn register_menu(b: &mut IfaceBuilder<Tray>) {
+    b.property("Version").get(|_, _| Ok(3u32));
+    b.property("TextDirection")
+        .get(|_, _| Ok(String::from("ltr")));
+    b.property("Status").get(|_, _| Ok(String::from("normal")));
+    b.property("IconThemePath")
+        .get(|_, _| Ok(Vec::<String>::new()));
+
+    b.method(
+        "GetLayout",
+        ("parentId", "recursionDepth", "propertyNames"),
+        ("revision", "layout"),
+        |_, tray: &mut Tray, (parent_id, _, _): (i32, i32, Vec<String>)| {
+            if parent_id == 0 || parent_id == MENU_PLAYERS {
+                tray.update_players();
+            }
+            match tray.layout(parent_id) {
+                Some(layout) => Ok((tray.revision.load(Ordering::Relaxed), layout)),
+                None => Err(MethodErr::invalid_arg(&parent_id)),
+            }
+        },
+    );
+    b.method(
+        "GetGroupProperties",
+        ("ids", "propertyNames"),
+        ("properties",),
+        |_, tray: &mut Tray, (ids, _): (Vec<i32>, Vec<String>)| {
+            let properties: Vec<(i32, PropMap)> = ids
+                .into_iter()
+                .filter_map(|id| tray.properties(id).map(|properties| (id, properties)))
+                .collect();
+            Ok((properties,))
+        },
+    );
+    b.method(
+        "GetProperty",
+        ("id", "name"),
+        ("value",),
+        |_, tray: &mut Tray, (id, name): (i32, String)| match tray
+            .properties(id)
+            .and_then(|mut properties| properties.remove(&name))
+        {
+            Some(value) => Ok((value,)),
+            None => Err(MethodErr::invalid_arg(&name)),
+        },
+    );
+    b.method(
+        "Event",
+        ("id", "eventId", "data", "timestamp"),
+        (),
+        |_, tray: &mut Tray, (id, event_id, _, _): MenuEvent| {
+            if event_id == "clicked" {
+                tray.clicked(id);
+            }
+            Ok(())
+        },
+    );
+    b.method(
+        "EventGroup",
+        ("events",),
+        ("idErrors",),
+        |_, tray: &mut Tray, (events,): (Vec<MenuEvent>,)| {
+            for (id, event_id, _, _) in events {
+                if event_id == "clicked" {
+                    tray.clicked(id);
+                }
+            }
+            Ok((Vec::<i32>::new(),))
+        },
+    );
+    b.method(
+        "AboutToShow",
+        ("id",),
+        ("needUpdate",),
+        |_, tray: &mut Tray, (id,): (i32,)| {
+            let need_update = (id == 0 || id == MENU_PLAYERS) && tray.update_players();
+            Ok((need_update,))
+        },
+    );
+    b.method(
+        "AboutToShowGroup",
+        ("ids",),
+        ("updatesNeeded", "idErrors"),
+        |_, tray: &mut Tray, (ids,): (Vec<i32>,)| {
+            let mut updates_needed = Vec::new();
+            if ids.contains(&MENU_PLAYERS) && tray.update_players() {
+                updates_needed.push(MENU_PLAYERS);
+            }
+            Ok((updates_needed, Vec::<i32>::new()))
+        },
+    );
+
+    b.signal::<(u32, i32), _>("LayoutUpdated", ("revision", "parent"));
+}
+
+// Show icon in the system tray. Works with any tray that implements the
+// StatusNotifierItem protocol (KDE, GNOME with AppIndicator extension, waybar, ...)
+pub fn start(sender: Sender<Event>, status: SharedStatus, config_file: Option<PathBuf>) {
+    thread::spawn(move || {
+        let result = Connection::new_session()
+            .and_then(|connection| serve(connection, sender, status, config_file));
+        if let Err(err) = result {
+            error!("[tray] Could not create tray icon: {}", err);
+        }
+    });
+}
+
+fn serve(
+    connection: Connection,
+    sender: Sender<Event>,
+    status: SharedStatus,
+    config_file: Option<PathBuf>,
+) -> Result<(), dbus::Error> {
+    let name = format!("org.kde.StatusNotifierItem-{}-1", process::id());
+    if connection.request_name(&name, false, true, true)? != RequestNameReply::PrimaryOwner {
+        return Err(dbus::Error::new_failed("could not own tray item name"));
+    }
+
+    let revision = Arc::new(AtomicU32::new(1));
+    let mut crossroads = Crossroads::new();
+    let item_interface = crossroads.register(ITEM_INTERFACE, register_item);
+    let menu_interface = crossroads.register(MENU_INTERFACE, register_menu);
+    for (path, interface) in [(ITEM_PATH, item_interface), (MENU_PATH, menu_interface)] {
+        crossroads.insert(
+            path,
+            &[interface],
+            Tray {
+                sender: sender.clone(),
+                status: status.clone(),
+                config_file: config_file.clone(),
+                revision: revision.clone(),
+                players: Vec::new(),
+            },
+        );
+    }
+    connection.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |message, connection| {
+            let _ = crossroads.handle_message(message, connection);
+            true
+        }),
+    );
+
+    // Register again every time the tray (re)starts, e.g. after restarting the panel
+    let watcher_started = Arc::new(AtomicBool::new(true));
+    let rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+        .with_sender("org.freedesktop.DBus");
+    connection.add_match_no_cb(&rule.match_str())?;
+    let watcher_started_signal = watcher_started.clone();
+    connection.start_receive(
+        rule,
+        Box::new(move |message, _| {
+            if let (Some(name), _, Some(new_owner)) = message.get3::<String, String, String>() {
+                if name == WATCHER_NAME && !new_owner.is_empty() {
+                    watcher_started_signal.store(true, Ordering::Relaxed);
+                }
+            }
+            true
+        }),
+    );
+
+    let mut is_registered = false;
+    let mut last_status = Status::default();
+    loop {
+        connection.process(Duration::from_millis(500))?;
+
+        if watcher_started.swap(false, Ordering::Relaxed) {
+            let watcher = connection.with_proxy(WATCHER_NAME, WATCHER_PATH, Duration::from_secs(2));
+            let result: Result<(), dbus::Error> =
+                watcher.method_call(WATCHER_NAME, "RegisterStatusNotifierItem", (name.as_str(),));
+            match result {
+                Ok(_) => {
+                    info!("[tray] Icon added to the system tray.");
+                    is_registered = true;
+                }
+                Err(_) if !is_registered => {
+                    warn!("[tray] System tray not found, waiting for it to start...");
+                }
+                Err(_) => {}
+            }
+        }
+
+        // Update icon, tooltip and menu when status changed in the main loop
+        let current_status = match status.lock() {
+            Ok(status) => status.clone(),
+            Err(_) => continue,
+        };
+        if current_status == last_status {
+            continue;
+        }
+
+        if icon_name(&current_status) != icon_name(&last_status) {
+            send_signal(&connection, ITEM_PATH, ITEM_INTERFACE, "NewIcon");
+        }
+        if tooltip(&current_status) != tooltip(&last_status) {
+            send_signal(&connection, ITEM_PATH, ITEM_INTERFACE, "NewToolTip");
+        }
+        let revision = revision.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Ok(message) = Message::new_signal(MENU_PATH, MENU_INTERFACE, "LayoutUpdated") {
+            let _ = connection.send(message.append2(revision, 0i32));
+        }
+        last_status = current_status;
+    }
+}
+
+fn send_signal(connection: &Connection, path: &str, interface: &str, name: &str) {
+    if let Ok(message) = Message::new_signal(path, interface, name) {
+        let _ = connection.send(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::channel::Channel;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+
+    // Private session bus, stopped when the test ends
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Option<Bus> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+            Some(Bus {
+                daemon,
+                address: address.trim().to_string(),
+            })
+        }
+
+        fn connect(&self) -> Connection {
+            let mut channel = Channel::open_private(&self.address).unwrap();
+            channel.register().unwrap();
+            Connection::from(channel)
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    fn label(layout: &Variant<Box<dyn RefArg>>) -> String {
+        let mut fields = layout.0.as_iter().unwrap();
+        let _id = fields.next();
+        let properties = fields.next().unwrap();
+        let mut properties = properties.as_iter().unwrap();
+        while let Some(key) = properties.next() {
+            let value = properties.next().unwrap();
+            if key.as_str() == Some("label") {
+                return value.as_str().unwrap().to_string();
+            }
+        }
+        String::new()
+    }
+
+    #[test]
+    fn registers_with_watcher() {
+        let Some(bus) = Bus::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+
+        // Watcher that remembers the items registered with it
+        let watcher = bus.connect();
+        watcher
+            .request_name(WATCHER_NAME, false, true, true)
+            .unwrap();
+        let registered = Arc::new(Mutex::new(Vec::<String>::new()));
+        let mut crossroads = Crossroads::new();
+        let registered_items = registered.clone();
+        let interface = crossroads.register(WATCHER_NAME, move |b: &mut IfaceBuilder<()>| {
+            let registered_items = registered_items.clone();
+            b.method(
+                "RegisterStatusNotifierItem",
+                ("service",),
+                (),
+                move |_, _, (service,): (String,)| {
+                    registered_items.lock().unwrap().push(service);
+                    Ok(())
+                },
+            );
+        });
+        crossroads.insert(WATCHER_PATH, &[interface], ());
+        watcher.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |message, connection| {
+                let _ = crossroads.handle_message(message, connection);
+                true
+            }),
+        );
+
+        let (sender, receiver) = mpsc::channel();
+        let status = control::new_status();
+        control::update(&status, |status| {
+            status.artist = String::from("Artist");
+            status.title = String::from("Title");
+            status.player = String::from("mpv");
+        });
+        let tray_connection = bus.connect();
+        let tray_status = status.clone();
+        thread::spawn(move || serve(tray_connection, sender, tray_status, None));
+
+        for _ in 0..50 {
+            watcher.process(Duration::from_millis(100)).unwrap();
+            if !registered.lock().unwrap().is_empty() {
+                break;
+            }
+        }
+        let name = format!("org.kde.StatusNotifierItem-{}-1", process::id());
+        assert_eq!(*registered.lock().unwrap(), vec![name.clone()]);
+
+        let client = bus.connect();
+        let item = client.with_proxy(name.as_str(), ITEM_PATH, Duration::from_secs(2));
+        let title: String = item.get(ITEM_INTERFACE, "Title").unwrap();
+        assert_eq!(title, "Music Discord RPC");
+        let icon: String = item.get(ITEM_INTERFACE, "IconName").unwrap();
+        assert_eq!(icon, "media-playback-start");
+        let menu_path: Path = item.get(ITEM_INTERFACE, "Menu").unwrap();
+        assert_eq!(&*menu_path, MENU_PATH);
+        let (_, _, _, description): ToolTip = item.get(ITEM_INTERFACE, "ToolTip").unwrap();
+        assert_eq!(description, "Artist - Title\nPlayer: mpv");
+
+        let menu = client.with_proxy(name.as_str(), MENU_PATH, Duration::from_secs(2));
+        let (_, (id, _, children)): (u32, Layout) = menu
+            .method_call(
+                MENU_INTERFACE,
+                "GetLayout",
+                (0i32, -1i32, Vec::<String>::new()),
+            )
+            .unwrap();
+        assert_eq!(id, 0);
+        let labels: Vec<String> = children.iter().map(label).collect();
+        assert_eq!(
+            labels,
+            [
+                "Artist - Title",
+                "",
+                "Pause presence",
+                "Preferred player",
+                "Open config file",
+                "",
+                "Quit",
+            ]
+        );
+
+        // Clicking the pause item pauses the presence and updates the icon
+        let event: MenuEvent = (
+            MENU_PAUSE,
+            String::from("clicked"),
+            Variant(Box::new(0i32)),
+            0,
+        );
+        let () = menu.method_call(MENU_INTERFACE, "Event", event).unwrap();
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_secs(2)),
+            Ok(Event::Pause)
+        ));
+        let icon: String = item.get(ITEM_INTERFACE, "IconName").unwrap();
+        assert_eq!(icon, "media-playback-pause");
+        let (_, (_, properties, _)): (u32, Layout) = menu
+            .method_call(
+                MENU_INTERFACE,
+                "GetLayout",
+                (MENU_PAUSE, 0i32, Vec::<String>::new()),
+            )
+            .unwrap();
+        assert_eq!(
+            properties.get("label").and_then(|label| label.0.as_str()),
+            Some("Resume presence")
+        );
+    }
+}
@@ -1,11 +1,16 @@
 use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
+use log::{error, info};
 use pickledb::PickleDb;
 use reqwest;
 use reqwest::blocking::Client;
 use reqwest::header::USER_AGENT;
+use serde::Deserialize;
 use serde_json;
 use std::env;
+use std::time::{Instant, SystemTime};
 
+#[cfg(target_os = "linux")]
+use log::{debug, trace};
 #[cfg(target_os = "linux")]
 use mpris::Player;
 #[cfg(target_os = "linux")]
@@ -13,9 +18,12 @@ use std::time::Duration;
 #[cfg(target_os = "linux")]
 use std::{fs, process};
 
+use crate::metrics;
+
 // A common struct to hold song information, ensuring a consistent
 // return type regardless of the platform.
-#[derive(Debug)]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct MediaInfo {
     pub title: String,
     pub artist: String,
@@ -25,27 +33,19 @@ pub struct MediaInfo {
     pub duration: u64,
     pub position: u64,
     pub is_track_position: bool,
-    pub art_url: String, // Link to cover art on the internet
-    pub url: String,     // Link to the currently playing media on the internet
+    pub art_url: String,              // Link to cover art on the internet
+    pub url: String,                  // Link to the currently playing media on the internet
+    pub musicbrainz_album_id: String, // MusicBrainz release ID, if the player knows it
+    pub path: String,                 // Local file of the track, if the player knows it
     #[cfg(target_os = "macos")]
     pub player_id: String,
 }
 
 // Use a Result to handle potential errors, like no media playing.
-type NowPlayingResult = Result<MediaInfo, Box<dyn std::error::Error>>;
+pub type NowPlayingResult = Result<MediaInfo, Box<dyn std::error::Error>>;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-// Use to print debug log if enabled with argument
-#[macro_export]
-macro_rules! debug_log {
-    ($cond:expr, $($arg:tt)*) => {
-        if $cond {
-            println!("\x1b[34;1m[debug]\x1b[0m {}", format!($($arg)*));
-        }
-    };
-}
-
 #[cfg(target_os = "linux")]
 fn is_systemd_present() {
     match process::Command::new("ps")
@@ -241,6 +241,12 @@ pub fn clear_activity(is_activity_set: &mut bool, client: &mut DiscordIpcClient)
     }
 }
 
+// Clear activity and close the connection with Discord
+pub fn close_client(client: &mut DiscordIpcClient) {
+    let _ = client.clear_activity();
+    let _ = client.close();
+}
+
 pub fn get_cover_url(
     album_id: &str,
     album: &str,
@@ -252,7 +258,7 @@ pub fn get_cover_url(
 ) -> String {
     // If no album or Unknown Album
     if album.eq("Unknown Album") {
-        println!("Missing album name or Unknown Album.");
+        info!("Missing album name or Unknown Album.");
 
         return String::from("missing-cover");
     }
@@ -269,8 +275,10 @@ pub fn get_cover_url(
         };
 
         if (!cache_url.is_empty()) && (cache_url.len() > 5) {
+            metrics::cache_lookup(true);
             return String::from(cache_url);
         }
+        metrics::cache_lookup(false);
     }
 
     let request_url = format!(
@@ -280,6 +288,7 @@ pub fn get_cover_url(
      	url_escape::encode_component(album)
     );
 
+    let request_start = Instant::now();
     let mut url: String = match reqwest::blocking::get(request_url) {
         Ok(res) => match res.json::<serde_json::Value>() {
             Ok(data) => data["album"]["image"][3]["#text"].to_string(),
@@ -287,20 +296,21 @@ pub fn get_cover_url(
         },
         Err(_) => String::new(),
     };
+    metrics::http_request("lastfm", request_start.elapsed());
 
     if !url.is_empty() && (url.len() > 5) {
         url.pop();
         url.remove(0);
-        println!("[last.fm] fetched image link: {}", url);
+        info!(provider = "lastfm"; "[last.fm] fetched image link: {}", url);
 
         // Save cover url to cache
         if cache_enabled {
             match album_cache.set(&album_id, &url) {
                 Ok(_) => {
-                    println!("[cache] saved image url for: {}.", album_id)
+                    info!("[cache] saved image url for: {}.", album_id)
                 }
                 Err(_) => {
-                    println!("[cache] error, unable to write to cache file.")
+                    error!("[cache] error, unable to write to cache file.")
                 }
             }
         }
@@ -318,10 +328,11 @@ pub fn get_cover_url_musicbrainz(
     cache_enabled: bool,
     album_cache: &mut PickleDb,
     artist: &str,
+    release_id: &str,
 ) -> String {
     // If no album or Unknown Album
     if album.eq("Unknown Album") {
-        println!("Missing album name or Unknown Album.");
+        info!("Missing album name or Unknown Album.");
 
         return String::from("missing-cover");
     }
@@ -338,8 +349,10 @@ pub fn get_cover_url_musicbrainz(
         };
 
         if (!cache_url.is_empty()) && (cache_url.len() > 5) {
+            metrics::cache_lookup(true);
             return String::from(cache_url);
         }
+        metrics::cache_lookup(false);
     }
 
     let user_agent = format!(
@@ -347,23 +360,29 @@ pub fn get_cover_url_musicbrainz(
         VERSION
     );
 
-    let request_url = format!(
-    	"https://musicbrainz.org/ws/2/release/?query=artist:\"{}\"ANDrelease:\"{}\"&fmt=json&limit=1",
-    	url_escape::encode_component(artist),
-     	url_escape::encode_component(album)
-    );
-
+    let request_start = Instant::now();
     let client = Client::new();
-    let mut mbid: String = match client
-        .get(request_url)
-        .header(USER_AGENT, &user_agent)
-        .send()
-    {
-        Ok(res) => match res.json::<serde_json::Value>() {
-            Ok(data) => data["releases"][0]["id"].to_string(),
+
+    // Some players (e.g. MPD) know the release ID from tags, no need to search for it
+    let mut mbid: String = if !release_id.is_empty() {
+        format!("\"{}\"", release_id)
+    } else {
+        let request_url = format!(
+            "https://musicbrainz.org/ws/2/release/?query=artist:\"{}\"ANDrelease:\"{}\"&fmt=json&limit=1",
+            url_escape::encode_component(artist),
+            url_escape::encode_component(album)
+        );
+        match client
+            .get(request_url)
+            .header(USER_AGENT, &user_agent)
+            .send()
+        {
+            Ok(res) => match res.json::<serde_json::Value>() {
+                Ok(data) => data["releases"][0]["id"].to_string(),
+                Err(_) => String::new(),
+            },
             Err(_) => String::new(),
-        },
-        Err(_) => String::new(),
+        }
     };
 
     if !mbid.is_empty() && (mbid.len() > 5) {
@@ -382,20 +401,21 @@ pub fn get_cover_url_musicbrainz(
         },
         Err(_) => String::new(),
     };
+    metrics::http_request("musicbrainz", request_start.elapsed());
 
     if !url.is_empty() && (url.len() > 5) {
         url.pop();
         url.remove(0);
-        println!("[musicbrainz] fetched image link: {}", url);
+        info!(provider = "musicbrainz"; "[musicbrainz] fetched image link: {}", url);
 
         // Save cover url to cache
         if cache_enabled {
             match album_cache.set(&album_id, &url) {
                 Ok(_) => {
-                    println!("[cache] saved image url for: {}.", album_id)
+                    info!("[cache] saved image url for: {}.", album_id)
                 }
                 Err(_) => {
-                    println!("[cache] error, unable to write to cache file.")
+                    error!("[cache] error, unable to write to cache file.")
                 }
             }
         }
@@ -424,13 +444,37 @@ pub fn get_lastfm_avatar(username: &str, lastfm_api_key: &str) -> String {
     if !url.is_empty() && (url.len() > 15) {
         url.pop();
         url.remove(0);
-        println!("[last.fm] fetched avatar link: {}", url);
+        info!("[last.fm] fetched avatar link: {}", url);
         return url;
     }
 
     return String::new();
 }
 
+// Seconds since the Unix epoch, 0 if the clock is broken
+pub fn unix_time() -> u64 {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+// Paths from the config can start with "~/"
+pub fn expand_home(path: &str) -> std::path::PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home_dir)) => std::path::PathBuf::from(home_dir).join(rest),
+        _ => std::path::PathBuf::from(path),
+    }
+}
+
+// File name without directories and extension, used as the title of untagged files
+pub fn file_stem(path: &str) -> String {
+    match std::path::Path::new(path).file_stem() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => path.to_string(),
+    }
+}
+
 pub fn sanitize_name(input: &str) -> String {
     input
         .to_lowercase()
@@ -444,12 +488,12 @@ pub fn sanitize_name(input: &str) -> String {
 }
 
 #[cfg(target_os = "linux")]
-pub fn get_currently_playing(player: &Player, debug_log: bool) -> NowPlayingResult {
+pub fn get_currently_playing(player: &Player) -> NowPlayingResult {
     let metadata = match player.get_metadata() {
         Ok(metadata) => metadata,
         Err(err) => return Err(format!("Could not get metadata from player: {}", err).into()),
     };
-    debug_log!(debug_log, "{:#?}", metadata);
+    trace!("{:?}", metadata);
 
     let playback_status = match player.get_playback_status() {
         Ok(status) => status,
@@ -463,7 +507,7 @@ pub fn get_currently_playing(player: &Player, debug_log: bool) -> NowPlayingResu
         mpris::PlaybackStatus::Paused => false,
         mpris::PlaybackStatus::Stopped => false,
     };
-    debug_log!(debug_log, "playback_status: {:#?}", playback_status);
+    debug!("playback_status: {:?}", playback_status);
 
     // Parse metadata
     let title = metadata.title().unwrap_or("Unknown Title").to_string();
@@ -536,6 +580,8 @@ pub fn get_currently_playing(player: &Player, debug_log: bool) -> NowPlayingResu
         is_track_position,
         art_url,
         url,
+        musicbrainz_album_id: String::new(),
+        path: String::new(),
     })
 }
 
@@ -590,6 +636,8 @@ pub fn get_currently_playing() -> NowPlayingResult {
                 is_track_position,
                 art_url,
                 url,
+                musicbrainz_album_id: String::new(),
+                path: String::new(),
                 player_id,
             })
         }
@@ -0,0 +1,241 @@
+use clap_serde_derive::clap::{ArgAction, Command};
+use clap_serde_derive::ClapSerde;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+
+use crate::settings::Cli;
+
+// Single problem found in the config file
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub is_error: bool,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = if self.is_error { "error" } else { "warning" };
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line, self.column, level, self.message
+        )
+    }
+}
+
+// Check config file text against the same rules as command line args.
+// Returns errors (config can't be used) and warnings (for example unknown keys).
+pub fn check_config(
+    yaml_str: &str,
+    command: &Command,
+    cli_only: &[&str],
+    config_only: &[&str],
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    // Syntax and types of values
+    if let Err(error) = serde_yaml::from_str::<<Cli as ClapSerde>::Opt>(yaml_str) {
+        let (line, column) = match error.location() {
+            Some(location) => (location.line(), location.column()),
+            None => (1, 1),
+        };
+        diagnostics.push(Diagnostic {
+            line,
+            column,
+            is_error: true,
+            message: error.to_string(),
+        });
+        return diagnostics;
+    }
+    let config = match serde_yaml::from_str::<Value>(yaml_str) {
+        Ok(Value::Object(config)) => config,
+        Ok(Value::Null) => return diagnostics,
+        _ => {
+            diagnostics.push(Diagnostic {
+                line: 1,
+                column: 1,
+                is_error: true,
+                message: String::from("expected a list of settings"),
+            });
+            return diagnostics;
+        }
+    };
+
+    let mut keys: Vec<&str> = command
+        .get_arguments()
+        .map(|arg| arg.get_id().as_str())
+        .filter(|id| !id.starts_with("no_"))
+        .collect();
+    keys.extend(config_only);
+
+    for (key, value) in &config {
+        let (line, column) = locate_key(yaml_str, key);
+
+        if config_only.contains(&key.as_str()) {
+            if !value.is_u64() {
+                diagnostics.push(Diagnostic {
+                    line,
+                    column,
+                    is_error: true,
+                    message: format!("invalid value for \"{}\": expected a number", key),
+                });
+            }
+            continue;
+        }
+
+        let arg = match command.get_arguments().find(|arg| arg.get_id() == key) {
+            Some(arg) if !key.starts_with("no_") => arg,
+            _ => {
+                let mut message = format!("unknown key \"{}\"", key);
+                if let Some(suggestion) = closest(key, &keys) {
+                    message += &format!(", did you mean \"{}\"?", suggestion);
+                }
+                diagnostics.push(Diagnostic {
+                    line,
+                    column,
+                    is_error: false,
+                    message,
+                });
+                continue;
+            }
+        };
+
+        if cli_only.contains(&key.as_str()) {
+            diagnostics.push(Diagnostic {
+                line,
+                column,
+                is_error: false,
+                message: format!(
+                    "\"{}\" is only available as a command line argument and will be ignored",
+                    key
+                ),
+            });
+            continue;
+        }
+
+        // Flags are already checked by serde
+        if matches!(arg.get_action(), ArgAction::SetTrue) {
+            continue;
+        }
+
+        let values: Vec<String> = match value {
+            Value::Array(values) => values.iter().filter_map(scalar_to_string).collect(),
+            value => scalar_to_string(value).into_iter().collect(),
+        };
+
+        let possible_values: Vec<String> = arg
+            .get_possible_values()
+            .iter()
+            .map(|possible_value| possible_value.get_name().to_string())
+            .collect();
+
+        for value in values {
+            let (line, column) = locate_value(yaml_str, line, &value);
+
+            if !possible_values.is_empty() {
+                if possible_values.contains(&value) {
+                    continue;
+                }
+
+                let names: Vec<&str> = possible_values.iter().map(|name| name.as_str()).collect();
+                let mut message = format!("invalid value \"{}\" for \"{}\"", value, key);
+                if let Some(suggestion) = closest(&value, &names) {
+                    message += &format!(", did you mean \"{}\"?", suggestion);
+                }
+                message += &format!(" [possible values: {}]", names.join(", "));
+                diagnostics.push(Diagnostic {
+                    line,
+                    column,
+                    is_error: true,
+                    message,
+                });
+                continue;
+            }
+
+            // Run the value through the same parser as the command line arg, e.g. to check ranges
+            let long = match arg.get_long() {
+                Some(long) => format!("--{}", long),
+                None => continue,
+            };
+            // Every item of the list is a single value, it is never split on commas
+            let command = command
+                .clone()
+                .mut_arg(arg.get_id(), |arg| arg.value_delimiter(None));
+            if let Err(error) = command.try_get_matches_from(["", &long, &value]) {
+                let reason = match error.source() {
+                    Some(source) => source.to_string(),
+                    None => error.kind().to_string(),
+                };
+                diagnostics.push(Diagnostic {
+                    line,
+                    column,
+                    is_error: true,
+                    message: format!("invalid value \"{}\" for \"{}\": {}", value, key, reason),
+                });
+            }
+        }
+    }
+
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+    diagnostics
+}
+
+fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+// Most similar valid name, used to suggest fixes for typos
+fn closest<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|candidate| {
+            let similarity = strsim::jaro_winkler(&name.to_lowercase(), &candidate.to_lowercase());
+            (similarity, *candidate)
+        })
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, candidate)| candidate)
+}
+
+// Position of top level key in the file (1-based line and column)
+fn locate_key(yaml_str: &str, key: &str) -> (usize, usize) {
+    for (index, line) in yaml_str.lines().enumerate() {
+        let trimmed = line.trim_start_matches(['"', '\'']);
+        if let Some(rest) = trimmed.strip_prefix(key) {
+            if rest
+                .trim_start_matches(['"', '\''])
+                .trim_start()
+                .starts_with(':')
+            {
+                return (index + 1, 1);
+            }
+        }
+    }
+    (1, 1)
+}
+
+// Position of the value belonging to the key at given line. List values can be
+// written on the same line or in the following indented lines.
+fn locate_value(yaml_str: &str, key_line: usize, value: &str) -> (usize, usize) {
+    for (index, line) in yaml_str.lines().enumerate().skip(key_line - 1) {
+        // Stop at the next top level key
+        if index + 1 > key_line && !line.is_empty() && !line.starts_with([' ', '\t', '-', '#']) {
+            break;
+        }
+        let content = match index + 1 == key_line {
+            true => line.split_once(':').map(|(_, rest)| rest).unwrap_or(""),
+            false => line,
+        };
+        if let Some(column) = content.find(value) {
+            return (index + 1, line.len() - content.len() + column + 1);
+        }
+    }
+    (key_line, 1)
+}
@@ -0,0 +1,326 @@
+use log::{debug, info, warn};
+use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_TYPE, USER_AGENT};
+use reqwest::Method;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::control::SharedStatus;
+use crate::now_playing::{self, NowPlaying};
+use crate::template;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Options of the webhook sink, see README
+pub struct Webhook {
+    pub url: String,
+    pub method: String,
+    // "Name: value"
+    pub headers: Vec<String>,
+    // JSON template, None means the whole state
+    pub body: Option<String>,
+    pub debounce: Duration,
+    pub retries: u32,
+}
+
+// Call the webhook when a track starts playing, is paused or stopped
+pub fn start(webhook: Webhook, status: SharedStatus) {
+    thread::spawn(move || {
+        let client = Client::new();
+        let mut last_sent = None;
+        let mut pending = None;
+        loop {
+            let now_playing = NowPlaying::new(&now_playing::current(&status));
+            let track = (
+                now_playing.status(),
+                now_playing.title.clone(),
+                now_playing.artist.clone(),
+                now_playing.album.clone(),
+                now_playing.player.clone(),
+            );
+
+            // Nothing was playing when the app started, there is nothing to report
+            if last_sent.is_none() && now_playing.status() == "stopped" {
+                last_sent = Some(track.clone());
+            }
+
+            // Wait until the track stays the same for the debounce window, so
+            // skipping through a playlist does not send every track
+            if last_sent.as_ref() == Some(&track) {
+                pending = None;
+            } else {
+                match &pending {
+                    Some((pending_track, since)) if *pending_track == track => {
+                        if Instant::now().duration_since(*since) >= webhook.debounce {
+                            send(&client, &webhook, &now_playing);
+                            last_sent = Some(track);
+                            pending = None;
+                        }
+                    }
+                    _ => pending = Some((track, Instant::now())),
+                }
+            }
+
+            thread::sleep(Duration::from_millis(500));
+        }
+    });
+}
+
+fn send(client: &Client, webhook: &Webhook, now_playing: &NowPlaying) {
+    let body = match &webhook.body {
+        Some(body) => template::render(body, now_playing, true),
+        None => default_body(now_playing),
+    };
+    let method = Method::from_bytes(webhook.method.as_bytes()).unwrap_or(Method::POST);
+
+    for attempt in 0..=webhook.retries {
+        if attempt > 0 {
+            // 1, 2, 4, ... seconds between attempts
+            thread::sleep(Duration::from_secs(1 << (attempt - 1).min(6)));
+        }
+
+        let mut request = client
+            .request(method.clone(), &webhook.url)
+            .timeout(Duration::from_secs(10))
+            .header(USER_AGENT, format!("music-discord-rpc/{}", VERSION))
+            .header(CONTENT_TYPE, "application/json");
+        for header in &webhook.headers {
+            if let Some((name, value)) = header.split_once(':') {
+                request = request.header(name.trim(), value.trim());
+            }
+        }
+        if method != Method::GET {
+            request = request.body(body.clone());
+        }
+
+        match request.send().and_then(|res| res.error_for_status()) {
+            Ok(res) => {
+                debug!(event = now_playing.status(); "[webhook] Sent, response: {}", res.status());
+                return;
+            }
+            Err(err) => warn!(
+                "[webhook] Request failed (attempt {} of {}): {}",
+                attempt + 1,
+                webhook.retries + 1,
+                err
+            ),
+        }
+    }
+    info!("[webhook] Giving up on the {} event", now_playing.status());
+}
+
+// Whole state with the event name
+fn default_body(now_playing: &NowPlaying) -> String {
+    let mut body = serde_json::to_value(now_playing).unwrap_or_default();
+    if let Some(object) = body.as_object_mut() {
+        object.insert(String::from("event"), now_playing.status().into());
+    }
+    body.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Status;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::sync::{Arc, Mutex};
+
+    struct Received {
+        request_line: String,
+        // Names in lowercase
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    impl Received {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.as_str())
+        }
+
+        fn json(&self) -> serde_json::Value {
+            serde_json::from_str(&self.body).unwrap()
+        }
+    }
+
+    // Webhook server that answers with the given status codes, then with 200
+    fn serve(mut codes: Vec<u16>) -> (String, Receiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    match line.split_once(':') {
+                        Some((name, value)) => {
+                            headers.push((name.to_lowercase(), value.trim().to_string()))
+                        }
+                        None => break,
+                    }
+                }
+                let length = headers
+                    .iter()
+                    .find(|(name, _)| name == "content-length")
+                    .map_or(0, |(_, value)| value.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let code = if codes.is_empty() {
+                    200
+                } else {
+                    codes.remove(0)
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    code
+                );
+                let _ = sender.send(Received {
+                    request_line: request_line.trim().to_string(),
+                    headers,
+                    body: String::from_utf8(body).unwrap(),
+                });
+            }
+        });
+        (format!("http://{}/hook", address), receiver)
+    }
+
+    fn options(url: String) -> Webhook {
+        Webhook {
+            url,
+            method: String::from("POST"),
+            headers: Vec::new(),
+            body: None,
+            debounce: Duration::ZERO,
+            retries: 0,
+        }
+    }
+
+    fn playing(title: &str) -> Status {
+        Status {
+            player: String::from("mpv"),
+            title: String::from(title),
+            artist: String::from("Radiohead"),
+            album: String::from("OK Computer"),
+            is_playing: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn requests() {
+        let (url, receiver) = serve(Vec::new());
+        let now_playing = NowPlaying::new(&playing("Airbag"));
+
+        let webhook = Webhook {
+            headers: vec![
+                String::from("Authorization: Bearer token"),
+                String::from("Accept: text/plain, application/json"),
+            ],
+            ..options(url.clone())
+        };
+        send(&Client::new(), &webhook, &now_playing);
+        let received = receiver.recv().unwrap();
+        assert_eq!(received.request_line, "POST /hook HTTP/1.1");
+        assert_eq!(received.header("content-type"), Some("application/json"));
+        assert_eq!(
+            received.header("user-agent"),
+            Some(format!("music-discord-rpc/{}", VERSION).as_str())
+        );
+        assert_eq!(received.header("authorization"), Some("Bearer token"));
+        assert_eq!(
+            received.header("accept"),
+            Some("text/plain, application/json")
+        );
+        let body = received.json();
+        assert_eq!(body["event"], "playing");
+        assert_eq!(body["title"], "Airbag");
+        assert_eq!(body["artist"], "Radiohead");
+        assert_eq!(body["player"], "mpv");
+
+        let webhook = Webhook {
+            method: String::from("PUT"),
+            body: Some(String::from(
+                r#"{"text": "{artist} - {title}", "event": "{status}"}"#,
+            )),
+            ..options(url.clone())
+        };
+        send(
+            &Client::new(),
+            &webhook,
+            &NowPlaying::new(&playing("\"Quoted\"")),
+        );
+        let received = receiver.recv().unwrap();
+        assert_eq!(received.request_line, "PUT /hook HTTP/1.1");
+        assert_eq!(
+            received.json(),
+            serde_json::json!({"text": "Radiohead - \"Quoted\"", "event": "playing"})
+        );
+
+        // GET requests have no body
+        let webhook = Webhook {
+            method: String::from("GET"),
+            ..options(url)
+        };
+        send(&Client::new(), &webhook, &now_playing);
+        let received = receiver.recv().unwrap();
+        assert_eq!(received.request_line, "GET /hook HTTP/1.1");
+        assert_eq!(received.body, "");
+    }
+
+    #[test]
+    fn failed_requests_are_retried() {
+        let (url, receiver) = serve(vec![500, 500]);
+        let now_playing = NowPlaying::new(&playing("Airbag"));
+
+        let webhook = Webhook {
+            retries: 2,
+            ..options(url.clone())
+        };
+        send(&Client::new(), &webhook, &now_playing);
+        assert_eq!(receiver.try_iter().count(), 3);
+
+        // Without retries the failed request is not sent again
+        let (url, receiver) = serve(vec![500]);
+        send(&Client::new(), &options(url), &now_playing);
+        assert_eq!(receiver.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn changes_are_debounced() {
+        let (url, receiver) = serve(Vec::new());
+        let status = Arc::new(Mutex::new(Status::default()));
+        let webhook = Webhook {
+            debounce: Duration::from_secs(1),
+            ..options(url)
+        };
+        start(webhook, status.clone());
+
+        // Nothing is sent for the stopped state at start
+        thread::sleep(Duration::from_millis(700));
+        assert!(receiver.try_recv().is_err());
+
+        // Only the track that stays for the debounce window is sent
+        *status.lock().unwrap() = playing("Airbag");
+        thread::sleep(Duration::from_millis(500));
+        *status.lock().unwrap() = playing("Paranoid Android");
+        let received = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(received.json()["title"], "Paranoid Android");
+        assert!(receiver.recv_timeout(Duration::from_secs(2)).is_err());
+
+        status.lock().unwrap().is_playing = false;
+        let received = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(received.json()["event"], "paused");
+        assert!(receiver.recv_timeout(Duration::from_secs(2)).is_err());
+    }
+}